@@ -0,0 +1,13 @@
+// turns on the timendus_suite cfg when rom/timendus_test_suite is checked out, so the tests against
+// it run on a plain `cargo test` there and are only ignored in a checkout without the submodule
+
+use std::path::Path;
+
+fn main() {
+    let suite = Path::new(env!("CARGO_MANIFEST_DIR")).join("../rom/timendus_test_suite/bin");
+    println!("cargo::rustc-check-cfg=cfg(timendus_suite)");
+    println!("cargo::rerun-if-changed={}", suite.display());
+    if suite.is_dir() {
+        println!("cargo::rustc-cfg=timendus_suite");
+    }
+}
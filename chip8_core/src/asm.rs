@@ -0,0 +1,850 @@
+// assembler -- compiles Octo source into ROM bytes that load_program takes as they are
+//
+// supported: every instruction (CHIP-8, SUPER-CHIP and XO-CHIP), labels (`: name`, used before or after they're
+// defined), :const, :alias, :macro, :org, :byte, :unpack, :breakpoint, `if ... then`, `if ... begin ... else ... end`,
+// `loop ... while ... again`, and bare numbers as data (so sprites are just their bytes, e.g. 0b01111110 or 0x7E)
+// not supported (yet): :calc, :stringmode, :assert, :next and the < > <= >= comparisons in if and while
+//
+// every instruction is recorded in a source map (address -> line), so the debugger can show the source being run
+// if : main isn't the first thing in the program, a jump to it goes at 0x200 like in Octo
+
+use crate::number;
+
+use alloc::collections::BTreeMap;
+use alloc::format;
+use alloc::string::{String, ToString};
+use alloc::vec::Vec;
+use core::fmt;
+
+const LOAD_ADDRESS: usize = 0x200; // Octo programs always start here
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Assembly {
+    pub rom: Vec<u8>,
+    pub source_map: BTreeMap<u16, usize>,  // the address of each instruction, and the line (from 1) it came from
+    pub labels: BTreeMap<String, u16>,
+    pub breakpoints: Vec<(String, u16)>,   // from :breakpoint name
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AssemblyError {
+    pub line: usize,
+    pub message: String,
+}
+
+impl fmt::Display for AssemblyError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "line {}: {}", self.line, self.message)
+    }
+}
+
+impl Assembly {
+    // the source map as text, one "address line" pair per line, e.g. "0x202 14"
+    pub fn source_map_text(&self) -> String {
+        self.source_map.iter().map(|(address, line)| format!("{:#05x} {}\n", address, line)).collect()
+    }
+
+    // the source line an address came from, if it's the start of an instruction
+    pub fn line_at(&self, address: u16) -> Option<usize> {
+        self.source_map.get(&address).copied()
+    }
+}
+
+pub fn assemble(source: &str) -> Result<Assembly, AssemblyError> {
+    // the program starts running at 0x200, so when : main isn't there it's assembled again with a jump to it first
+    // (like Octo does), programs that start with : main don't pay for the extra instruction
+    let assembly = assemble_from(source, false)?;
+    if assembly.labels.get("main") == Some(&(LOAD_ADDRESS as u16)) {
+        return Ok(assembly);
+    }
+    assemble_from(source, true)
+}
+
+fn assemble_from(source: &str, jump_to_main: bool) -> Result<Assembly, AssemblyError> {
+    let mut assembler = Assembler {
+        tokens: tokenize(source),
+        position: 0,
+        rom: Vec::new(),
+        address: LOAD_ADDRESS,
+        start: LOAD_ADDRESS,
+        labels: BTreeMap::new(),
+        constants: BTreeMap::new(),
+        aliases: BTreeMap::new(),
+        macros: BTreeMap::new(),
+        fixups: Vec::new(),
+        blocks: Vec::new(),
+        source_map: BTreeMap::new(),
+        breakpoints: Vec::new(),
+        main_line: 1,
+    };
+    if jump_to_main {
+        let offset = assembler.jump_placeholder(1);
+        assembler.refer(offset, Patch::Nnn, String::from("main"), 1);
+        assembler.start = assembler.address;
+    }
+    while assembler.position < assembler.tokens.len() {
+        assembler.statement()?;
+    }
+    assembler.finish()
+}
+
+#[derive(Debug, Clone)]
+struct Token {
+    text: String,
+    line: usize,
+    depth: usize, // how many macros deep it came from, 0 for the source itself
+}
+
+fn tokenize(source: &str) -> Vec<Token> {
+    let mut tokens = Vec::new();
+    for (i, line) in source.lines().enumerate() {
+        // a word starting with # starts a comment that runs to the end of the line, as in Octo
+        // (so there are no #ff style numbers, hex is 0xff)
+        for word in line.split_whitespace() {
+            if word.starts_with('#') {break}
+            tokens.push(Token { text: word.to_string(), line: i + 1, depth: 0 });
+        }
+    }
+    tokens
+}
+
+struct Macro {
+    parameters: Vec<String>,
+    body: Vec<Token>,
+}
+
+// a reference to a label that might not be defined yet, patched in at the end
+struct Fixup {
+    offset: usize, // into the ROM
+    patch: Patch,
+    label: String,
+    line: usize,
+}
+
+#[derive(Clone, Copy)]
+enum Patch {
+    Nnn,            // the low 12 bits of the instruction
+    Long,           // the 16 bit address after F000
+    UnpackHigh(u8), // 6X(N << 4 | address >> 8)
+    UnpackLong,     // 6X(address >> 8)
+    UnpackLow,      // 6X(address & 0xFF)
+}
+
+// an if ... begin or a loop that hasn't been closed yet
+enum Block {
+    If { jump: usize, line: usize },         // the jump over the body, waiting for else / end
+    Else { jump: usize, line: usize },       // the jump over the else part, waiting for end
+    Loop { start: u16, exits: Vec<usize>, line: usize }, // the while jumps, waiting for again
+}
+
+// a condition for if / while, as the instruction that skips the next one when it's false
+#[derive(Clone, Copy)]
+struct Condition {
+    opcode: u16,
+}
+
+impl Condition {
+    // the instruction that skips the next one when it's true instead
+    fn negated(self) -> Condition {
+        let opcode = self.opcode;
+        let flipped = match opcode >> 12 {
+            0x3 => 0x4000 | (opcode & 0x0FFF),
+            0x4 => 0x3000 | (opcode & 0x0FFF),
+            0x5 => 0x9000 | (opcode & 0x0FFF),
+            0x9 => 0x5000 | (opcode & 0x0FFF),
+            // EX9E <-> EXA1
+            _ => (opcode & 0xFF00) | if opcode & 0xFF == 0x9E {0xA1} else {0x9E},
+        };
+        Condition { opcode: flipped }
+    }
+}
+
+const MAX_MACRO_DEPTH: usize = 64; // macros used inside macros, stops one that uses itself from going on forever
+
+struct Assembler {
+    tokens: Vec<Token>,
+    position: usize,
+    rom: Vec<u8>,
+    address: usize, // where the next byte goes
+    start: usize,   // where the program's own bytes start, after the jump to main if there is one
+    labels: BTreeMap<String, u16>,
+    constants: BTreeMap<String, i32>,
+    aliases: BTreeMap<String, u8>,
+    macros: BTreeMap<String, Macro>,
+    fixups: Vec<Fixup>,
+    blocks: Vec<Block>,
+    source_map: BTreeMap<u16, usize>,
+    breakpoints: Vec<(String, u16)>,
+    main_line: usize, // where : main is, for the jump to it in the source map
+}
+
+impl Assembler {
+    fn statement(&mut self) -> Result<(), AssemblyError> {
+        let token = self.next("a statement")?;
+        let line = token.line;
+        let word = token.text.as_str();
+
+        match word {
+            ":" => {
+                let name = self.name()?;
+                if name == "main" {
+                    self.main_line = line;
+                }
+                if self.labels.insert(name.clone(), self.address as u16).is_some() {
+                    return Err(self.error(line, format!("the label {} is defined twice", name)));
+                }
+            },
+            ":const" => {
+                let name = self.name()?;
+                let value = self.value()?;
+                self.constants.insert(name, value);
+            },
+            ":alias" => {
+                let name = self.name()?;
+                let register = self.register()?;
+                self.aliases.insert(name, register);
+            },
+            ":macro" => self.define_macro(line)?,
+            ":org" => {
+                let address = self.value()?;
+                if !(0..=0xFFFF).contains(&address) || (address as usize) < self.start {
+                    return Err(self.error(line, format!(":org {:#x} is outside the program", address)));
+                }
+                self.address = address as usize;
+            },
+            ":byte" => {
+                let value = self.byte()?;
+                self.emit_byte(value);
+            },
+            ":unpack" => self.unpack(line)?,
+            ":breakpoint" => {
+                let name = self.name()?;
+                self.breakpoints.push((name, self.address as u16));
+            },
+            "clear" => self.instruction(line, 0x00E0),
+            "return" | ";" => self.instruction(line, 0x00EE),
+            "exit" => self.instruction(line, 0x00FD),
+            "lores" => self.instruction(line, 0x00FE),
+            "hires" => self.instruction(line, 0x00FF),
+            "scroll-right" => self.instruction(line, 0x00FB),
+            "scroll-left" => self.instruction(line, 0x00FC),
+            "audio" => self.instruction(line, 0xF002),
+            "scroll-down" => {
+                let n = self.nibble()?;
+                self.instruction(line, 0x00C0 | n);
+            },
+            "scroll-up" => {
+                let n = self.nibble()?;
+                self.instruction(line, 0x00D0 | n);
+            },
+            "plane" => {
+                let n = self.nibble()?;
+                self.instruction(line, 0xF001 | (n << 8));
+            },
+            "bcd" => self.register_instruction(line, 0xF033)?,
+            "saveflags" => self.register_instruction(line, 0xF075)?,
+            "loadflags" => self.register_instruction(line, 0xF085)?,
+            "save" | "load" => {
+                let x = self.register()? as u16;
+                if self.peek_is("-") {
+                    // save vx - vy / load vx - vy (XO-CHIP)
+                    self.position += 1;
+                    let y = self.register()? as u16;
+                    let n = if word == "save" {0x2} else {0x3};
+                    self.instruction(line, 0x5000 | (x << 8) | (y << 4) | n);
+                } else {
+                    let nn = if word == "save" {0x55} else {0x65};
+                    self.instruction(line, 0xF000 | (x << 8) | nn);
+                }
+            },
+            "sprite" => {
+                let x = self.register()? as u16;
+                let y = self.register()? as u16;
+                let n = self.nibble()?;
+                self.instruction(line, 0xD000 | (x << 8) | (y << 4) | n);
+            },
+            "jump" => self.address_instruction(line, 0x1000)?,
+            "jump0" => self.address_instruction(line, 0xB000)?,
+            "native" => self.address_instruction(line, 0x0000)?,
+            ":call" => self.address_instruction(line, 0x2000)?,
+            "delay" | "buzzer" | "pitch" => {
+                self.expect(":=")?;
+                let opcode = match word {
+                    "delay" => 0xF015,
+                    "buzzer" => 0xF018,
+                    _ => 0xF03A,
+                };
+                self.register_instruction(line, opcode)?;
+            },
+            "i" => self.index_statement(line)?,
+            "if" => {
+                let condition = self.condition()?;
+                let form = self.next("then or begin")?;
+                match form.text.as_str() {
+                    "then" => self.instruction(line, condition.opcode),
+                    "begin" => {
+                        // skip the jump over the body when the condition holds
+                        self.instruction(line, condition.negated().opcode);
+                        let jump = self.jump_placeholder(line);
+                        self.blocks.push(Block::If { jump, line });
+                    },
+                    _ => return Err(self.error(form.line, format!("expected then or begin, found {}", form.text))),
+                }
+            },
+            "else" => match self.blocks.pop() {
+                Some(Block::If { jump, .. }) => {
+                    let skip_else = self.jump_placeholder(line);
+                    self.patch_jump(jump, self.address);
+                    self.blocks.push(Block::Else { jump: skip_else, line });
+                },
+                _ => return Err(self.error(line, String::from("else without an if ... begin"))),
+            },
+            "end" => match self.blocks.pop() {
+                Some(Block::If { jump, .. } | Block::Else { jump, .. }) => self.patch_jump(jump, self.address),
+                _ => return Err(self.error(line, String::from("end without an if ... begin"))),
+            },
+            "loop" => self.blocks.push(Block::Loop { start: self.address as u16, exits: Vec::new(), line }),
+            "while" => {
+                let condition = self.condition()?;
+                // skip the jump out of the loop when the condition holds
+                self.instruction(line, condition.negated().opcode);
+                let jump = self.jump_placeholder(line);
+                match self.blocks.iter_mut().rev().find(|block| matches!(block, Block::Loop { .. })) {
+                    Some(Block::Loop { exits, .. }) => exits.push(jump),
+                    _ => return Err(self.error(line, String::from("while outside a loop"))),
+                }
+            },
+            "again" => match self.blocks.pop() {
+                Some(Block::Loop { start, exits, .. }) => {
+                    self.instruction(line, 0x1000 | start);
+                    for exit in exits {
+                        self.patch_jump(exit, self.address);
+                    }
+                },
+                _ => return Err(self.error(line, String::from("again without a loop"))),
+            },
+            _ if self.is_register(word) => self.register_statement(line, word)?,
+            _ if self.macros.contains_key(word) => self.expand_macro(word.to_string(), line, token.depth)?,
+            _ if parse_number(word).is_some() || self.constants.contains_key(word) => {
+                // a bare number is a byte of data, e.g. a row of a sprite
+                let value = self.resolve_value(&token)?;
+                let byte = self.check_byte(line, value)?;
+                self.emit_byte(byte);
+            },
+            _ if is_name(word) => {
+                // anything else is a call to a subroutine, which might not be defined yet
+                let offset = self.offset();
+                self.instruction(line, 0x2000);
+                self.refer(offset, Patch::Nnn, word.to_string(), line);
+            },
+            _ => return Err(self.error(line, format!("don't know what {} means", word))),
+        }
+        Ok(())
+    }
+
+    fn finish(mut self) -> Result<Assembly, AssemblyError> {
+        if let Some(block) = self.blocks.last() {
+            let (line, what) = match block {
+                Block::If { line, .. } | Block::Else { line, .. } => (*line, "if ... begin without an end"),
+                Block::Loop { line, .. } => (*line, "loop without an again"),
+            };
+            return Err(self.error(line, String::from(what)));
+        }
+        if !self.labels.contains_key("main") {
+            return Err(self.error(1, String::from("there's no : main label")));
+        }
+        if self.start != LOAD_ADDRESS {
+            self.source_map.insert(LOAD_ADDRESS as u16, self.main_line);
+        }
+
+        for fixup in core::mem::take(&mut self.fixups) {
+            let Some(&address) = self.labels.get(&fixup.label) else {
+                return Err(self.error(fixup.line, format!("{} isn't defined", fixup.label)));
+            };
+            let at = fixup.offset;
+            match fixup.patch {
+                Patch::Nnn => {
+                    if address > 0xFFF {
+                        return Err(self.error(fixup.line, format!("{} is past 0xFFF, use i := long", fixup.label)));
+                    }
+                    self.rom[at] = (self.rom[at] & 0xF0) | (address >> 8) as u8;
+                    self.rom[at + 1] = address as u8;
+                },
+                Patch::Long => {
+                    self.rom[at] = (address >> 8) as u8;
+                    self.rom[at + 1] = address as u8;
+                },
+                Patch::UnpackHigh(nibble) => self.rom[at + 1] = (nibble << 4) | (address >> 8) as u8 & 0xF,
+                Patch::UnpackLong => self.rom[at + 1] = (address >> 8) as u8,
+                Patch::UnpackLow => self.rom[at + 1] = address as u8,
+            }
+        }
+
+        Ok(Assembly { rom: self.rom, source_map: self.source_map, labels: self.labels, breakpoints: self.breakpoints })
+    }
+
+    // vx := ..., vx += ... and the rest of the register operations
+    fn register_statement(&mut self, line: usize, register: &str) -> Result<(), AssemblyError> {
+        let x = self.register_named(line, register)? as u16 * 0x100;
+        let operator = self.next("an operator like := or +=")?;
+        let operand = self.next("a value or a register")?;
+        let register_operand = if self.is_register(&operand.text) {Some(self.register_named(operand.line, &operand.text)? as u16 * 0x10)} else {None};
+
+        let opcode = match (operator.text.as_str(), register_operand) {
+            (":=", Some(y)) => 0x8000 | x | y,
+            (":=", None) => match operand.text.as_str() {
+                "random" => 0xC000 | x | self.byte()? as u16,
+                "key" => 0xF00A | x,
+                "delay" => 0xF007 | x,
+                _ => 0x6000 | x | self.check_byte(operand.line, self.resolve_value(&operand)?)? as u16,
+            },
+            ("+=", Some(y)) => 0x8004 | x | y,
+            ("+=", None) => 0x7000 | x | self.check_byte(operand.line, self.resolve_value(&operand)?)? as u16,
+            ("-=", Some(y)) => 0x8005 | x | y,
+            ("-=", None) => {
+                // there's no subtract immediate, so add the negative
+                let value = self.check_byte(operand.line, self.resolve_value(&operand)?)?;
+                0x7000 | x | value.wrapping_neg() as u16
+            },
+            ("|=", Some(y)) => 0x8001 | x | y,
+            ("&=", Some(y)) => 0x8002 | x | y,
+            ("^=", Some(y)) => 0x8003 | x | y,
+            (">>=", Some(y)) => 0x8006 | x | y,
+            ("=-", Some(y)) => 0x8007 | x | y,
+            ("<<=", Some(y)) => 0x800E | x | y,
+            ("|=" | "&=" | "^=" | ">>=" | "=-" | "<<=", None) => {
+                return Err(self.error(operand.line, format!("{} needs a register on the right", operator.text)));
+            },
+            _ => return Err(self.error(operator.line, format!("don't know the operator {}", operator.text))),
+        };
+        self.instruction(line, opcode);
+        Ok(())
+    }
+
+    // i := address, i := hex vx, i := bighex vx, i := long address, i += vx
+    fn index_statement(&mut self, line: usize) -> Result<(), AssemblyError> {
+        let operator = self.next(":= or +=")?;
+        match operator.text.as_str() {
+            "+=" => self.register_instruction(line, 0xF01E),
+            ":=" => {
+                if self.peek_is("hex") || self.peek_is("bighex") {
+                    let opcode = if self.next("hex")?.text == "hex" {0xF029} else {0xF030};
+                    return self.register_instruction(line, opcode);
+                }
+                if self.peek_is("long") {
+                    self.position += 1;
+                    self.instruction(line, 0xF000);
+                    let offset = self.offset();
+                    self.emit_byte(0);
+                    self.emit_byte(0);
+                    return self.address_operand(offset, Patch::Long, 0xFFFF);
+                }
+                self.address_instruction(line, 0xA000)
+            },
+            _ => Err(self.error(operator.line, format!("expected := or += after i, found {}", operator.text))),
+        }
+    }
+
+    // the instruction a condition compiles to, skipping the next one when it's false
+    fn condition(&mut self) -> Result<Condition, AssemblyError> {
+        let left = self.register()? as u16;
+        let operator = self.next("a comparison")?;
+        let opcode = match operator.text.as_str() {
+            "key" => 0xE0A1 | (left << 8),
+            "-key" => 0xE09E | (left << 8),
+            "==" | "!=" => {
+                let right = self.next("a value or a register")?;
+                let equal = operator.text == "==";
+                if self.is_register(&right.text) {
+                    let y = self.register_named(right.line, &right.text)? as u16;
+                    (if equal {0x9000} else {0x5000}) | (left << 8) | (y << 4)
+                } else {
+                    let value = self.check_byte(right.line, self.resolve_value(&right)?)? as u16;
+                    (if equal {0x4000} else {0x3000}) | (left << 8) | value
+                }
+            },
+            "<" | ">" | "<=" | ">=" => return Err(self.error(operator.line, format!("{} isn't supported yet, only ==, !=, key and -key", operator.text))),
+            _ => return Err(self.error(operator.line, format!("expected a comparison, found {}", operator.text))),
+        };
+        Ok(Condition { opcode })
+    }
+
+    fn define_macro(&mut self, line: usize) -> Result<(), AssemblyError> {
+        let name = self.name()?;
+        let mut parameters = Vec::new();
+        loop {
+            let token = self.next("{ to start the macro")?;
+            if token.text == "{" {break}
+            parameters.push(token.text);
+        }
+        let mut depth = 1;
+        let mut body = Vec::new();
+        loop {
+            let token = self.next("} to end the macro").map_err(|_| self.error(line, format!("the macro {} has no closing }}", name)))?;
+            match token.text.as_str() {
+                "{" => depth += 1,
+                "}" => {
+                    depth -= 1;
+                    if depth == 0 {break}
+                },
+                _ => {},
+            }
+            body.push(token);
+        }
+        self.macros.insert(name, Macro { parameters, body });
+        Ok(())
+    }
+
+    // swaps the macro's name and arguments for its body, with the parameters replaced
+    // `depth` is how deep in macros the use of this one is
+    fn expand_macro(&mut self, name: String, line: usize, depth: usize) -> Result<(), AssemblyError> {
+        if depth >= MAX_MACRO_DEPTH {
+            return Err(self.error(line, format!("the macro {} keeps expanding (does it use itself?)", name)));
+        }
+        let count = self.macros[&name].parameters.len();
+        let mut arguments = Vec::with_capacity(count);
+        for _ in 0..count {
+            arguments.push(self.next("a macro argument")?.text);
+        }
+        let definition = &self.macros[&name];
+        let expanded: Vec<Token> = definition.body.iter().map(|token| {
+            let text = match definition.parameters.iter().position(|parameter| *parameter == token.text) {
+                Some(i) => arguments[i].clone(),
+                None => token.text.clone(),
+            };
+            // errors inside a macro point at where it was used
+            Token { text, line, depth: depth + 1 }
+        }).collect();
+        self.tokens.splice(self.position..self.position, expanded);
+        Ok(())
+    }
+
+    // :unpack N label puts N << 12 | label in v0 (high byte) and v1 (low byte), :unpack long label the whole 16 bits
+    fn unpack(&mut self, line: usize) -> Result<(), AssemblyError> {
+        let high = if self.peek_is("long") {
+            self.position += 1;
+            Patch::UnpackLong
+        } else {
+            Patch::UnpackHigh(self.nibble()? as u8)
+        };
+        let label = self.name()?;
+        let offset = self.offset();
+        self.instruction(line, 0x6000);
+        self.instruction(line, 0x6100);
+        self.refer(offset, high, label.clone(), line);
+        self.refer(offset + 2, Patch::UnpackLow, label, line);
+        Ok(())
+    }
+
+    // helpers for the operands
+
+    fn next(&mut self, expected: &str) -> Result<Token, AssemblyError> {
+        match self.tokens.get(self.position) {
+            Some(token) => {
+                self.position += 1;
+                Ok(token.clone())
+            },
+            None => {
+                let line = self.tokens.last().map_or(1, |token| token.line);
+                Err(self.error(line, format!("expected {} but the program ended", expected)))
+            },
+        }
+    }
+
+    fn peek_is(&self, text: &str) -> bool {
+        self.tokens.get(self.position).is_some_and(|token| token.text == text)
+    }
+
+    fn expect(&mut self, text: &str) -> Result<(), AssemblyError> {
+        let token = self.next(text)?;
+        if token.text != text {
+            return Err(self.error(token.line, format!("expected {}, found {}", text, token.text)));
+        }
+        Ok(())
+    }
+
+    fn name(&mut self) -> Result<String, AssemblyError> {
+        let token = self.next("a name")?;
+        if !is_name(&token.text) {
+            return Err(self.error(token.line, format!("{} can't be used as a name", token.text)));
+        }
+        Ok(token.text)
+    }
+
+    fn is_register(&self, text: &str) -> bool {
+        register_number(text).is_some() || self.aliases.contains_key(text)
+    }
+
+    fn register(&mut self) -> Result<u8, AssemblyError> {
+        let token = self.next("a register")?;
+        self.register_named(token.line, &token.text)
+    }
+
+    fn register_named(&self, line: usize, text: &str) -> Result<u8, AssemblyError> {
+        register_number(text)
+            .or_else(|| self.aliases.get(text).copied())
+            .ok_or_else(|| self.error(line, format!("expected a register (v0 to vf), found {}", text)))
+    }
+
+    fn value(&mut self) -> Result<i32, AssemblyError> {
+        let token = self.next("a value")?;
+        self.resolve_value(&token)
+    }
+
+    // a number, a constant or a label that's already been defined
+    fn resolve_value(&self, token: &Token) -> Result<i32, AssemblyError> {
+        parse_number(&token.text)
+            .or_else(|| self.constants.get(&token.text).copied())
+            .or_else(|| self.labels.get(&token.text).map(|&address| address as i32))
+            .ok_or_else(|| self.error(token.line, format!("expected a number, found {}", token.text)))
+    }
+
+    fn byte(&mut self) -> Result<u8, AssemblyError> {
+        let token = self.next("a byte")?;
+        let value = self.resolve_value(&token)?;
+        self.check_byte(token.line, value)
+    }
+
+    // -128 to 255, negative numbers are stored as two's complement
+    fn check_byte(&self, line: usize, value: i32) -> Result<u8, AssemblyError> {
+        if !(-128..=255).contains(&value) {
+            return Err(self.error(line, format!("{} doesn't fit in a byte", value)));
+        }
+        Ok(value as u8)
+    }
+
+    fn nibble(&mut self) -> Result<u16, AssemblyError> {
+        let token = self.next("a number from 0 to 15")?;
+        let value = self.resolve_value(&token)?;
+        if !(0..=15).contains(&value) {
+            return Err(self.error(token.line, format!("{} doesn't fit in a nibble (0 to 15)", value)));
+        }
+        Ok(value as u16)
+    }
+
+    // emitting
+
+    fn offset(&self) -> usize {
+        self.address - LOAD_ADDRESS
+    }
+
+    fn emit_byte(&mut self, byte: u8) {
+        let offset = self.offset();
+        if self.rom.len() <= offset {
+            self.rom.resize(offset + 1, 0);
+        }
+        self.rom[offset] = byte;
+        self.address += 1;
+    }
+
+    fn instruction(&mut self, line: usize, opcode: u16) {
+        self.source_map.insert(self.address as u16, line);
+        self.emit_byte((opcode >> 8) as u8);
+        self.emit_byte(opcode as u8);
+    }
+
+    fn register_instruction(&mut self, line: usize, opcode: u16) -> Result<(), AssemblyError> {
+        let x = self.register()? as u16;
+        self.instruction(line, opcode | (x << 8));
+        Ok(())
+    }
+
+    // an instruction with a 12 bit address, which can be a label defined further on
+    fn address_instruction(&mut self, line: usize, opcode: u16) -> Result<(), AssemblyError> {
+        let offset = self.offset();
+        self.instruction(line, opcode);
+        self.address_operand(offset, Patch::Nnn, 0xFFF)
+    }
+
+    fn address_operand(&mut self, offset: usize, patch: Patch, max: i32) -> Result<(), AssemblyError> {
+        let token = self.next("an address or a label")?;
+        if parse_number(&token.text).is_none() && !self.constants.contains_key(&token.text) {
+            // labels are always patched in at the end, so they can be used before they're defined
+            self.refer(offset, patch, token.text, token.line);
+            return Ok(());
+        }
+        let value = self.resolve_value(&token)?;
+        if !(0..=max).contains(&value) {
+            return Err(self.error(token.line, format!("{:#x} is too big for this instruction", value)));
+        }
+        match patch {
+            Patch::Long => {
+                self.rom[offset] = (value >> 8) as u8;
+                self.rom[offset + 1] = value as u8;
+            },
+            _ => {
+                self.rom[offset] |= (value >> 8) as u8;
+                self.rom[offset + 1] = value as u8;
+            },
+        }
+        Ok(())
+    }
+
+    fn refer(&mut self, offset: usize, patch: Patch, label: String, line: usize) {
+        self.fixups.push(Fixup { offset, patch, label, line });
+    }
+
+    // a jump whose address is filled in by patch_jump once we know it
+    fn jump_placeholder(&mut self, line: usize) -> usize {
+        let offset = self.offset();
+        self.instruction(line, 0x1000);
+        offset
+    }
+
+    fn patch_jump(&mut self, offset: usize, address: usize) {
+        self.rom[offset] = 0x10 | ((address >> 8) & 0xF) as u8;
+        self.rom[offset + 1] = address as u8;
+    }
+
+    fn error(&self, line: usize, message: String) -> AssemblyError {
+        AssemblyError { line, message }
+    }
+}
+
+fn register_number(text: &str) -> Option<u8> {
+    let digit = text.strip_prefix('v').or_else(|| text.strip_prefix('V'))?;
+    if digit.len() != 1 {
+        return None;
+    }
+    u8::from_str_radix(digit, 16).ok()
+}
+
+// decimal, 0x hex or 0b binary, optionally negative
+fn parse_number(text: &str) -> Option<i32> {
+    let (negative, digits) = match text.strip_prefix('-') {
+        Some(rest) => (true, rest),
+        None => (false, text),
+    };
+    let value = i32::try_from(number::parse_number(digits)?).ok()?;
+    Some(if negative {-value} else {value})
+}
+
+fn is_name(text: &str) -> bool {
+    let mut chars = text.chars();
+    chars.next().is_some_and(|c| c.is_ascii_alphabetic() || c == '_')
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-')
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rom(source: &str) -> Vec<u8> {
+        assemble(source).unwrap_or_else(|err| panic!("{}", err)).rom
+    }
+
+    fn error(source: &str) -> AssemblyError {
+        assemble(source).unwrap_err()
+    }
+
+    #[test]
+    fn instructions() {
+        assert_eq!(rom(": main clear v0 := 0x12 v1 += v2 sprite v1 v2 5 i := hex v3 bcd v4 ;"),
+            [0x00, 0xE0, 0x60, 0x12, 0x81, 0x24, 0xD1, 0x25, 0xF3, 0x29, 0xF4, 0x33, 0x00, 0xEE]);
+        // there's no subtract immediate
+        assert_eq!(rom(": main v3 -= 1"), [0x73, 0xFF]);
+    }
+
+    #[test]
+    fn labels_before_and_after() {
+        assert_eq!(rom(": main loop-here jump later : later jump main : loop-here ;"),
+            [0x22, 0x06, 0x12, 0x04, 0x12, 0x00, 0x00, 0xEE]);
+        assert_eq!(error(": main jump nowhere"), AssemblyError { line: 1, message: String::from("nowhere isn't defined") });
+        assert_eq!(error(": main\n: main").line, 2);
+    }
+
+    #[test]
+    fn constants_and_aliases() {
+        let source = ":const speed 3\n:alias player v5\n: main player := speed player += speed if player == speed then ;";
+        assert_eq!(rom(source), [0x65, 0x03, 0x75, 0x03, 0x45, 0x03, 0x00, 0xEE]);
+    }
+
+    #[test]
+    fn macros() {
+        let source = ":macro move who by { who += by }\n: main move v1 2 move v2 v3";
+        assert_eq!(rom(source), [0x71, 0x02, 0x82, 0x34]);
+        // used inside each other, and many more times than they're nested
+        let source = ":macro one { v0 += 1 }\n:macro two { one one }\n: main two two";
+        assert_eq!(rom(source), [0x70, 0x01, 0x70, 0x01, 0x70, 0x01, 0x70, 0x01]);
+        let many = alloc::format!(":macro one {{ v0 += 1 }}\n: main {}", "one ".repeat(20_000));
+        assert_eq!(rom(&many).len(), 40_000);
+    }
+
+    #[test]
+    fn a_macro_that_uses_itself_stops() {
+        let err = error(":macro forever { forever v0 += 1 }\n: main\nforever");
+        assert_eq!(err, AssemblyError { line: 3, message: String::from("the macro forever keeps expanding (does it use itself?)") });
+    }
+
+    #[test]
+    fn if_then_and_begin() {
+        assert_eq!(rom(": main if v0 == 1 then v1 := 2"), [0x40, 0x01, 0x61, 0x02]);
+        assert_eq!(rom(": main if v0 != v1 then v1 := 2"), [0x50, 0x10, 0x61, 0x02]);
+        assert_eq!(rom(": main if v2 key then v1 := 2"), [0xE2, 0xA1, 0x61, 0x02]);
+        // the skip is flipped so the jump over the body is skipped when the condition holds
+        assert_eq!(rom(": main if v0 == 1 begin v1 := 2 else v1 := 3 end"),
+            [0x30, 0x01, 0x12, 0x08, 0x61, 0x02, 0x12, 0x0A, 0x61, 0x03]);
+        assert_eq!(error(": main if v0 == 1 begin").message, "if ... begin without an end");
+        assert_eq!(error(": main if v0 < 1 then ;").message, "< isn't supported yet, only ==, !=, key and -key");
+    }
+
+    #[test]
+    fn loop_while_again() {
+        assert_eq!(rom(": main loop v0 += 1 while v0 != 10 again"), [0x70, 0x01, 0x40, 0x0A, 0x12, 0x08, 0x12, 0x00]);
+        assert_eq!(error(": main\nwhile v0 == 1").line, 2);
+        assert_eq!(error(": main again").message, "again without a loop");
+        assert_eq!(error(": main\nloop\n;").line, 2);
+    }
+
+    #[test]
+    fn sprite_literals() {
+        assert_eq!(rom(": main ; : box 0b11110000 0x90 144 -1 :byte 7"), [0x00, 0xEE, 0xF0, 0x90, 0x90, 0xFF, 0x07]);
+        assert_eq!(error(": main\n\n300"), AssemblyError { line: 3, message: String::from("300 doesn't fit in a byte") });
+    }
+
+    #[test]
+    fn error_lines() {
+        let source = ": main\n  v0 := 1\n  v1 := 2\n  v2 += bogus\n";
+        assert_eq!(error(source), AssemblyError { line: 4, message: String::from("expected a number, found bogus") });
+        assert_eq!(error(source).to_string(), "line 4: expected a number, found bogus");
+        assert_eq!(error(": main\nv0 :=").message, "expected a value or a register but the program ended");
+        assert_eq!(error("v0 := 1").message, "there's no : main label");
+    }
+
+    #[test]
+    fn comments() {
+        assert_eq!(rom("# a whole line\n: main v0 := 1 # the rest of this one\nv1 := 2 #no space"), [0x60, 0x01, 0x61, 0x02]);
+        // Octo has no #ff numbers, so that's a comment and the := has nothing after it
+        assert_eq!(error(": main v0 := #ff").message, "expected a value or a register but the program ended");
+    }
+
+    #[test]
+    fn main_later_gets_a_jump() {
+        let assembly = assemble(": draw ;\n\n: main\n  draw").unwrap();
+        assert_eq!(assembly.rom, [0x12, 0x04, 0x00, 0xEE, 0x22, 0x02]);
+        assert_eq!(assembly.labels["main"], 0x204);
+        assert_eq!(assembly.line_at(0x200), Some(3));
+        // data first is fine too
+        assert_eq!(rom(": box 0xFF\n: main i := box"), [0x12, 0x03, 0xFF, 0xA2, 0x02]);
+        // main first doesn't get one
+        assert_eq!(rom(": main ;"), [0x00, 0xEE]);
+    }
+
+    #[test]
+    fn source_map() {
+        let assembly = assemble(": main\n  v0 := 1\n\n  loop\n  again\n: data 1 2\n").unwrap();
+        assert_eq!(assembly.line_at(0x200), Some(2));
+        assert_eq!(assembly.line_at(0x202), Some(5));
+        assert_eq!(assembly.line_at(0x204), None); // data isn't an instruction
+        assert_eq!(assembly.source_map_text(), "0x200 2\n0x202 5\n");
+    }
+
+    #[test]
+    fn breakpoints_and_org() {
+        let assembly = assemble(": main v0 := 1 :breakpoint here v1 := 1 :org 0x300 : far ;").unwrap();
+        assert_eq!(assembly.breakpoints, [(String::from("here"), 0x202)]);
+        assert_eq!(assembly.labels["far"], 0x300);
+        assert_eq!(assembly.rom.len(), 0x102);
+        assert_eq!(error(": main :org 0x100").message, ":org 0x100 is outside the program");
+    }
+}
@@ -0,0 +1,1375 @@
+// chip8 struct 
+// implements fetch - decode - execute cycle using opcode
+
+use crate::coverage::{self, Coverage};
+use crate::display::Display;
+use crate::error::{Chip8Error, ErrorPolicy};
+use crate::hash;
+use crate::memory::{Memory, BIG_FONT_ADDRESS, DEFAULT_LOAD_ADDRESS, FONT_ADDRESS};
+use crate::opcode::Opcode;
+use crate::platform::Platform;
+use crate::profile::Profile;
+use crate::quirks::Quirks;
+use crate::rng::Rng; //random number generator
+use crate::timer::Timer;
+use crate::trace::Trace;
+use crate::watch::{Access, WatchHit, Watchpoint};
+
+use alloc::vec::Vec;
+
+#[derive(Debug)]
+pub struct Chip8 {
+        pub(crate) memory: Memory,         // 4KB RAM, fontset, etc.
+        pub display: Display,   // 64x32 screen, or 128x64 in SUPER-CHIP hi-res, two bitplanes for XO-CHIP
+        pub(crate) program_counter: u16,   // Program Counter (starts at the load address, wraps round the end of memory)
+        pub(crate) index: u16,             // Index Register
+        pub(crate) stack: [u16; 16],       // 16-level call stack
+        pub(crate) stack_pointer: u8,      // Stack Pointer (how many levels are in use)
+        pub(crate) v_reg: [u8; 16],        // Variable registers V0-VF
+        pub delay_timer: Timer,     // Countdown timer
+        pub sound_timer: Timer,     // Beep timer
+        pub(crate) keypad: [bool; 16],     // State of the 16 CHIP-8 keys
+        pub(crate) temp_key: Option<u8>,           // stores the i of the key that has been pressed in wait method, once it's released set to 0  
+        pub(crate) platform: Platform,     // which instruction set we understand
+        pub(crate) quirks: Quirks,         // which interpreter's behaviour to copy
+        pub(crate) vblank: bool,           // set by each timer tick, cleared by draw (for the display wait quirk)
+        pub(crate) rpl: [u8; 16],          // user flags (the HP-48's RPL registers) for FX75/FX85, 8 on SUPER-CHIP, 16 on XO-CHIP
+        pub exited: bool,       // set by 00FD, the program has asked to quit
+        pub(crate) planes: u8,             // XO-CHIP bitplanes selected by FN01 for drawing, clearing and scrolling
+        pub(crate) audio_pattern: Option<[u8; 16]>, // XO-CHIP 1-bit audio pattern loaded by F002 (None plays the usual beep)
+        pub(crate) pitch: u8,              // XO-CHIP playback rate for the pattern, set by FX3A
+        pub(crate) rng: Rng,               // random numbers for CXNN
+        pub(crate) seed: u64,              // what the RNG was seeded with, so a run can be repeated exactly
+        pub(crate) error_policy: ErrorPolicy, // what to do when an instruction faults
+        pub(crate) fault: Option<Chip8Error>, // the error we halted on, if any
+        pub(crate) frame_count: u64,       // timer ticks since power on, movies use it to time key presses
+        pub(crate) watchpoints: Vec<Watchpoint>, // memory ranges the debugger wants to hear about
+        pub(crate) watch_hit: Option<WatchHit>,  // the first watched access since take_watch_hit was last called
+        pub(crate) trace: Option<Trace>,         // logs each instruction as it runs, when tracing
+        pub(crate) profile: Option<Profile>,     // counts where the cycles go, when profiling
+        pub(crate) coverage: Option<Coverage>,   // which bytes have been run, read and written, when measuring it
+        pub(crate) rom_hash: Option<[u8; 20]>,   // SHA-1 of the loaded program, for looking it up in the ROM database
+    }
+
+    impl Chip8 {
+        pub fn new(platform: Platform, quirks: Quirks) -> Self {
+            // 4KB RAM (64KB for XO-CHIP), programs loaded at 0x200
+            Self::with_memory(platform, quirks, Memory::new(platform.memory_size(), DEFAULT_LOAD_ADDRESS))
+        }
+
+        // for machines with a different memory size or load address, e.g. the ETI-660 which loads at 0x600
+        pub fn with_memory(platform: Platform, quirks: Quirks, memory: Memory) -> Self {
+            Chip8 {
+                program_counter: memory.load_address() as u16, // Program Counter
+                memory,                        // RAM, fontset, etc.
+                display: Display::new(),       // 64x32 screen
+                index: 0,                      // Index Register
+                stack: [0; 16],                // 16-level call stack
+                stack_pointer: 0,              // Stack Pointer
+                v_reg: [0; 16],                // Variable registers V0-VF
+                delay_timer: Timer::new(60),     // Countdown timer
+                sound_timer: Timer::new(60),     // Beep timer
+                keypad: [false; 16],           // State of the 16 CHIP-8 keys
+                temp_key: None,
+                platform,
+                quirks,
+                vblank: false,
+                rpl: [0; 16],
+                exited: false,
+                planes: 1,
+                audio_pattern: None,
+                pitch: 64,
+                rng: Rng::new(0),
+                seed: 0,
+                error_policy: ErrorPolicy::default(),
+                fault: None,
+                frame_count: 0,
+                watchpoints: Vec::new(),
+                watch_hit: None,
+                trace: None,
+                profile: None,
+                coverage: None,
+                rom_hash: None,
+            }
+        }
+
+        pub fn load_program(&mut self, program: &[u8]) -> Result<(), Chip8Error> {
+            //loads a program into the memory
+            self.memory.load_program(program)?;
+            self.rom_hash = Some(hash::sha1(program));
+            Ok(())
+        }
+
+        // the SHA-1 of the program loaded, the key to its settings in the ROM database
+        pub fn rom_hash(&self) -> Option<[u8; 20]> {
+            self.rom_hash
+        }
+
+        pub fn memory(&self) -> &Memory {
+            &self.memory
+        }
+
+        // e.g. for poking in a byte before the program starts
+        pub fn memory_mut(&mut self) -> &mut Memory {
+            &mut self.memory
+        }
+
+        // CXNN is deterministic: the same seed (0 unless set) always gives the same numbers
+        // the core has no clock or OS to get entropy from, so frontends that want variety pass in one (e.g. the time)
+        pub fn seed_rng(&mut self, seed: u64) {
+            self.rng = Rng::new(seed);
+            self.seed = seed;
+        }
+
+        pub fn rng_seed(&self) -> u64 {
+            self.seed
+        }
+
+        pub fn set_error_policy(&mut self, policy: ErrorPolicy) {
+            self.error_policy = policy;
+        }
+
+        // the error we halted on (only with ErrorPolicy::Halt)
+        pub fn fault(&self) -> Option<Chip8Error> {
+            self.fault
+        }
+
+        pub fn program_counter(&self) -> u16 {
+            self.program_counter
+        }
+
+        pub fn index(&self) -> u16 {
+            self.index
+        }
+
+        // V0-VF
+        pub fn registers(&self) -> &[u8; 16] {
+            &self.v_reg
+        }
+
+        // the return addresses in use, oldest first
+        pub fn stack(&self) -> &[u16] {
+            &self.stack[..self.stack_pointer as usize]
+        }
+
+        pub fn add_watchpoint(&mut self, watchpoint: Watchpoint) {
+            self.watchpoints.push(watchpoint);
+        }
+
+        pub fn watchpoints(&self) -> &[Watchpoint] {
+            &self.watchpoints
+        }
+
+        pub fn remove_watchpoint(&mut self, watchpoint: &Watchpoint) {
+            self.watchpoints.retain(|w| w != watchpoint);
+        }
+
+        pub fn clear_watchpoints(&mut self) {
+            self.watchpoints.clear();
+            self.watch_hit = None;
+        }
+
+        // the first watched access since the last call, if there's been one
+        pub fn take_watch_hit(&mut self) -> Option<WatchHit> {
+            self.watch_hit.take()
+        }
+
+        // start (Some) or stop (None) tracing, see trace.rs for the format
+        pub fn set_trace(&mut self, trace: Option<Trace>) {
+            self.trace = trace;
+        }
+
+        pub fn trace_mut(&mut self) -> Option<&mut Trace> {
+            self.trace.as_mut()
+        }
+
+        // start (Some) or stop (None) profiling, see profile.rs
+        pub fn set_profile(&mut self, profile: Option<Profile>) {
+            self.profile = profile;
+        }
+
+        pub fn profile(&self) -> Option<&Profile> {
+            self.profile.as_ref()
+        }
+
+        // start (Some) or stop (None) recording coverage, Coverage::new takes memory().size()
+        pub fn set_coverage(&mut self, coverage: Option<Coverage>) {
+            self.coverage = coverage;
+        }
+
+        pub fn coverage(&self) -> Option<&Coverage> {
+            self.coverage.as_ref()
+        }
+
+        pub fn platform(&self) -> Platform {
+            self.platform
+        }
+
+        pub fn quirks(&self) -> Quirks {
+            self.quirks
+        }
+
+        // how many times update_timers has been called
+        pub fn frame_count(&self) -> u64 {
+            self.frame_count
+        }
+
+        // which of the 16 keys are down
+        pub fn keypad(&self) -> [bool; 16] {
+            self.keypad
+        }
+
+        pub fn update_keypad(&mut self, key: usize, value: bool) {
+            // there are only 16 keys, anything else is ignored
+            if let Some(pressed) = self.keypad.get_mut(key) {
+                *pressed = value;
+            }
+        }
+
+        pub fn run_cycle_once(&mut self) -> Result<(), Chip8Error> {
+            // once the program has exited there's nothing left to run
+            if self.exited {return Ok(())}
+            // once halted we stay halted
+            if let Some(fault) = self.fault {return Err(fault)}
+
+            let address = self.program_counter;
+            let mut trace = self.trace.take();
+            let pending = trace.as_mut().and_then(|trace| trace.begin(self));
+            let opcode = self.fetch();
+            let result = self.decode_execute(opcode);
+            if let (Some(trace), Some(pending)) = (&mut trace, pending) {
+                trace.finish(pending, self, result.err());
+            }
+            self.trace = trace;
+            match result {
+                Err(err) if self.error_policy == ErrorPolicy::Halt => {
+                    // stay on the faulting instruction so it can be shown
+                    self.program_counter = address;
+                    self.fault = Some(err);
+                    Err(err)
+                }
+                // ignore and wrap carry on with the next instruction
+                _ => Ok(()),
+            }
+        }
+
+        // the XO-CHIP audio pattern and its pitch (set by FX3A), if F002 has loaded one
+        pub fn audio_pattern(&self) -> Option<([u8; 16], u8)> {
+            self.audio_pattern.map(|pattern| (pattern, self.pitch))
+        }
+
+        pub fn update_timers(&mut self) {
+            self.delay_timer.tick();
+            self.sound_timer.tick();
+            self.vblank = true;
+            self.frame_count += 1;
+            if let Some(profile) = &mut self.profile {
+                profile.frame();
+            }
+        }
+
+        // all data accesses by instructions go through these two, so watchpoints and coverage see them
+        // addresses past the end wrap round to the start, like the real address bus
+        fn read_byte(&mut self, address: usize) -> u8 {
+            let address = self.memory.wrap(address);
+            let byte = self.memory.read_byte(address);
+            self.watch(address, Access::Read, byte);
+            if let Some(coverage) = &mut self.coverage {
+                coverage.mark(address, coverage::READ);
+            }
+            byte
+        }
+
+        fn write_byte(&mut self, address: usize, byte: u8) {
+            let address = self.memory.wrap(address);
+            self.memory.write_byte(address, byte);
+            self.watch(address, Access::Write, byte);
+            if let Some(coverage) = &mut self.coverage {
+                coverage.mark(address, coverage::WRITTEN);
+            }
+        }
+
+        // the two bytes of an instruction word
+        fn mark_fetched(&mut self, address: usize) {
+            if let Some(coverage) = &mut self.coverage {
+                coverage.mark(self.memory.wrap(address), coverage::FETCHED);
+                coverage.mark(self.memory.wrap(address + 1), coverage::FETCHED);
+            }
+        }
+
+        fn watch(&mut self, address: usize, access: Access, value: u8) {
+            if self.watch_hit.is_none() && self.watchpoints.iter().any(|watchpoint| watchpoint.matches(address, access)) {
+                self.watch_hit = Some(WatchHit { address, access, value });
+            }
+        }
+
+        // the PC always wraps round the end of memory, like the real address bus
+        fn set_program_counter(&mut self, address: usize) {
+            self.program_counter = self.memory.wrap(address) as u16;
+        }
+
+        // step the PC back so the current instruction runs again next cycle (for waiting)
+        fn repeat_instruction(&mut self) {
+            self.set_program_counter(self.program_counter as usize + self.memory.size() - 2);
+        }
+
+        // the instruction words that follow the PC (for skips and F000 NNNN)
+        fn read_word(&self, address: usize) -> u16 {
+            u16::from_be_bytes([self.memory.read_byte(address), self.memory.read_byte(address + 1)])
+        }
+
+        fn fetch(&mut self) -> Opcode {
+            // fetch the instruction from memory at the current PC
+
+            // an opcode is 2 bytes, so need to read 2 bytes
+            let [first_byte, second_byte] = self.read_word(self.program_counter as usize).to_be_bytes();
+            self.mark_fetched(self.program_counter as usize);
+
+            //increment the program_counter -- do this here to avoid errors
+            self.set_program_counter(self.program_counter as usize + 2);
+
+            Opcode::new(first_byte, second_byte)
+        }
+
+        fn decode_execute(&mut self, opcode: Opcode) -> Result<(), Chip8Error> {
+            // decode the instruction and execute it
+            // the instructions are simple enough that executing it will be a one line call to a separate method
+            // match statement
+
+            // XO-CHIP is a superset of SUPER-CHIP
+            let schip = self.platform != Platform::Chip8;
+            let xo = self.platform == Platform::XoChip;
+
+            let unknown = Chip8Error::UnknownOpcode(opcode.opcode);
+
+            if let Some(profile) = &mut self.profile {
+                // fetch has already moved the PC on
+                let address = self.memory.wrap(self.program_counter as usize + self.memory.size() - 2);
+                profile.count(address as u16, &opcode);
+            }
+
+            match opcode.a {
+                0x0 => {
+                    match opcode.nn {
+                        0xE0 => self.clear_display(),
+                        0xEE => self.return_from_subroutine()?,
+                        0xC0..=0xCF | 0xFB | 0xFC if schip => self.scroll(opcode),
+                        0xD0..=0xDF if xo => self.scroll(opcode),
+                        0xFD if schip => self.exited = true,
+                        0xFE if schip => self.display.set_hires(false),
+                        0xFF if schip => self.display.set_hires(true),
+                        // 0NNN machine code routines can't be run here
+                        _ => return Err(unknown),
+                    }
+                },
+                0x1 | 0xB => self.jump(opcode),
+                0x2 => self.call(opcode)?,
+                0x5 if xo && (opcode.n == 0x2 || opcode.n == 0x3) => self.register_range(opcode),
+                0x5 | 0x9 if opcode.n != 0 => return Err(unknown),
+                0xE if opcode.nn != 0x9E && opcode.nn != 0xA1 => return Err(unknown),
+                0x3 | 0x4 | 0x5 | 0x9 | 0xE => self.skip(opcode),
+                0x8 if !matches!(opcode.n, 0x0..=0x7 | 0xE) => return Err(unknown),
+                0x6 | 0x8 | 0xC => self.set_register(opcode),
+                0x7 => self.add_to_register(opcode),
+                0xA => self.set_index_register(opcode),
+                0xD => self.draw(opcode),
+                0xF => match opcode.nn {
+                    0x00 if xo && opcode.x == 0 => self.long_index(),
+                    0x01 if xo => self.planes = opcode.x & 0x3,
+                    0x02 if xo && opcode.x == 0 => self.load_audio_pattern(),
+                    0x3A if xo => self.pitch = self.v_reg[opcode.x as usize],
+                    0x07 => self.set_register(opcode),
+                    0x0A => self.wait(opcode),
+                    0x15 => self.set_delay_timer(opcode),
+                    0x18 => self.set_sound_timer(opcode),
+                    0x1E | 0x29 => self.set_index_register(opcode),
+                    0x30 if schip => self.set_index_register(opcode),
+                    0x33 => self.store_bcd_mem(opcode),
+                    0x55 => self.store_mem(opcode),
+                    0x65 => self.read_mem(opcode),
+                    0x75 | 0x85 if schip => self.user_flags(opcode),
+                    _ => return Err(unknown),
+                }
+                _ => return Err(unknown),
+            }
+            Ok(())
+        }
+
+        fn clear_display(&mut self) {
+            // set all pixels in the display to 0 (only in the selected planes on XO-CHIP)
+            self.display.clear(self.planes);
+        }
+
+        fn scroll(&mut self, opcode: Opcode) {
+            // SUPER-CHIP scrolling, in pixels of the current resolution (00DN scroll up is XO-CHIP only)
+            match opcode.nn {
+                0xFB => self.display.scroll_right(4, self.planes),
+                0xFC => self.display.scroll_left(4, self.planes),
+                0xD0..=0xDF => self.display.scroll_up(opcode.n as usize, self.planes),
+                _ => self.display.scroll_down(opcode.n as usize, self.planes),
+            }
+        }
+
+        fn skip_next(&mut self) {
+            // skip the next instruction -- on XO-CHIP that might be the 4 byte F000 NNNN
+            let mut step = 2;
+            if self.platform == Platform::XoChip && self.read_word(self.program_counter as usize) == 0xF000 {
+                step = 4;
+            }
+            self.set_program_counter(self.program_counter as usize + step);
+        }
+
+        fn return_from_subroutine(&mut self) -> Result<(), Chip8Error> {
+            //subtracts 1 from the stack pointer, then sets the program counter to the address at the top of the stack.
+            if self.stack_pointer == 0 {
+                if self.error_policy != ErrorPolicy::Wrap {return Err(Chip8Error::StackUnderflow)}
+                self.stack_pointer = self.stack.len() as u8;
+            }
+            self.stack_pointer -= 1;
+            self.program_counter = self.stack[self.stack_pointer as usize];
+            if let Some(profile) = &mut self.profile {
+                profile.leave();
+            }
+            Ok(())
+        }
+
+        fn jump(&mut self, opcode: Opcode) {
+            // takes opcode 0x1NNN and jumps program counter to 0xNNN
+            if opcode.a == 0x1 {
+                self.set_program_counter(opcode.nnn as usize);
+            }
+            else if opcode.a == 0xB {
+                // BNNN jumps to NNN + V0, the CHIP-48 / SUPER-CHIP BXNN jumps to XNN + VX
+                let offset = if self.quirks.jumping {self.v_reg[opcode.x as usize]} else {self.v_reg[0]};
+                self.set_program_counter(opcode.nnn as usize + offset as usize);
+            }
+        }
+
+        fn call(&mut self, opcode: Opcode) -> Result<(), Chip8Error> {
+            // call subroutine at nnn
+            if self.stack_pointer as usize == self.stack.len() {
+                if self.error_policy != ErrorPolicy::Wrap {return Err(Chip8Error::StackOverflow)}
+                self.stack_pointer = 0;
+            }
+            self.stack[self.stack_pointer as usize] = self.program_counter;
+            self.stack_pointer += 1;
+            self.set_program_counter(opcode.nnn as usize);
+            if let Some(profile) = &mut self.profile {
+                profile.enter(self.program_counter);
+            }
+            Ok(())
+        }
+
+        fn skip(&mut self, opcode: Opcode) {
+
+            let vx = self.v_reg[opcode.x as usize];
+            let vy = self.v_reg[opcode.y as usize];
+            let should_skip = match opcode.a {
+                // skip next instruction if Vx == nn
+                0x3 => vx == opcode.nn,
+                // skip next instruction if Vx != nn
+                0x4 => vx != opcode.nn,
+                // skip next instruction if Vx == Vy
+                0x5 => vx == vy,
+                //Skip next instruction if Vx != Vy.
+                0x9 => vx != vy,
+                // Ex9E - SKP Vx
+                // Skip next instruction if key with the value of Vx is pressed.
+                0xE if opcode.nn == 0x9E => self.keypad[vx as usize & 0xF],
+                //ExA1 - SKNP Vx
+                //Skip next instruction if key with the value of Vx is not pressed.
+                0xE if opcode.nn == 0xA1 => !self.keypad[vx as usize & 0xF],
+                // decode_execute only sends skip opcodes here
+                _ => false,
+            };
+            if should_skip {self.skip_next();}
+        }
+
+        fn set_register(&mut self, opcode: Opcode) {
+
+            let x = opcode.x as usize;
+            let y = opcode.y as usize;
+            match opcode.a {
+                0x6 => {
+                    // set register VX
+                    self.v_reg[x] = opcode.nn;
+                },
+                0x7 => {
+                    // add value to register VX
+                    self.v_reg[x] = self.v_reg[x].wrapping_add(opcode.nn);
+                },
+                0x8 => {
+                    match opcode.n {
+                        0x0 => {
+                            // Set Vx = Vy.
+                            self.v_reg[x] = self.v_reg[y];
+                        },
+                        0x1 => {
+                            // Set Vx = Vx OR Vy.
+                            self.v_reg[x] |= self.v_reg[y];
+                            if self.quirks.vf_reset {self.v_reg[0xF] = 0;}
+                        },
+                        0x2 => {
+                            // Set Vx = Vx AND Vy.
+                            self.v_reg[x] &= self.v_reg[y];
+                            if self.quirks.vf_reset {self.v_reg[0xF] = 0;}
+                        },  
+                        0x3 => {
+                            // Set Vx = Vx XOR Vy.
+                            self.v_reg[x] ^= self.v_reg[y];
+                            if self.quirks.vf_reset {self.v_reg[0xF] = 0;}
+                        },
+                        0x4 => {
+                            // Set Vx = Vx + Vy, set VF = carry 
+                            let (sum, carry) = self.v_reg[x].overflowing_add(self.v_reg[y]);
+                            self.v_reg[x] = sum;
+                            self.v_reg[0xF] = if carry {1} else {0};
+                        },
+                        0x5 => {
+                            // Set Vx = Vx - Vy, set VF = NOT borrow.
+                            let (sub, carry) = self.v_reg[x].overflowing_sub(self.v_reg[y]);
+                            self.v_reg[x] = sub;
+                            self.v_reg[0xF] = if carry {0} else {1};
+                        },
+                        0x6 => {
+                            // Set Vx = Vy SHR 1, effectively divides by 2
+                            // CHIP-48 / SUPER-CHIP shift Vx in place instead (the shifting quirk)
+                            if !self.quirks.shifting {self.v_reg[x] = self.v_reg[y];}
+                            let carry = self.v_reg[x] & 1;
+                            self.v_reg[x] >>= 1;
+                            self.v_reg[0xF] = carry;
+                        },
+                        0x7 => {
+                            // Set Vx = Vy - Vx, set VF = NOT borrow.
+                            let (sub, carry) = self.v_reg[y].overflowing_sub(self.v_reg[x]);
+                            self.v_reg[x] = sub;
+                            self.v_reg[0xF] = if carry {0} else {1};
+                        },
+                        0xE => {
+                            // Set Vx = Vy SHL 1, effectively multiplies by 2
+                            // CHIP-48 / SUPER-CHIP shift Vx in place instead (the shifting quirk)
+                            if !self.quirks.shifting {self.v_reg[x] = self.v_reg[y];}
+                            let carry = (self.v_reg[x] & 0x80) >> 7;
+                            self.v_reg[x] <<= 1;
+                            self.v_reg[0xF] = carry;
+                        },
+                        _ => {},
+                    }
+                },
+                0xC => {
+                    // Set Vx = random byte AND nn
+                    let random_byte = self.rng.next_u8();
+                    self.v_reg[opcode.x as usize] = random_byte & opcode.nn;
+                },
+                0xF => {
+                    // FX07 - Set Vx = delay timer value (the only 0xF opcode sent here)
+                    self.v_reg[opcode.x as usize] = self.delay_timer.current_time;
+                }
+                _ => {},
+            }
+            
+        }
+
+        fn add_to_register(&mut self, opcode: Opcode) {
+            //add value to register VX
+            let i: usize = opcode.x.into();
+            self.v_reg[i] = self.v_reg[i].wrapping_add(opcode.nn);
+        }
+
+        fn set_index_register(&mut self, opcode: Opcode) {
+            //sets index register 
+            match opcode.a {
+                0xA => {self.index = opcode.nnn;}
+                0xF => {
+                    match opcode.nn {
+                        0x1E => {
+                            self.index = self.index.wrapping_add(self.v_reg[opcode.x as usize] as u16);
+                        }
+                        0x29 => {
+                            // Set I = location of sprite for digit Vx
+                            // The value of I is set to the location for the hexadecimal sprite corresponding to the value of Vx
+                            let letter = self.v_reg[opcode.x as usize] & 0xF;
+                            self.index = FONT_ADDRESS as u16 + (letter as u16) *5;
+                        }
+                        0x30 => {
+                            // Set I = location of the big 8x10 sprite for digit Vx (SUPER-CHIP)
+                            let letter = self.v_reg[opcode.x as usize] & 0xF;
+                            self.index = BIG_FONT_ADDRESS as u16 + (letter as u16) *10;
+                        }
+                        _ => {}
+                    }
+                }
+                _ => {}
+            }
+        }   
+        
+        fn long_index(&mut self) {
+            // F000 NNNN - XO-CHIP loads I with the 16 bit address in the next two bytes
+            self.index = self.read_word(self.program_counter as usize);
+            self.mark_fetched(self.program_counter as usize);
+            self.set_program_counter(self.program_counter as usize + 2);
+        }
+
+        fn draw(&mut self, opcode: Opcode) {
+            // sprite pixels in memory are XORed onto the screen
+
+            // the COSMAC VIP waits for the vertical blank before drawing, so at most one sprite per frame
+            if self.quirks.display_wait && !self.vblank {
+                self.repeat_instruction();
+                return;
+            }
+            self.vblank = false;
+
+            let width = self.display.width();
+            let height = self.display.height();
+
+            // first get x and y coordinates, the starting position wraps even if the sprite itself is clipped
+            let x_coord = self.v_reg[opcode.x as usize] as usize % width;
+            let y_coord = self.v_reg[opcode.y as usize] as usize % height;
+
+            // SUPER-CHIP draws a 16x16 sprite (two bytes per row) for DXY0
+            let (rows, cols) = if opcode.n == 0 && self.platform != Platform::Chip8 {(16, 16)} else {(opcode.n as usize, 8)};
+            let bytes_per_row = cols / 8;
+
+            // in hi-res SUPER-CHIP counts the rows that collided (or fell off the bottom) instead of just setting VF to 1
+            let mut collision = false;
+            let mut collided_rows = 0;
+
+            // XO-CHIP draws the sprite into each selected plane in turn, the data for plane 2 follows straight after plane 1's
+            // all of it is read before anything is drawn (so watchpoints stop before the screen changes)
+            let mut sprite = Vec::new(); // (plane, y, row) for each row that's drawn
+            let mut sprite_start = self.index as usize;
+            for plane in [1u8, 2] {
+                if self.planes & plane == 0 {continue}
+
+                //for n rows (starting at memory address stored in I)
+                for row in 0..rows {
+                    let mut y = y_coord + row;
+
+                    //stop if you reach bottom edge of the screen, or wrap round to the top
+                    if y >= height {
+                        if self.quirks.clipping {
+                            if plane == 1 {collided_rows += rows - row;}
+                            break
+                        }
+                        y %= height;
+                    }
+
+                    // get the sprite data for this row
+                    let i = sprite_start + row * bytes_per_row;
+                    let mut sprite_row: u16 = 0;
+                    for byte in 0..bytes_per_row {
+                        sprite_row = (sprite_row << 8) | self.read_byte(i + byte) as u16;
+                    }
+                    sprite.push((plane, y, sprite_row));
+                }
+                sprite_start += rows * bytes_per_row;
+            }
+
+            for (plane, y, sprite_row) in sprite {
+                // for each of the pixels in the sprite row, xor them onto the screen
+                let mut collided = false;
+                for bit in 0..cols {
+                    let mut x = x_coord + bit;
+                    //if you reach right edge of the screen stop drawing this row (break loop), or wrap round
+                    if x >= width {
+                        if self.quirks.clipping {break}
+                        x %= width;
+                    }
+                    let sprite_pix = (sprite_row >> (cols - 1 - bit)) & 1;
+
+                    // turning off a pixel that was on is a collision
+                    if sprite_pix == 1 && self.display.flip(x, y, plane) {collided = true}
+                }
+                if collided {
+                    collision = true;
+                    collided_rows += 1;
+                }
+            }
+
+            self.v_reg[0xF] = if self.platform == Platform::SuperChip && self.display.is_hires() {
+                collided_rows as u8
+            } else if collision {1} else {0};
+        }
+
+        fn wait(&mut self, opcode: Opcode) {
+            // Wait for a key press, store the value of the key in Vx
+            let mut found = false;
+            // check to see if a key is pressed
+            if let Some(pressed) = self.temp_key {
+                // need to check whether it's been released yet
+                if !self.keypad[pressed as usize] {
+                    self.v_reg[opcode.x as usize] = pressed;
+                    self.temp_key = None;
+                    found = true;
+                }
+            }
+            else {
+                for i in 0..=15 {
+                    if self.keypad[i] {
+                        // if so, store its value in Vx
+                        self.temp_key = Some(i as u8);
+                    }
+                }
+            }
+            // if not, adjust program counter -2
+            if !found {
+                self.repeat_instruction();
+                if let Some(profile) = &mut self.profile {
+                    profile.waiting = true;
+                }
+            }
+        }
+
+        fn user_flags(&mut self, opcode: Opcode) {
+            // FX75 saves V0 through Vx to the RPL user flags, FX85 reads them back
+            // SUPER-CHIP only has 8 flags, XO-CHIP has all 16
+            let flags = if self.platform == Platform::XoChip {16} else {8};
+            let x = (opcode.x as usize).min(flags - 1);
+            if opcode.nn == 0x75 {
+                self.rpl[..=x].copy_from_slice(&self.v_reg[..=x]);
+            }
+            else {
+                self.v_reg[..=x].copy_from_slice(&self.rpl[..=x]);
+            }
+        }
+
+        fn register_range(&mut self, opcode: Opcode) {
+            // 5XY2 saves Vx through Vy to memory starting at I, 5XY3 loads them back (XO-CHIP)
+            // the range can go either way and I is left alone
+            let x = opcode.x as usize;
+            let y = opcode.y as usize;
+            let registers: Vec<usize> = if x <= y {(x..=y).collect()} else {(y..=x).rev().collect()};
+            let i = self.index as usize;
+            for (offset, reg) in registers.into_iter().enumerate() {
+                if opcode.n == 0x2 {
+                    self.write_byte(i + offset, self.v_reg[reg]);
+                }
+                else {
+                    self.v_reg[reg] = self.read_byte(i + offset);
+                }
+            }
+        }
+
+        fn load_audio_pattern(&mut self) {
+            // F002 - copy the 16 byte (128 bit) audio pattern from memory at I (XO-CHIP)
+            let mut pattern = [0; 16];
+            for (offset, byte) in pattern.iter_mut().enumerate() {
+                *byte = self.read_byte(self.index as usize + offset);
+            }
+            self.audio_pattern = Some(pattern);
+        }
+
+        fn set_delay_timer(&mut self, opcode: Opcode) {
+            self.delay_timer.current_time = self.v_reg[opcode.x as usize];
+        }
+
+        fn set_sound_timer(&mut self, opcode: Opcode) {
+            self.sound_timer.current_time = self.v_reg[opcode.x as usize];
+        }
+
+        fn store_bcd_mem(&mut self, opcode: Opcode) {
+            // Store BCD representation of Vx in memory locations I, I+1, and I+2
+            let value = self.v_reg[opcode.x as usize];
+            let hunds = (value / 100) % 10;
+            let tens = (value / 10) % 10;// >> 4 & 0x0F;
+            let ones = value % 10;//& 0x0F;
+            let i = self.index as usize;
+
+            // hundreds digit
+            self.write_byte(i,hunds);
+            // tens digit
+            self.write_byte(i+1,tens);
+            // ones digit
+            self.write_byte(i+2,ones);
+        }
+
+        fn store_mem(&mut self, opcode: Opcode) {
+            // Store registers V0 through Vx in memory starting at location I
+            let i = self.index as usize;
+            for j in 0..=opcode.x as usize {
+                self.write_byte(i + j, self.v_reg[j]);
+            }
+            // the original interpreter left I pointing just past the last register, CHIP-48 one short of that
+            self.index = self.index.wrapping_add(self.quirks.memory_increment.amount(opcode.x));
+        }
+
+        fn read_mem(&mut self, opcode: Opcode) {
+            // Read registers V0 through Vx from memory starting at location I
+            let i = self.index as usize;
+            for j in 0..=opcode.x as usize {
+                self.v_reg[j] = self.read_byte(i + j);
+            }
+            self.index = self.index.wrapping_add(self.quirks.memory_increment.amount(opcode.x));
+        }
+
+    }
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn machine(platform: Platform, quirks: Quirks, program: &[u8]) -> Chip8 {
+        let mut chip8 = Chip8::new(platform, quirks);
+        chip8.load_program(program).unwrap();
+        chip8
+    }
+
+    fn chip48(program: &[u8]) -> Chip8 {
+        machine(Platform::Chip8, Quirks::CHIP_48, program)
+    }
+
+    fn schip(program: &[u8]) -> Chip8 {
+        machine(Platform::SuperChip, Quirks::SUPER_CHIP, program)
+    }
+
+    fn xo(program: &[u8]) -> Chip8 {
+        machine(Platform::XoChip, Quirks::XO_CHIP, program)
+    }
+
+    fn vip(program: &[u8]) -> Chip8 {
+        machine(Platform::Chip8, Quirks::COSMAC_VIP, program)
+    }
+
+    fn pixel(chip8: &Chip8, x: usize, y: usize) -> u8 {
+        chip8.display.pixels()[y * chip8.display.width() + x]
+    }
+
+    fn run(chip8: &mut Chip8, cycles: usize) {
+        for _ in 0..cycles {
+            chip8.run_cycle_once().unwrap();
+        }
+    }
+
+    // 2200 calls itself forever
+    const RECURSE: [u8; 2] = [0x22, 0x00];
+
+    #[test]
+    fn unknown_opcodes_halt_by_default() {
+        let mut chip8 = chip48(&[0x00, 0x00, 0x60, 0x01]);
+        assert_eq!(chip8.run_cycle_once(), Err(Chip8Error::UnknownOpcode(0x0000)));
+        // it stays on the faulting instruction, and keeps saying so
+        assert_eq!(chip8.program_counter(), 0x200);
+        assert_eq!(chip8.fault(), Some(Chip8Error::UnknownOpcode(0x0000)));
+        assert_eq!(chip8.run_cycle_once(), Err(Chip8Error::UnknownOpcode(0x0000)));
+        assert_eq!(chip8.registers()[0], 0);
+    }
+
+    #[test]
+    fn unknown_opcodes_are_skipped_by_ignore_and_wrap() {
+        for policy in [ErrorPolicy::Ignore, ErrorPolicy::Wrap] {
+            let mut chip8 = chip48(&[0x00, 0x00, 0x60, 0x01]);
+            chip8.set_error_policy(policy);
+            run(&mut chip8, 2);
+            assert_eq!(chip8.registers()[0], 1);
+            assert_eq!(chip8.fault(), None);
+        }
+    }
+
+    #[test]
+    fn platform_opcodes_are_unknown_elsewhere() {
+        assert_eq!(chip48(&[0x00, 0xFF]).run_cycle_once(), Err(Chip8Error::UnknownOpcode(0x00FF)));
+        assert_eq!(chip48(&[0xF0, 0x30]).run_cycle_once(), Err(Chip8Error::UnknownOpcode(0xF030)));
+        assert_eq!(machine(Platform::SuperChip, Quirks::SUPER_CHIP, &[0x50, 0x12]).run_cycle_once(), Err(Chip8Error::UnknownOpcode(0x5012)));
+        assert_eq!(chip48(&[0x80, 0x18]).run_cycle_once(), Err(Chip8Error::UnknownOpcode(0x8018)));
+        assert_eq!(chip48(&[0xE0, 0x00]).run_cycle_once(), Err(Chip8Error::UnknownOpcode(0xE000)));
+    }
+
+    #[test]
+    fn stack_overflow() {
+        let mut chip8 = chip48(&RECURSE);
+        run(&mut chip8, 16);
+        assert_eq!(chip8.stack().len(), 16);
+        assert_eq!(chip8.run_cycle_once(), Err(Chip8Error::StackOverflow));
+        assert_eq!(chip8.program_counter(), 0x200);
+        assert_eq!(chip8.stack().len(), 16);
+    }
+
+    #[test]
+    fn stack_overflow_ignored() {
+        let mut chip8 = chip48(&[0x22, 0x00, 0x60, 0x01]);
+        chip8.set_error_policy(ErrorPolicy::Ignore);
+        run(&mut chip8, 17);
+        // the 17th call is dropped and it carries on after it
+        assert_eq!(chip8.program_counter(), 0x202);
+        assert_eq!(chip8.stack().len(), 16);
+    }
+
+    #[test]
+    fn stack_overflow_wraps() {
+        let mut chip8 = chip48(&RECURSE);
+        chip8.set_error_policy(ErrorPolicy::Wrap);
+        run(&mut chip8, 17);
+        // the 17th call overwrites the first level
+        assert_eq!(chip8.program_counter(), 0x200);
+        assert_eq!(chip8.stack(), [0x202]);
+    }
+
+    #[test]
+    fn stack_underflow() {
+        let mut chip8 = chip48(&[0x00, 0xEE, 0x60, 0x01]);
+        assert_eq!(chip8.run_cycle_once(), Err(Chip8Error::StackUnderflow));
+        assert_eq!(chip8.program_counter(), 0x200);
+
+        let mut chip8 = chip48(&[0x00, 0xEE, 0x60, 0x01]);
+        chip8.set_error_policy(ErrorPolicy::Ignore);
+        run(&mut chip8, 2);
+        assert_eq!(chip8.registers()[0], 1);
+
+        // wrapping returns to whatever is in the 16th level
+        let mut chip8 = chip48(&[0x00, 0xEE]);
+        chip8.set_error_policy(ErrorPolicy::Wrap);
+        chip8.stack[15] = 0x300;
+        run(&mut chip8, 1);
+        assert_eq!(chip8.program_counter(), 0x300);
+        assert_eq!(chip8.stack().len(), 15);
+    }
+
+    #[test]
+    fn rom_too_large() {
+        let mut chip8 = Chip8::new(Platform::Chip8, Quirks::COSMAC_VIP);
+        assert!(chip8.load_program(&[0; 0x1000 - 0x200]).is_ok());
+        assert_eq!(chip8.load_program(&[0; 0x1000 - 0x200 + 1]), Err(Chip8Error::RomTooLarge { rom_size: 0xE01, max_size: 0xE00 }));
+    }
+
+    #[test]
+    fn nothing_runs_after_exit() {
+        let mut chip8 = machine(Platform::SuperChip, Quirks::SUPER_CHIP, &[0x00, 0xFD, 0x60, 0x01]);
+        run(&mut chip8, 3);
+        assert!(chip8.exited);
+        assert_eq!(chip8.program_counter(), 0x202);
+        assert_eq!(chip8.registers()[0], 0);
+    }
+
+    // a 1K machine, so NNN can be past the end of memory
+    fn small_machine(program: &[u8]) -> Chip8 {
+        let mut chip8 = Chip8::with_memory(Platform::Chip8, Quirks::CHIP_48, Memory::new(0x400, 0x200));
+        chip8.load_program(program).unwrap();
+        chip8
+    }
+
+    #[test]
+    fn jumps_wrap_round_a_small_memory() {
+        let mut chip8 = small_machine(&[0x1F, 0x04]);
+        run(&mut chip8, 1);
+        assert_eq!(chip8.program_counter(), 0x304);
+        // the CHIP-48 BXNN jumps to XNN + VX
+        let mut chip8 = small_machine(&[0x63, 0xFF, 0xB3, 0xF0]);
+        run(&mut chip8, 2);
+        assert_eq!(chip8.program_counter(), 0x3F0 + 0xFF - 0x400);
+    }
+
+    #[test]
+    fn calls_wrap_round_a_small_memory() {
+        let mut chip8 = small_machine(&[0x2C, 0x10]);
+        chip8.memory_mut().write_byte(0x010, 0x00);
+        chip8.memory_mut().write_byte(0x011, 0xEE);
+        run(&mut chip8, 1);
+        assert_eq!(chip8.program_counter(), 0x010);
+        assert_eq!(chip8.stack(), [0x202]);
+        run(&mut chip8, 1);
+        assert_eq!(chip8.program_counter(), 0x202);
+    }
+
+    #[test]
+    fn the_pc_wraps_past_the_end_of_a_small_memory() {
+        let mut chip8 = small_machine(&[0x13, 0xFE]);
+        chip8.memory_mut().write_byte(0x3FE, 0x60);
+        chip8.memory_mut().write_byte(0x3FF, 0x07);
+        run(&mut chip8, 2);
+        assert_eq!(chip8.registers()[0], 7);
+        assert_eq!(chip8.program_counter(), 0x000);
+    }
+
+    #[test]
+    fn shifting_quirk() {
+        // 8016, 8106 with V0 = 0F and V1 = F0: the VIP shifts VY into VX, CHIP-48 shifts VX in place
+        let program = [0x80, 0x16];
+        let mut chip8 = vip(&program);
+        chip8.v_reg[0] = 0x0F;
+        chip8.v_reg[1] = 0xF0;
+        run(&mut chip8, 1);
+        assert_eq!((chip8.v_reg[0], chip8.v_reg[0xF]), (0x78, 0));
+        let mut chip8 = chip48(&program);
+        chip8.v_reg[0] = 0x0F;
+        chip8.v_reg[1] = 0xF0;
+        run(&mut chip8, 1);
+        assert_eq!((chip8.v_reg[0], chip8.v_reg[0xF]), (0x07, 1));
+
+        // and the same for 801E
+        let program = [0x80, 0x1E];
+        let mut chip8 = vip(&program);
+        chip8.v_reg[0] = 0x81;
+        chip8.v_reg[1] = 0x40;
+        run(&mut chip8, 1);
+        assert_eq!((chip8.v_reg[0], chip8.v_reg[0xF]), (0x80, 0));
+        let mut chip8 = chip48(&program);
+        chip8.v_reg[0] = 0x81;
+        chip8.v_reg[1] = 0x40;
+        run(&mut chip8, 1);
+        assert_eq!((chip8.v_reg[0], chip8.v_reg[0xF]), (0x02, 1));
+    }
+
+    #[test]
+    fn jumping_quirk() {
+        // B308 with V0 = 1 and V3 = 2: BNNN adds V0, CHIP-48's BXNN adds VX
+        let mut chip8 = vip(&[0xB3, 0x08]);
+        chip8.v_reg[0] = 1;
+        chip8.v_reg[3] = 2;
+        run(&mut chip8, 1);
+        assert_eq!(chip8.program_counter(), 0x309);
+        let mut chip8 = chip48(&[0xB3, 0x08]);
+        chip8.v_reg[0] = 1;
+        chip8.v_reg[3] = 2;
+        run(&mut chip8, 1);
+        assert_eq!(chip8.program_counter(), 0x30A);
+    }
+
+    #[test]
+    fn memory_increment_quirk() {
+        for (quirks, after) in [(Quirks::COSMAC_VIP, 0x303), (Quirks::CHIP_48, 0x302), (Quirks::SUPER_CHIP, 0x300)] {
+            // F255 then F265, both from I = 300
+            let mut chip8 = machine(Platform::Chip8, quirks, &[0xF2, 0x55, 0xA3, 0x00, 0xF2, 0x65]);
+            chip8.v_reg[..3].copy_from_slice(&[7, 8, 9]);
+            chip8.index = 0x300;
+            run(&mut chip8, 1);
+            assert_eq!(chip8.index(), after, "{:?}", quirks.memory_increment);
+            assert_eq!([0x300, 0x301, 0x302].map(|address| chip8.memory().read_byte(address)), [7, 8, 9]);
+            chip8.v_reg[..3].fill(0);
+            run(&mut chip8, 2);
+            assert_eq!(chip8.index(), after);
+            assert_eq!(chip8.v_reg[..3], [7, 8, 9]);
+        }
+    }
+
+    #[test]
+    fn vf_reset_quirk() {
+        for opcode in [0x01, 0x02, 0x03] {
+            let mut chip8 = vip(&[0x80, 0x10 | opcode]);
+            chip8.v_reg[0xF] = 5;
+            run(&mut chip8, 1);
+            assert_eq!(chip8.v_reg[0xF], 0, "801{}", opcode);
+            let mut chip8 = chip48(&[0x80, 0x10 | opcode]);
+            chip8.v_reg[0xF] = 5;
+            run(&mut chip8, 1);
+            assert_eq!(chip8.v_reg[0xF], 5, "801{}", opcode);
+        }
+    }
+
+    // D014 at (62, 30) with I at a solid 8x4 block
+    fn draw_at_the_corner(platform: Platform, quirks: Quirks) -> Chip8 {
+        let mut chip8 = machine(platform, quirks, &[0xD0, 0x14, 0xFF, 0xFF, 0xFF, 0xFF]);
+        chip8.v_reg[0] = 62;
+        chip8.v_reg[1] = 30;
+        chip8.index = 0x202;
+        chip8.update_timers();
+        run(&mut chip8, 1);
+        chip8
+    }
+
+    #[test]
+    fn clipping_quirk() {
+        let chip8 = draw_at_the_corner(Platform::Chip8, Quirks::COSMAC_VIP);
+        assert_eq!(chip8.display.pixels().iter().filter(|&&pixel| pixel != 0).count(), 2 * 2);
+        assert_eq!((pixel(&chip8, 63, 31), pixel(&chip8, 0, 31), pixel(&chip8, 63, 0)), (1, 0, 0));
+
+        // without it the sprite wraps round both edges
+        let chip8 = draw_at_the_corner(Platform::Chip8, Quirks { clipping: false, ..Quirks::COSMAC_VIP });
+        assert_eq!(chip8.display.pixels().iter().filter(|&&pixel| pixel != 0).count(), 8 * 4);
+        assert_eq!((pixel(&chip8, 63, 31), pixel(&chip8, 5, 31), pixel(&chip8, 5, 1), pixel(&chip8, 6, 1)), (1, 1, 1, 0));
+    }
+
+    #[test]
+    fn sprites_start_wrapped_even_when_clipped() {
+        // (66, 35) is (2, 3)
+        let mut chip8 = vip(&[0xD0, 0x11, 0x80]);
+        chip8.v_reg[0] = 66;
+        chip8.v_reg[1] = 35;
+        chip8.index = 0x202;
+        chip8.update_timers();
+        run(&mut chip8, 1);
+        assert_eq!(pixel(&chip8, 2, 3), 1);
+    }
+
+    #[test]
+    fn display_wait_quirk() {
+        // D001 twice, with no timer tick before the first
+        let program = [0xD0, 0x01, 0xD0, 0x01];
+        let mut chip8 = vip(&program);
+        run(&mut chip8, 3);
+        // it waits for the vertical blank before drawing
+        assert_eq!(chip8.program_counter(), 0x200);
+        chip8.update_timers();
+        run(&mut chip8, 1);
+        assert_eq!(chip8.program_counter(), 0x202);
+        // one sprite per frame
+        run(&mut chip8, 1);
+        assert_eq!(chip8.program_counter(), 0x202);
+        chip8.update_timers();
+        run(&mut chip8, 1);
+        assert_eq!(chip8.program_counter(), 0x204);
+
+        let mut chip8 = chip48(&program);
+        run(&mut chip8, 2);
+        assert_eq!(chip8.program_counter(), 0x204);
+    }
+
+    #[test]
+    fn collisions_set_vf() {
+        let mut chip8 = chip48(&[0xD0, 0x01, 0xD0, 0x01, 0xD0, 0x01, 0x80]);
+        chip8.index = 0x206;
+        run(&mut chip8, 1);
+        assert_eq!((pixel(&chip8, 0, 0), chip8.v_reg[0xF]), (1, 0));
+        run(&mut chip8, 1);
+        assert_eq!((pixel(&chip8, 0, 0), chip8.v_reg[0xF]), (0, 1));
+        run(&mut chip8, 1);
+        assert_eq!(chip8.v_reg[0xF], 0);
+    }
+
+    // the screen as (x, y) for each pixel that's on
+    fn lit(chip8: &Chip8) -> Vec<(usize, usize)> {
+        let width = chip8.display.width();
+        chip8.display.pixels().iter().enumerate().filter(|(_, &pixel)| pixel != 0).map(|(i, _)| (i % width, i / width)).collect()
+    }
+
+    #[test]
+    fn hires_and_lores() {
+        // 00FF, then 00FE
+        let mut chip8 = schip(&[0x00, 0xFF, 0x00, 0xFE]);
+        run(&mut chip8, 1);
+        assert!(chip8.display.is_hires());
+        assert_eq!((chip8.display.width(), chip8.display.height()), (128, 64));
+        chip8.display.flip(100, 50, 1);
+        run(&mut chip8, 1);
+        assert!(!chip8.display.is_hires());
+        // switching clears the screen
+        assert!(lit(&chip8).is_empty());
+    }
+
+    #[test]
+    fn scroll_down() {
+        // 00C3
+        let mut chip8 = schip(&[0x00, 0xC3]);
+        chip8.display.flip(5, 0, 1);
+        chip8.display.flip(5, 31, 1);
+        run(&mut chip8, 1);
+        assert_eq!(lit(&chip8), [(5, 3)]);
+    }
+
+    #[test]
+    fn scroll_right_and_left() {
+        // 00FB, 00FC, 00FC
+        let mut chip8 = schip(&[0x00, 0xFB, 0x00, 0xFC, 0x00, 0xFC]);
+        chip8.display.flip(0, 2, 1);
+        chip8.display.flip(62, 2, 1);
+        run(&mut chip8, 1);
+        assert_eq!(lit(&chip8), [(4, 2)]);
+        run(&mut chip8, 1);
+        assert_eq!(lit(&chip8), [(0, 2)]);
+        run(&mut chip8, 1);
+        assert!(lit(&chip8).is_empty());
+    }
+
+    #[test]
+    fn scrolling_is_in_hires_pixels() {
+        // 00FF, 00C1, 00FB
+        let mut chip8 = schip(&[0x00, 0xFF, 0x00, 0xC1, 0x00, 0xFB]);
+        run(&mut chip8, 1);
+        chip8.display.flip(100, 10, 1);
+        run(&mut chip8, 2);
+        assert_eq!(lit(&chip8), [(104, 11)]);
+    }
+
+    // D010 with I at a 16x16 sprite whose rows are all 8001 (the left and right edge)
+    fn big_sprite(hires: bool, y: u8) -> Chip8 {
+        let mut program = alloc::vec![0x00, if hires {0xFF} else {0xFE}, 0xD0, 0x10, 0xD0, 0x10];
+        program.extend([0x80, 0x01].repeat(16));
+        let mut chip8 = schip(&program);
+        chip8.index = 0x206;
+        chip8.v_reg[0] = 4;
+        chip8.v_reg[1] = y;
+        chip8
+    }
+
+    #[test]
+    fn dxy0_draws_16_by_16() {
+        let mut chip8 = big_sprite(true, 0);
+        run(&mut chip8, 2);
+        let pixels = lit(&chip8);
+        assert_eq!(pixels.len(), 32);
+        assert!(pixels.contains(&(4, 0)) && pixels.contains(&(19, 0)) && pixels.contains(&(4, 15)) && pixels.contains(&(19, 15)));
+        assert_eq!(chip8.v_reg[0xF], 0);
+
+        // lo-res SUPER-CHIP draws them too
+        let mut chip8 = big_sprite(false, 0);
+        run(&mut chip8, 2);
+        assert_eq!(lit(&chip8).len(), 32);
+        // but plain CHIP-8 draws nothing for D010
+        let mut chip8 = chip48(&[0xD0, 0x10]);
+        run(&mut chip8, 1);
+        assert!(lit(&chip8).is_empty());
+    }
+
+    #[test]
+    fn hires_collisions_count_rows() {
+        // drawn twice in the same place every row collides
+        let mut chip8 = big_sprite(true, 0);
+        run(&mut chip8, 3);
+        assert_eq!(chip8.v_reg[0xF], 16);
+        assert!(lit(&chip8).is_empty());
+
+        // rows clipped off the bottom count too: 12 of them at y = 60, then 4 more that collide
+        let mut chip8 = big_sprite(true, 60);
+        run(&mut chip8, 2);
+        assert_eq!(chip8.v_reg[0xF], 12);
+        run(&mut chip8, 1);
+        assert_eq!(chip8.v_reg[0xF], 16);
+
+        // lo-res is still just 0 or 1
+        let mut chip8 = big_sprite(false, 0);
+        run(&mut chip8, 3);
+        assert_eq!(chip8.v_reg[0xF], 1);
+    }
+
+    #[test]
+    fn big_font() {
+        // F330 with V3 = 7
+        let mut chip8 = schip(&[0xF3, 0x30]);
+        chip8.v_reg[3] = 7;
+        run(&mut chip8, 1);
+        assert_eq!(chip8.index() as usize, BIG_FONT_ADDRESS + 7 * 10);
+    }
+
+    #[test]
+    fn user_flags() {
+        // F775 saves V0-V7, F785 loads them back; FF75 only has 8 flags to go into on SUPER-CHIP
+        let mut chip8 = schip(&[0xF7, 0x75, 0xF7, 0x85, 0xFF, 0x75]);
+        for (i, v) in chip8.v_reg.iter_mut().enumerate() {
+            *v = i as u8 + 1;
+        }
+        run(&mut chip8, 1);
+        assert_eq!(chip8.rpl[..8], [1, 2, 3, 4, 5, 6, 7, 8]);
+        chip8.v_reg = [0; 16];
+        run(&mut chip8, 1);
+        assert_eq!(chip8.v_reg[..9], [1, 2, 3, 4, 5, 6, 7, 8, 0]);
+        chip8.v_reg[8] = 9;
+        run(&mut chip8, 1);
+        assert_eq!(chip8.rpl[8], 0);
+    }
+
+    #[test]
+    fn exit() {
+        let mut chip8 = schip(&[0x00, 0xFD]);
+        run(&mut chip8, 1);
+        assert!(chip8.exited);
+        assert_eq!(chip8.run_cycle_once(), Ok(()));
+        assert_eq!(chip8.program_counter(), 0x202);
+        assert_eq!(chip48(&[0x00, 0xFD]).run_cycle_once(), Err(Chip8Error::UnknownOpcode(0x00FD)));
+    }
+
+    #[test]
+    fn long_index() {
+        // F000 ABCD, then 6001
+        let mut chip8 = xo(&[0xF0, 0x00, 0xAB, 0xCD, 0x60, 0x01]);
+        run(&mut chip8, 2);
+        assert_eq!(chip8.index(), 0xABCD);
+        assert_eq!(chip8.v_reg[0], 1);
+        assert_eq!(chip8.memory().size(), 0x10000);
+        assert_eq!(machine(Platform::SuperChip, Quirks::SUPER_CHIP, &[0xF0, 0x00]).run_cycle_once(), Err(Chip8Error::UnknownOpcode(0xF000)));
+    }
+
+    #[test]
+    fn skips_step_over_long_index() {
+        // 3000 (taken) over F000 ABCD to 6001
+        let mut chip8 = xo(&[0x30, 0x00, 0xF0, 0x00, 0xAB, 0xCD, 0x60, 0x01]);
+        run(&mut chip8, 1);
+        assert_eq!(chip8.program_counter(), 0x206);
+        run(&mut chip8, 1);
+        assert_eq!((chip8.v_reg[0], chip8.index()), (1, 0));
+
+        // SUPER-CHIP only skips two bytes
+        let mut chip8 = machine(Platform::SuperChip, Quirks::SUPER_CHIP, &[0x30, 0x00, 0xF0, 0x00]);
+        run(&mut chip8, 1);
+        assert_eq!(chip8.program_counter(), 0x204);
+    }
+
+    #[test]
+    fn register_range_save_and_load() {
+        // 5242 saves V2-V4 at I, 5422 saves them backwards, and I is left alone
+        let mut chip8 = xo(&[0x52, 0x42, 0xA3, 0x10, 0x54, 0x22]);
+        chip8.v_reg[2..5].copy_from_slice(&[0x22, 0x33, 0x44]);
+        chip8.index = 0x300;
+        run(&mut chip8, 1);
+        assert_eq!(chip8.index(), 0x300);
+        assert_eq!([0x300, 0x301, 0x302].map(|address| chip8.memory().read_byte(address)), [0x22, 0x33, 0x44]);
+        run(&mut chip8, 2);
+        assert_eq!([0x310, 0x311, 0x312].map(|address| chip8.memory().read_byte(address)), [0x44, 0x33, 0x22]);
+
+        // 5133 loads V1-V3 from I, 5313 loads them backwards
+        let mut chip8 = xo(&[0x51, 0x33, 0x53, 0x13, 0x10, 0x20, 0x30]);
+        chip8.index = 0x204;
+        run(&mut chip8, 1);
+        assert_eq!(chip8.v_reg[1..4], [0x10, 0x20, 0x30]);
+        run(&mut chip8, 1);
+        assert_eq!(chip8.v_reg[1..4], [0x30, 0x20, 0x10]);
+    }
+
+    #[test]
+    fn plane_select() {
+        // F201 then D001: plane 2 only
+        let mut chip8 = xo(&[0xF2, 0x01, 0xD0, 0x01, 0x80]);
+        chip8.index = 0x204;
+        run(&mut chip8, 2);
+        assert_eq!(pixel(&chip8, 0, 0), 2);
+
+        // F301 then D002: both planes, plane 2's row straight after plane 1's
+        let mut chip8 = xo(&[0xF3, 0x01, 0xD0, 0x01, 0x80, 0x40]);
+        chip8.index = 0x204;
+        run(&mut chip8, 2);
+        assert_eq!((pixel(&chip8, 0, 0), pixel(&chip8, 1, 0)), (1, 2));
+
+        // F001 selects nothing, so nothing's drawn
+        let mut chip8 = xo(&[0xF0, 0x01, 0xD0, 0x01, 0x80]);
+        chip8.index = 0x204;
+        run(&mut chip8, 2);
+        assert!(lit(&chip8).is_empty());
+    }
+
+    #[test]
+    fn clear_and_scroll_only_the_selected_planes() {
+        // F101, 00E0, F201, 00D1
+        let mut chip8 = xo(&[0xF1, 0x01, 0x00, 0xE0, 0xF2, 0x01, 0x00, 0xD1]);
+        chip8.display.flip(3, 3, 1);
+        chip8.display.flip(3, 3, 2);
+        chip8.display.flip(5, 5, 2);
+        run(&mut chip8, 2);
+        assert_eq!((pixel(&chip8, 3, 3), pixel(&chip8, 5, 5)), (2, 2));
+        run(&mut chip8, 2);
+        assert_eq!(lit(&chip8), [(3, 2), (5, 4)]);
+        assert_eq!(pixel(&chip8, 3, 2), 2);
+    }
+
+    #[test]
+    fn audio_pattern_and_pitch() {
+        // F002 from I, then F53A with V5 = 100
+        let mut program = alloc::vec![0xF0, 0x02, 0xF5, 0x3A];
+        program.extend(0..16);
+        let mut chip8 = xo(&program);
+        chip8.index = 0x204;
+        chip8.v_reg[5] = 100;
+        assert_eq!(chip8.audio_pattern(), None);
+        run(&mut chip8, 1);
+        assert_eq!(chip8.audio_pattern(), Some((core::array::from_fn(|i| i as u8), 64)));
+        run(&mut chip8, 1);
+        assert_eq!(chip8.audio_pattern().unwrap().1, 100);
+    }
+
+    #[test]
+    fn sixteen_user_flags() {
+        let mut chip8 = xo(&[0xFF, 0x75, 0xFF, 0x85]);
+        chip8.v_reg = core::array::from_fn(|i| i as u8 + 1);
+        run(&mut chip8, 1);
+        chip8.v_reg = [0; 16];
+        run(&mut chip8, 1);
+        assert_eq!(chip8.v_reg, core::array::from_fn(|i| i as u8 + 1));
+    }
+}
@@ -0,0 +1,211 @@
+// coverage -- which bytes of memory were run as instructions, read as data or written
+// for finding dead code in a ROM, and checking that test inputs reach every branch
+//
+// fetches are marked by fetch (and the second word of F000 NNNN), reads and writes by read_byte / write_byte,
+// so everything an instruction touches through I (sprites, FX65, FX33, ...) is counted
+
+use crate::png;
+
+use alloc::format;
+use alloc::string::String;
+use alloc::vec;
+use alloc::vec::Vec;
+
+pub const FETCHED: u8 = 1;
+pub const READ: u8 = 2;
+pub const WRITTEN: u8 = 4;
+
+// colours for the memory map image
+const UNUSED: [u8; 3] = [0x20, 0x22, 0x33];        // outside the ROM, never touched
+const UNTOUCHED: [u8; 3] = [0x80, 0x80, 0x80];     // in the ROM but never touched, dead code or data
+const EXECUTED: [u8; 3] = [0x3F, 0xB9, 0x50];
+const DATA_READ: [u8; 3] = [0x58, 0xA6, 0xFF];
+const DATA_WRITTEN: [u8; 3] = [0xF8, 0x51, 0x49];
+const SELF_MODIFIED: [u8; 3] = [0xE3, 0xB3, 0x41]; // run as code and written to
+
+#[derive(Debug, Clone)]
+pub struct Coverage {
+    flags: Vec<u8>, // FETCHED | READ | WRITTEN for every byte of memory
+}
+
+impl Coverage {
+    pub fn new(memory_size: usize) -> Self {
+        Coverage { flags: vec![0; memory_size] }
+    }
+
+    pub(crate) fn mark(&mut self, address: usize, flag: u8) {
+        if let Some(flags) = self.flags.get_mut(address) {
+            *flags |= flag;
+        }
+    }
+
+    pub fn flags(&self, address: usize) -> u8 {
+        self.flags.get(address).copied().unwrap_or(0)
+    }
+
+    // a summary of the ROM (`rom_length` bytes from `load_address`), then every range of memory that was touched,
+    // and the parts of the ROM that weren't
+    pub fn report(&self, load_address: usize, rom_length: usize) -> String {
+        let rom = load_address.min(self.flags.len())..(load_address + rom_length).min(self.flags.len());
+        let count = |flag: u8| self.flags[rom.clone()].iter().filter(|&&flags| flags & flag != 0).count();
+        let untouched = self.flags[rom.clone()].iter().filter(|&&flags| flags == 0).count();
+        let percent = |count: usize| if rom.is_empty() {0.0} else {count as f64 * 100.0 / rom.len() as f64};
+
+        let mut out = String::from("# chip8 coverage\n");
+        out += &format!("rom        {:#05x}-{:#05x}, {} bytes\n", rom.start, rom.end.saturating_sub(1), rom.len());
+        for (name, count) in [("executed", count(FETCHED)), ("read", count(READ)), ("written", count(WRITTEN)), ("untouched", untouched)] {
+            out += &format!("{:<9} {:>6}  {:>5.1}%\n", name, count, percent(count));
+        }
+        out += "\n";
+
+        // runs of bytes with the same flags, skipping untouched memory outside the ROM
+        let mut start = 0;
+        while start < self.flags.len() {
+            let flags = self.flags[start];
+            let in_rom = rom.contains(&start);
+            let mut end = start + 1;
+            while end < self.flags.len() && self.flags[end] == flags && rom.contains(&end) == in_rom {
+                end += 1;
+            }
+            if flags != 0 || in_rom {
+                out += &format!("{:#05x}-{:#05x}  {:>5}  {}\n", start, end - 1, end - start, describe(flags));
+            }
+            start = end;
+        }
+        out
+    }
+
+    // the memory map as an RGB image, one `scale` x `scale` square per byte, 64 bytes a row (256 for XO-CHIP's 64K)
+    // returns the width, height and pixels
+    pub fn image(&self, load_address: usize, rom_length: usize, scale: usize) -> (usize, usize, Vec<u8>) {
+        let columns = if self.flags.len() > 4096 {256} else {64};
+        let rows = self.flags.len().div_ceil(columns);
+        let (width, height) = (columns * scale, rows * scale);
+        let mut rgb = Vec::with_capacity(width * height * 3);
+        for row in 0..rows {
+            for _ in 0..scale {
+                for column in 0..columns {
+                    let address = row * columns + column;
+                    let in_rom = (load_address..load_address + rom_length).contains(&address);
+                    let colour = colour(self.flags(address), in_rom);
+                    for _ in 0..scale {
+                        rgb.extend_from_slice(&colour);
+                    }
+                }
+            }
+        }
+        (width, height, rgb)
+    }
+
+    // image() as a PNG, 256x256 for 4K of memory and 512x512 for 64K
+    pub fn png(&self, load_address: usize, rom_length: usize) -> Vec<u8> {
+        let scale = if self.flags.len() > 4096 {2} else {4};
+        let (width, height, rgb) = self.image(load_address, rom_length, scale);
+        png::encode_rgb(width, height, &rgb)
+    }
+}
+
+fn describe(flags: u8) -> String {
+    if flags == 0 {
+        return String::from("untouched");
+    }
+    let names: Vec<&str> = [(FETCHED, "executed"), (READ, "read"), (WRITTEN, "written")]
+        .iter()
+        .filter(|(flag, _)| flags & flag != 0)
+        .map(|(_, name)| *name)
+        .collect();
+    names.join(", ")
+}
+
+fn colour(flags: u8, in_rom: bool) -> [u8; 3] {
+    match flags {
+        0 if in_rom => UNTOUCHED,
+        0 => UNUSED,
+        _ if flags & FETCHED != 0 && flags & WRITTEN != 0 => SELF_MODIFIED,
+        _ if flags & FETCHED != 0 => EXECUTED,
+        _ if flags & WRITTEN != 0 => DATA_WRITTEN,
+        _ => DATA_READ,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chip8::Chip8;
+    use crate::platform::Platform;
+    use crate::quirks::Quirks;
+
+    // reads two bytes of data with FX65, writes three with FX33, then loops, leaving two bytes untouched
+    const PROGRAM: [u8; 14] = [
+        0xA2, 0x0A, 0xF1, 0x65, 0xA3, 0x00, 0xF0, 0x33, 0x12, 0x08,
+        0x12, 0x34,
+        0x00, 0x00,
+    ];
+
+    fn covered() -> Chip8 {
+        let mut chip8 = Chip8::new(Platform::Chip8, Quirks::CHIP_48);
+        chip8.load_program(&PROGRAM).unwrap();
+        chip8.set_coverage(Some(Coverage::new(chip8.memory().size())));
+        for _ in 0..6 {
+            chip8.run_cycle_once().unwrap();
+        }
+        chip8
+    }
+
+    #[test]
+    fn marks_fetches_reads_and_writes() {
+        let chip8 = covered();
+        let coverage = chip8.coverage().unwrap();
+        assert!((0x200..0x20A).all(|address| coverage.flags(address) == FETCHED));
+        assert_eq!([0x20A, 0x20B].map(|address| coverage.flags(address)), [READ; 2]);
+        assert_eq!([0x20C, 0x20D].map(|address| coverage.flags(address)), [0; 2]);
+        assert_eq!([0x300, 0x301, 0x302, 0x303].map(|address| coverage.flags(address)), [WRITTEN, WRITTEN, WRITTEN, 0]);
+        // outside memory is never touched
+        assert_eq!(coverage.flags(0x1000), 0);
+    }
+
+    #[test]
+    fn report_summarises_the_rom_then_lists_runs() {
+        let chip8 = covered();
+        assert_eq!(chip8.coverage().unwrap().report(0x200, PROGRAM.len()), concat!(
+            "# chip8 coverage\n",
+            "rom        0x200-0x20d, 14 bytes\n",
+            "executed      10   71.4%\n",
+            "read           2   14.3%\n",
+            "written        0    0.0%\n",
+            "untouched      2   14.3%\n",
+            "\n",
+            "0x200-0x209     10  executed\n",
+            "0x20a-0x20b      2  read\n",
+            "0x20c-0x20d      2  untouched\n",
+            "0x300-0x302      3  written\n",
+        ));
+    }
+
+    #[test]
+    fn combines_flags() {
+        let mut coverage = Coverage::new(16);
+        coverage.mark(3, FETCHED);
+        coverage.mark(3, WRITTEN);
+        coverage.mark(4, READ);
+        coverage.mark(4, WRITTEN);
+        coverage.mark(16, READ);
+        assert_eq!(coverage.report(0, 0).lines().skip(7).collect::<Vec<_>>(), ["0x003-0x003      1  executed, written", "0x004-0x004      1  read, written"]);
+        assert_eq!(colour(coverage.flags(3), true), SELF_MODIFIED);
+        assert_eq!(colour(coverage.flags(4), true), DATA_WRITTEN);
+        assert_eq!(colour(0, true), UNTOUCHED);
+        assert_eq!(colour(0, false), UNUSED);
+    }
+
+    #[test]
+    fn image_is_a_square_per_byte() {
+        let chip8 = covered();
+        let (width, height, rgb) = chip8.coverage().unwrap().image(0x200, PROGRAM.len(), 2);
+        assert_eq!((width, height, rgb.len()), (128, 128, 128 * 128 * 3));
+        // 0x200 is the first byte of row 8, 0x20C the 13th
+        let pixel = |x: usize, y: usize| &rgb[(y * width + x) * 3..][..3];
+        assert_eq!(pixel(1, 17), EXECUTED);
+        assert_eq!(pixel(24, 16), UNTOUCHED);
+        assert_eq!(pixel(0, 0), UNUSED);
+    }
+}
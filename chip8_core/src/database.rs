@@ -0,0 +1,472 @@
+// the ROM database -- settings for known ROMs, looked up by the SHA-1 of the ROM
+//
+// it reads the programs.json of the community CHIP-8 database (https://github.com/chip-8/chip-8-database):
+// an array of programs, each with a "roms" object keyed by SHA-1, and for each ROM the platforms it runs on
+// (best first), "tickrate" (instructions per frame), "keys", "colors" and "quirkyPlatforms" (quirks that differ from
+// the platform's usual ones). everything else in there (titles aside) is ignored
+//
+// bundled() is data/programs.json, compiled in. the copy checked in here is empty, drop the real one in its place
+// (or point a frontend at it) to get the whole database
+
+use crate::error::Chip8Error;
+use crate::hash;
+use crate::platform::Platform;
+use crate::quirks::{MemoryIncrement, Quirks};
+
+use alloc::collections::BTreeMap;
+use alloc::string::String;
+use alloc::vec::Vec;
+
+const BUNDLED: &str = include_str!("../data/programs.json");
+
+// what the database knows about one ROM
+#[derive(Debug, Clone, PartialEq)]
+pub struct RomInfo {
+    pub title: String,
+    pub platform: Platform,
+    pub quirks: Quirks,
+    pub cycles_per_frame: Option<u32>,
+    pub keys: BTreeMap<String, u8>, // e.g. "up" -> 5, the CHIP-8 key the game uses for each control
+    pub colors: Vec<u32>,           // 0xRRGGBB, the background then each plane (empty if the database doesn't say)
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct Database {
+    roms: BTreeMap<String, RomInfo>, // by SHA-1 in lower case hex
+}
+
+impl Database {
+    // panics if data/programs.json doesn't parse, that's a broken build rather than something to carry on from
+    // (the bundled_database_parses test catches it first)
+    pub fn bundled() -> Database {
+        match Database::parse(BUNDLED) {
+            Ok(database) => database,
+            Err(err) => panic!("the bundled data/programs.json is broken: {}", err),
+        }
+    }
+
+    pub fn parse(text: &str) -> Result<Database, Chip8Error> {
+        let programs = match json::parse(text)? {
+            json::Value::Array(programs) => programs,
+            _ => return Err(Chip8Error::InvalidDatabase("expected an array of programs")),
+        };
+        let mut roms = BTreeMap::new();
+        for program in &programs {
+            let title = program.get("title").and_then(json::Value::as_str).unwrap_or("");
+            let Some(json::Value::Object(entries)) = program.get("roms") else {continue};
+            for (sha1, rom) in entries {
+                // ROMs for platforms we can't run (MegaChip, CHIP-8X) are left out
+                if let Some(info) = rom_info(title, rom) {
+                    roms.insert(sha1.to_ascii_lowercase(), info);
+                }
+            }
+        }
+        Ok(Database { roms })
+    }
+
+    pub fn lookup(&self, sha1: &[u8; 20]) -> Option<&RomInfo> {
+        self.roms.get(&hash::to_hex(sha1))
+    }
+
+    pub fn len(&self) -> usize {
+        self.roms.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.roms.is_empty()
+    }
+}
+
+fn rom_info(title: &str, rom: &json::Value) -> Option<RomInfo> {
+    let platforms = match rom.get("platforms") {
+        Some(json::Value::Array(platforms)) => platforms.iter().filter_map(json::Value::as_str).collect(),
+        _ => Vec::new(),
+    };
+    // the first platform on the list that we can run
+    let (id, platform, mut quirks) = platforms.iter().find_map(|&id| platform(id).map(|(platform, quirks)| (id, platform, quirks)))?;
+
+    if let Some(overrides) = rom.get("quirkyPlatforms").and_then(|quirky| quirky.get(id)) {
+        let flag = |name: &str| overrides.get(name).and_then(json::Value::as_bool);
+        if let Some(on) = flag("shift") {quirks.shifting = on}
+        // leaving I alone wins over incrementing by X if a ROM somehow says both
+        if let Some(on) = flag("memoryIncrementByX") {
+            if on {
+                quirks.memory_increment = MemoryIncrement::ByX;
+            } else if quirks.memory_increment == MemoryIncrement::ByX {
+                quirks.memory_increment = MemoryIncrement::ByXPlusOne;
+            }
+        }
+        if let Some(on) = flag("memoryLeaveIUnchanged") {
+            if on {
+                quirks.memory_increment = MemoryIncrement::Unchanged;
+            } else if quirks.memory_increment == MemoryIncrement::Unchanged {
+                quirks.memory_increment = MemoryIncrement::ByXPlusOne;
+            }
+        }
+        if let Some(on) = flag("wrap") {quirks.clipping = !on}
+        if let Some(on) = flag("jump") {quirks.jumping = on}
+        if let Some(on) = flag("vblank") {quirks.display_wait = on}
+        if let Some(on) = flag("logic") {quirks.vf_reset = on}
+    }
+
+    let cycles_per_frame = rom.get("tickrate").and_then(json::Value::as_number).filter(|&n| n >= 1.0).map(|n| n as u32);
+    let mut keys = BTreeMap::new();
+    if let Some(json::Value::Object(entries)) = rom.get("keys") {
+        for (name, key) in entries {
+            if let Some(key) = key.as_number().filter(|&key| (0.0..16.0).contains(&key)) {
+                keys.insert(name.clone(), key as u8);
+            }
+        }
+    }
+    let colors = match rom.get("colors").and_then(|colors| colors.get("pixels")) {
+        Some(json::Value::Array(pixels)) => pixels.iter().filter_map(|pixel| pixel.as_str().and_then(parse_color)).collect(),
+        _ => Vec::new(),
+    };
+    Some(RomInfo { title: String::from(title), platform, quirks, cycles_per_frame, keys, colors })
+}
+
+// the database's platform ids, and what we run them as
+fn platform(id: &str) -> Option<(Platform, Quirks)> {
+    match id {
+        "originalChip8" | "hybridVIP" => Some((Platform::Chip8, Quirks::COSMAC_VIP)),
+        // what most modern interpreters do, the VIP's instruction set without its quirks
+        "modernChip8" => Some((Platform::Chip8, Quirks { vf_reset: false, memory_increment: MemoryIncrement::Unchanged, display_wait: false, ..Quirks::COSMAC_VIP })),
+        "chip48" => Some((Platform::Chip8, Quirks::CHIP_48)),
+        "superchip1" | "superchip" => Some((Platform::SuperChip, Quirks::SUPER_CHIP)),
+        "xochip" => Some((Platform::XoChip, Quirks::XO_CHIP)),
+        _ => None,
+    }
+}
+
+// "#RRGGBB" (or without the #), for colours in config files too
+pub fn parse_color(text: &str) -> Option<u32> {
+    let hex = text.strip_prefix('#').unwrap_or(text);
+    if hex.len() != 6 {return None}
+    u32::from_str_radix(hex, 16).ok()
+}
+
+// just enough JSON to read the database
+mod json {
+    use crate::error::Chip8Error;
+
+    use alloc::string::String;
+    use alloc::vec::Vec;
+
+    #[derive(Debug, Clone, PartialEq)]
+    pub enum Value {
+        Null,
+        Bool(bool),
+        Number(f64),
+        String(String),
+        Array(Vec<Value>),
+        Object(Vec<(String, Value)>),
+    }
+
+    impl Value {
+        pub fn get(&self, key: &str) -> Option<&Value> {
+            match self {
+                Value::Object(entries) => entries.iter().find(|(name, _)| name == key).map(|(_, value)| value),
+                _ => None,
+            }
+        }
+
+        pub fn as_str(&self) -> Option<&str> {
+            match self {
+                Value::String(text) => Some(text),
+                _ => None,
+            }
+        }
+
+        pub fn as_number(&self) -> Option<f64> {
+            match self {
+                Value::Number(n) => Some(*n),
+                _ => None,
+            }
+        }
+
+        pub fn as_bool(&self) -> Option<bool> {
+            match self {
+                Value::Bool(b) => Some(*b),
+                _ => None,
+            }
+        }
+    }
+
+    pub fn parse(text: &str) -> Result<Value, Chip8Error> {
+        let mut parser = Parser { bytes: text.as_bytes(), at: 0 };
+        let value = parser.value(0)?;
+        parser.skip_space();
+        if parser.at != parser.bytes.len() {
+            return Err(Chip8Error::InvalidDatabase("something after the end of the JSON"));
+        }
+        Ok(value)
+    }
+
+    const MAX_DEPTH: usize = 64;
+
+    struct Parser<'a> {
+        bytes: &'a [u8],
+        at: usize,
+    }
+
+    impl Parser<'_> {
+        fn skip_space(&mut self) {
+            while self.bytes.get(self.at).is_some_and(u8::is_ascii_whitespace) {
+                self.at += 1;
+            }
+        }
+
+        fn next(&mut self) -> Option<u8> {
+            let byte = self.bytes.get(self.at).copied();
+            self.at += 1;
+            byte
+        }
+
+        fn expect(&mut self, byte: u8, reason: &'static str) -> Result<(), Chip8Error> {
+            self.skip_space();
+            if self.next() == Some(byte) {Ok(())} else {Err(Chip8Error::InvalidDatabase(reason))}
+        }
+
+        fn literal(&mut self, word: &str, value: Value) -> Result<Value, Chip8Error> {
+            if self.bytes[self.at..].starts_with(word.as_bytes()) {
+                self.at += word.len();
+                Ok(value)
+            } else {
+                Err(Chip8Error::InvalidDatabase("unexpected character"))
+            }
+        }
+
+        fn value(&mut self, depth: usize) -> Result<Value, Chip8Error> {
+            if depth > MAX_DEPTH {
+                return Err(Chip8Error::InvalidDatabase("nested too deeply"));
+            }
+            self.skip_space();
+            match self.bytes.get(self.at) {
+                Some(b'{') => {
+                    self.at += 1;
+                    let mut entries = Vec::new();
+                    self.skip_space();
+                    if self.bytes.get(self.at) == Some(&b'}') {
+                        self.at += 1;
+                        return Ok(Value::Object(entries));
+                    }
+                    loop {
+                        self.expect(b'"', "expected a key in quotes")?;
+                        let key = self.string()?;
+                        self.expect(b':', "expected : after a key")?;
+                        entries.push((key, self.value(depth + 1)?));
+                        self.skip_space();
+                        match self.next() {
+                            Some(b',') => continue,
+                            Some(b'}') => return Ok(Value::Object(entries)),
+                            _ => return Err(Chip8Error::InvalidDatabase("expected , or } in an object")),
+                        }
+                    }
+                },
+                Some(b'[') => {
+                    self.at += 1;
+                    let mut items = Vec::new();
+                    self.skip_space();
+                    if self.bytes.get(self.at) == Some(&b']') {
+                        self.at += 1;
+                        return Ok(Value::Array(items));
+                    }
+                    loop {
+                        items.push(self.value(depth + 1)?);
+                        self.skip_space();
+                        match self.next() {
+                            Some(b',') => continue,
+                            Some(b']') => return Ok(Value::Array(items)),
+                            _ => return Err(Chip8Error::InvalidDatabase("expected , or ] in an array")),
+                        }
+                    }
+                },
+                Some(b'"') => {
+                    self.at += 1;
+                    Ok(Value::String(self.string()?))
+                },
+                Some(b't') => self.literal("true", Value::Bool(true)),
+                Some(b'f') => self.literal("false", Value::Bool(false)),
+                Some(b'n') => self.literal("null", Value::Null),
+                Some(_) => self.number(),
+                None => Err(Chip8Error::InvalidDatabase("unexpected end of the JSON")),
+            }
+        }
+
+        // after the opening quote
+        fn string(&mut self) -> Result<String, Chip8Error> {
+            let mut text = String::new();
+            loop {
+                // plain runs are copied in one go, they're always whole UTF-8 characters since " and \ are ASCII
+                let run = self.at;
+                while self.bytes.get(self.at).is_some_and(|&byte| byte != b'"' && byte != b'\\') {
+                    self.at += 1;
+                }
+                text += core::str::from_utf8(&self.bytes[run..self.at]).map_err(|_| Chip8Error::InvalidDatabase("bad UTF-8"))?;
+                match self.next() {
+                    Some(b'"') => return Ok(text),
+                    Some(b'\\') => {
+                        let c = match self.next() {
+                            Some(b'"') => '"',
+                            Some(b'\\') => '\\',
+                            Some(b'/') => '/',
+                            Some(b'b') => '\u{8}',
+                            Some(b'f') => '\u{c}',
+                            Some(b'n') => '\n',
+                            Some(b'r') => '\r',
+                            Some(b't') => '\t',
+                            // surrogate pairs come out as two replacement characters, nothing we read has any
+                            Some(b'u') => {
+                                let hex = self.bytes.get(self.at..self.at + 4).and_then(|hex| core::str::from_utf8(hex).ok());
+                                let code = hex.and_then(|hex| u32::from_str_radix(hex, 16).ok())
+                                    .ok_or(Chip8Error::InvalidDatabase("bad \\u escape"))?;
+                                self.at += 4;
+                                char::from_u32(code).unwrap_or(char::REPLACEMENT_CHARACTER)
+                            },
+                            _ => return Err(Chip8Error::InvalidDatabase("bad escape in a string")),
+                        };
+                        text.push(c);
+                    },
+                    _ => return Err(Chip8Error::InvalidDatabase("unterminated string")),
+                }
+            }
+        }
+
+        fn number(&mut self) -> Result<Value, Chip8Error> {
+            let start = self.at;
+            while self.bytes.get(self.at).is_some_and(|&byte| byte.is_ascii_digit() || b"+-.eE".contains(&byte)) {
+                self.at += 1;
+            }
+            core::str::from_utf8(&self.bytes[start..self.at]).ok()
+                .and_then(|text| text.parse().ok())
+                .map(Value::Number)
+                .ok_or(Chip8Error::InvalidDatabase("unexpected character"))
+        }
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+
+        fn invalid(text: &str) -> Chip8Error {
+            parse(text).unwrap_err()
+        }
+
+        #[test]
+        fn escapes() {
+            let text = r#""a\"b\\c\/d\n\t\u0041\u00e9 é""#;
+            assert_eq!(parse(text), Ok(Value::String(String::from("a\"b\\c/d\n\tAé é"))));
+        }
+
+        #[test]
+        fn nesting() {
+            let value = parse(r#" { "a": [1, {"b": [ ]}, {}], "c": {"d": null, "e": true, "f": false} } "#).unwrap();
+            assert_eq!(value.get("a"), Some(&Value::Array(alloc::vec![
+                Value::Number(1.0),
+                Value::Object(alloc::vec![(String::from("b"), Value::Array(Vec::new()))]),
+                Value::Object(Vec::new()),
+            ])));
+            let c = value.get("c").unwrap();
+            assert_eq!(c.get("d"), Some(&Value::Null));
+            assert_eq!(c.get("e").and_then(Value::as_bool), Some(true));
+            assert_eq!(c.get("f").and_then(Value::as_bool), Some(false));
+            assert_eq!(c.get("g"), None);
+        }
+
+        #[test]
+        fn numbers() {
+            for (text, n) in [("0", 0.0), ("-12", -12.0), ("3.5", 3.5), ("1e3", 1000.0), ("2.5E-1", 0.25)] {
+                assert_eq!(parse(text), Ok(Value::Number(n)), "{}", text);
+            }
+            assert_eq!(invalid("1.2.3"), Chip8Error::InvalidDatabase("unexpected character"));
+            assert_eq!(invalid("-"), Chip8Error::InvalidDatabase("unexpected character"));
+        }
+
+        #[test]
+        fn malformed() {
+            let cases = [
+                ("", "unexpected end of the JSON"),
+                ("[1, 2", "expected , or ] in an array"),
+                ("[1 2]", "expected , or ] in an array"),
+                (r#"{"a" 1}"#, "expected : after a key"),
+                (r#"{a: 1}"#, "expected a key in quotes"),
+                (r#"{"a": 1,}"#, "expected a key in quotes"),
+                (r#"{"a": 1"#, "expected , or } in an object"),
+                (r#""abc"#, "unterminated string"),
+                (r#""\x""#, "bad escape in a string"),
+                (r#""\u12""#, "bad \\u escape"),
+                ("tru", "unexpected character"),
+                ("[] []", "something after the end of the JSON"),
+            ];
+            for (text, reason) in cases {
+                assert_eq!(invalid(text), Chip8Error::InvalidDatabase(reason), "{}", text);
+            }
+            let deep = "[".repeat(MAX_DEPTH + 2);
+            assert_eq!(invalid(&deep), Chip8Error::InvalidDatabase("nested too deeply"));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SHA1: &str = "0123456789abcdef0123456789abcdef01234567";
+
+    fn sha1() -> [u8; 20] {
+        let mut bytes = [0; 20];
+        for (i, byte) in bytes.iter_mut().enumerate() {
+            *byte = u8::from_str_radix(&SHA1[i * 2..i * 2 + 2], 16).unwrap();
+        }
+        bytes
+    }
+
+    fn info(rom: &str) -> RomInfo {
+        let text = alloc::format!(r#"[{{"title": "Test", "roms": {{"{}": {}}}}}]"#, SHA1.to_ascii_uppercase(), rom);
+        Database::parse(&text).unwrap().lookup(&sha1()).unwrap().clone()
+    }
+
+    #[test]
+    fn bundled_database_parses() {
+        // the copy checked in is empty, but it still has to be a valid programs.json
+        assert!(Database::parse(BUNDLED).is_ok());
+    }
+
+    #[test]
+    fn reads_a_rom() {
+        let info = info(r##"{"platforms": ["megachip8", "chip48"], "tickrate": 15, "keys": {"up": 5, "a": 16},
+            "colors": {"pixels": ["#000000", "ffcc00", "bad"]}}"##);
+        assert_eq!(info.title, "Test");
+        assert_eq!(info.platform, Platform::Chip8);
+        assert_eq!(info.quirks, Quirks::CHIP_48);
+        assert_eq!(info.cycles_per_frame, Some(15));
+        assert_eq!(info.keys.into_iter().collect::<Vec<_>>(), [(String::from("up"), 5)]);
+        assert_eq!(info.colors, [0x000000, 0xFFCC00]);
+    }
+
+    #[test]
+    fn skips_platforms_it_cant_run() {
+        let database = Database::parse(&alloc::format!(r#"[{{"roms": {{"{}": {{"platforms": ["megachip8"]}}}}}}]"#, SHA1)).unwrap();
+        assert!(database.is_empty());
+    }
+
+    #[test]
+    fn quirky_platforms() {
+        let quirks = |overrides: &str| {
+            info(&alloc::format!(r#"{{"platforms": ["originalChip8"], "quirkyPlatforms": {{"originalChip8": {}}}}}"#, overrides)).quirks
+        };
+        assert_eq!(quirks(r#"{"memoryIncrementByX": true}"#).memory_increment, MemoryIncrement::ByX);
+        assert_eq!(quirks(r#"{"memoryLeaveIUnchanged": true}"#).memory_increment, MemoryIncrement::Unchanged);
+        assert_eq!(quirks(r#"{"memoryLeaveIUnchanged": true, "memoryIncrementByX": true}"#).memory_increment, MemoryIncrement::Unchanged);
+        assert_eq!(quirks(r#"{"memoryLeaveIUnchanged": false, "memoryIncrementByX": true}"#).memory_increment, MemoryIncrement::ByX);
+        assert_eq!(quirks(r#"{"memoryIncrementByX": false}"#).memory_increment, MemoryIncrement::ByXPlusOne);
+        let quirks = quirks(r#"{"shift": true, "wrap": true, "jump": true, "vblank": false, "logic": false}"#);
+        assert!(quirks.shifting && !quirks.clipping && quirks.jumping && !quirks.display_wait && !quirks.vf_reset);
+    }
+
+    #[test]
+    fn chip48_can_be_told_to_go_back_to_x_plus_one() {
+        let info = info(r#"{"platforms": ["chip48"], "quirkyPlatforms": {"chip48": {"memoryIncrementByX": false}}}"#);
+        assert_eq!(info.quirks.memory_increment, MemoryIncrement::ByXPlusOne);
+    }
+}
@@ -0,0 +1,564 @@
+// debugger -- breakpoints, conditions, watchpoints and stepping, on top of run_cycle_once
+// frontends call run instead of run_cycle_once, and stop ticking the timers while it's paused
+
+use crate::chip8::Chip8;
+use crate::error::Chip8Error;
+use crate::watch::{parse_address, WatchHit};
+
+use alloc::collections::BTreeSet;
+use alloc::vec::Vec;
+use core::fmt;
+
+#[derive(Debug, Default)]
+pub struct Debugger {
+    breakpoints: BTreeSet<u16>,
+    conditions: Vec<(Condition, bool)>, // and whether each was true last time we looked
+    paused: bool,
+    leaving: bool,               // the next instruction runs even if it has a breakpoint, so we can carry on from one
+    run_until: Option<RunUntil>, // where step over / run to return stops
+    last_stop: Option<Stop>,
+}
+
+// why run paused
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Stop {
+    Breakpoint(u16),
+    Condition(usize),                       // the index into conditions()
+    Watchpoint { pc: u16, hit: WatchHit }, // the instruction at pc touched a watched byte
+    Finished,                               // a step over or run to return got where it was going
+}
+
+// a conditional breakpoint, e.g. "V3 == 0x10", "I >= 0xF00" or "0x2A4 if V0 != 0"
+// without an address it stops when the comparison becomes true, with one whenever that instruction is next and it's true
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Condition {
+    pub at: Option<u16>,
+    pub left: Operand,
+    pub comparison: Comparison,
+    pub right: Operand,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Operand {
+    V(u8),
+    I,
+    Pc,
+    Sp,
+    Dt,
+    St,
+    Memory(u16), // [0x300] is the byte at 0x300
+    Value(u16),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Comparison {
+    Equal,
+    NotEqual,
+    Less,
+    LessOrEqual,
+    Greater,
+    GreaterOrEqual,
+}
+
+#[derive(Debug, Clone, Copy)]
+enum RunUntil {
+    After { address: u16, depth: usize }, // back from the call we stepped over
+    Return { depth: usize },              // out of the current subroutine
+}
+
+impl Debugger {
+    pub fn new() -> Self {
+        Debugger::default()
+    }
+
+    pub fn is_paused(&self) -> bool {
+        self.paused
+    }
+
+    pub fn pause(&mut self) {
+        self.paused = true;
+        self.run_until = None;
+        self.last_stop = None;
+    }
+
+    pub fn resume(&mut self) {
+        self.paused = false;
+        self.leaving = true;
+    }
+
+    // true if it's now set
+    pub fn toggle_breakpoint(&mut self, address: u16) -> bool {
+        if self.breakpoints.remove(&address) {
+            return false;
+        }
+        self.breakpoints.insert(address)
+    }
+
+    pub fn add_breakpoint(&mut self, address: u16) {
+        self.breakpoints.insert(address);
+    }
+
+    pub fn remove_breakpoint(&mut self, address: u16) {
+        self.breakpoints.remove(&address);
+    }
+
+    pub fn has_breakpoint(&self, address: u16) -> bool {
+        self.breakpoints.contains(&address)
+    }
+
+    pub fn breakpoints(&self) -> impl Iterator<Item = u16> + '_ {
+        self.breakpoints.iter().copied()
+    }
+
+    pub fn add_condition(&mut self, condition: Condition) {
+        self.conditions.push((condition, false));
+    }
+
+    pub fn conditions(&self) -> impl Iterator<Item = &Condition> {
+        self.conditions.iter().map(|(condition, _)| condition)
+    }
+
+    pub fn clear_conditions(&mut self) {
+        self.conditions.clear();
+    }
+
+    // why we last paused, None if it was pause or step
+    pub fn last_stop(&self) -> Option<Stop> {
+        self.last_stop
+    }
+
+    // run up to `cycles` instructions, stopping early at a breakpoint, a condition, a watchpoint
+    // or the end of a step over / run to return
+    pub fn run(&mut self, chip8: &mut Chip8, cycles: usize) -> Result<(), Chip8Error> {
+        for _i in 0..cycles {
+            if self.paused {break}
+            let address = chip8.program_counter();
+            let leaving = core::mem::take(&mut self.leaving);
+            if self.breakpoints.contains(&address) && !leaving {
+                self.stop(Stop::Breakpoint(address));
+                break;
+            }
+            if let Some(index) = self.check_conditions(chip8, leaving) {
+                self.stop(Stop::Condition(index));
+                break;
+            }
+            chip8.run_cycle_once()?;
+            if let Some(hit) = chip8.take_watch_hit() {
+                self.stop(Stop::Watchpoint { pc: address, hit });
+            }
+            else if self.reached(chip8) {
+                self.stop(Stop::Finished);
+            }
+        }
+        Ok(())
+    }
+
+    fn stop(&mut self, reason: Stop) {
+        self.pause();
+        self.last_stop = Some(reason);
+    }
+
+    // the first condition that's just been met, if any
+    fn check_conditions(&mut self, chip8: &Chip8, leaving: bool) -> Option<usize> {
+        let mut met = None;
+        for (i, (condition, was_true)) in self.conditions.iter_mut().enumerate() {
+            let is_true = condition.holds(chip8);
+            let stop = match condition.at {
+                Some(address) => is_true && address == chip8.program_counter() && !leaving,
+                None => is_true && !*was_true,
+            };
+            *was_true = is_true;
+            if stop && met.is_none() {
+                met = Some(i);
+            }
+        }
+        met
+    }
+
+    // one instruction, then stay paused
+    pub fn step(&mut self, chip8: &mut Chip8) -> Result<(), Chip8Error> {
+        self.pause();
+        let address = chip8.program_counter();
+        chip8.run_cycle_once()?;
+        if let Some(hit) = chip8.take_watch_hit() {
+            self.last_stop = Some(Stop::Watchpoint { pc: address, hit });
+        }
+        Ok(())
+    }
+
+    // like step, but a 2NNN call runs until it returns
+    pub fn step_over(&mut self, chip8: &mut Chip8) -> Result<(), Chip8Error> {
+        let address = chip8.program_counter();
+        let opcode = chip8.memory().data[chip8.memory().wrap(address as usize)];
+        if opcode >> 4 != 0x2 {
+            return self.step(chip8);
+        }
+        self.run_until = Some(RunUntil::After { address: address.wrapping_add(2), depth: chip8.stack().len() });
+        self.paused = false;
+        self.leaving = true;
+        Ok(())
+    }
+
+    // keep going until the current subroutine returns, false if we're not in one
+    pub fn run_to_return(&mut self, chip8: &Chip8) -> bool {
+        let depth = chip8.stack().len();
+        if depth == 0 {
+            return false;
+        }
+        self.run_until = Some(RunUntil::Return { depth });
+        self.paused = false;
+        self.leaving = true;
+        true
+    }
+
+    fn reached(&self, chip8: &Chip8) -> bool {
+        match self.run_until {
+            Some(RunUntil::After { address, depth }) => chip8.program_counter() == address && chip8.stack().len() == depth,
+            Some(RunUntil::Return { depth }) => chip8.stack().len() < depth,
+            None => false,
+        }
+    }
+}
+
+impl Condition {
+    pub fn parse(text: &str) -> Result<Condition, Chip8Error> {
+        let (at, expression) = match text.split_once(" if ") {
+            Some((address, expression)) => {
+                let address = parse_address(address).ok_or(Chip8Error::InvalidExpression("expected an address before if"))?;
+                (Some(address as u16), expression)
+            },
+            None => (None, text),
+        };
+
+        // the two character comparisons first, so <= isn't read as <
+        let comparisons = [
+            ("==", Comparison::Equal),
+            ("!=", Comparison::NotEqual),
+            ("<=", Comparison::LessOrEqual),
+            (">=", Comparison::GreaterOrEqual),
+            ("<", Comparison::Less),
+            (">", Comparison::Greater),
+        ];
+        let (left, comparison, right) = comparisons
+            .iter()
+            .find_map(|&(symbol, comparison)| expression.split_once(symbol).map(|(left, right)| (left, comparison, right)))
+            .ok_or(Chip8Error::InvalidExpression("expected a comparison (==, !=, <, <=, > or >=)"))?;
+
+        Ok(Condition { at, left: Operand::parse(left)?, comparison, right: Operand::parse(right)? })
+    }
+
+    pub fn holds(&self, chip8: &Chip8) -> bool {
+        let (left, right) = (self.left.value(chip8), self.right.value(chip8));
+        match self.comparison {
+            Comparison::Equal => left == right,
+            Comparison::NotEqual => left != right,
+            Comparison::Less => left < right,
+            Comparison::LessOrEqual => left <= right,
+            Comparison::Greater => left > right,
+            Comparison::GreaterOrEqual => left >= right,
+        }
+    }
+}
+
+impl Operand {
+    fn parse(text: &str) -> Result<Operand, Chip8Error> {
+        let text = text.trim();
+        let upper = text.to_ascii_uppercase();
+        let operand = match upper.as_str() {
+            "I" => Operand::I,
+            "PC" => Operand::Pc,
+            "SP" => Operand::Sp,
+            "DT" => Operand::Dt,
+            "ST" => Operand::St,
+            _ => {
+                if let Some(register) = upper.strip_prefix('V').filter(|digit| digit.len() == 1) {
+                    let register = u8::from_str_radix(register, 16).map_err(|_| Chip8Error::InvalidExpression("registers are V0 to VF"))?;
+                    Operand::V(register)
+                } else if let Some(address) = text.strip_prefix('[').and_then(|rest| rest.strip_suffix(']')) {
+                    let address = parse_address(address).ok_or(Chip8Error::InvalidExpression("expected an address inside [ ]"))?;
+                    Operand::Memory(address as u16)
+                } else {
+                    let value = parse_address(text).ok_or(Chip8Error::InvalidExpression("expected a register, I, PC, SP, DT, ST, [address] or a number"))?;
+                    Operand::Value(value as u16)
+                }
+            },
+        };
+        Ok(operand)
+    }
+
+    fn value(&self, chip8: &Chip8) -> u16 {
+        match *self {
+            Operand::V(register) => chip8.registers()[register as usize] as u16,
+            Operand::I => chip8.index(),
+            Operand::Pc => chip8.program_counter(),
+            Operand::Sp => chip8.stack().len() as u16,
+            Operand::Dt => chip8.delay_timer.current_time as u16,
+            Operand::St => chip8.sound_timer.current_time as u16,
+            Operand::Memory(address) => chip8.memory().data[chip8.memory().wrap(address as usize)] as u16,
+            Operand::Value(value) => value,
+        }
+    }
+}
+
+// back in the same form parse takes
+impl fmt::Display for Condition {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if let Some(address) = self.at {
+            write!(f, "{:#05X} if ", address)?;
+        }
+        let comparison = match self.comparison {
+            Comparison::Equal => "==",
+            Comparison::NotEqual => "!=",
+            Comparison::Less => "<",
+            Comparison::LessOrEqual => "<=",
+            Comparison::Greater => ">",
+            Comparison::GreaterOrEqual => ">=",
+        };
+        write!(f, "{} {} {}", self.left, comparison, self.right)
+    }
+}
+
+impl fmt::Display for Operand {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Operand::V(register) => write!(f, "V{:X}", register),
+            Operand::I => write!(f, "I"),
+            Operand::Pc => write!(f, "PC"),
+            Operand::Sp => write!(f, "SP"),
+            Operand::Dt => write!(f, "DT"),
+            Operand::St => write!(f, "ST"),
+            Operand::Memory(address) => write!(f, "[{:#05X}]", address),
+            Operand::Value(value) => write!(f, "{:#04X}", value),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::platform::Platform;
+    use crate::quirks::Quirks;
+    use crate::watch::{Access, Watchpoint};
+    use alloc::string::ToString;
+
+    // 200: V0 := 1, 202: call 208, 204: V0 += 1, 206: jump 206
+    // 208: V1 := 5, 20A: V1 := 10, 20C: return
+    const PROGRAM: [u8; 14] = [0x60, 0x01, 0x22, 0x08, 0x70, 0x01, 0x12, 0x06, 0x61, 0x05, 0x61, 0x0A, 0x00, 0xEE];
+
+    fn machine() -> Chip8 {
+        let mut chip8 = Chip8::new(Platform::Chip8, Quirks::CHIP_48);
+        chip8.load_program(&PROGRAM).unwrap();
+        chip8
+    }
+
+    #[test]
+    fn breakpoints_stop_before_the_instruction() {
+        let (mut chip8, mut debugger) = (machine(), Debugger::new());
+        debugger.add_breakpoint(0x204);
+        debugger.run(&mut chip8, 100).unwrap();
+        assert!(debugger.is_paused());
+        assert_eq!(debugger.last_stop(), Some(Stop::Breakpoint(0x204)));
+        assert_eq!((chip8.program_counter(), chip8.registers()[0]), (0x204, 1));
+
+        // resuming runs the instruction with the breakpoint rather than stopping on it again
+        debugger.resume();
+        debugger.run(&mut chip8, 100).unwrap();
+        assert!(!debugger.is_paused());
+        assert_eq!((chip8.program_counter(), chip8.registers()[0]), (0x206, 2));
+    }
+
+    #[test]
+    fn breakpoint_list() {
+        let mut debugger = Debugger::new();
+        assert!(debugger.toggle_breakpoint(0x208));
+        debugger.add_breakpoint(0x200);
+        assert!(debugger.has_breakpoint(0x208));
+        assert_eq!(debugger.breakpoints().collect::<Vec<_>>(), [0x200, 0x208]);
+        assert!(!debugger.toggle_breakpoint(0x208));
+        debugger.remove_breakpoint(0x200);
+        assert_eq!(debugger.breakpoints().count(), 0);
+    }
+
+    #[test]
+    fn nothing_runs_while_paused() {
+        let (mut chip8, mut debugger) = (machine(), Debugger::new());
+        debugger.pause();
+        debugger.run(&mut chip8, 100).unwrap();
+        assert_eq!(chip8.program_counter(), 0x200);
+    }
+
+    #[test]
+    fn step_runs_one_instruction() {
+        let (mut chip8, mut debugger) = (machine(), Debugger::new());
+        debugger.step(&mut chip8).unwrap();
+        debugger.step(&mut chip8).unwrap();
+        assert!(debugger.is_paused());
+        assert_eq!(debugger.last_stop(), None);
+        // into the call
+        assert_eq!(chip8.program_counter(), 0x208);
+    }
+
+    #[test]
+    fn step_over_runs_the_whole_call() {
+        let (mut chip8, mut debugger) = (machine(), Debugger::new());
+        debugger.step(&mut chip8).unwrap();
+        debugger.step_over(&mut chip8).unwrap();
+        assert!(!debugger.is_paused());
+        debugger.run(&mut chip8, 100).unwrap();
+        assert_eq!(debugger.last_stop(), Some(Stop::Finished));
+        assert_eq!((chip8.program_counter(), chip8.registers()[1]), (0x204, 10));
+
+        // anything else is just a step
+        debugger.step_over(&mut chip8).unwrap();
+        assert!(debugger.is_paused());
+        assert_eq!(chip8.program_counter(), 0x206);
+    }
+
+    #[test]
+    fn step_over_still_stops_at_breakpoints_inside() {
+        let (mut chip8, mut debugger) = (machine(), Debugger::new());
+        debugger.add_breakpoint(0x20A);
+        debugger.step(&mut chip8).unwrap();
+        debugger.step_over(&mut chip8).unwrap();
+        debugger.run(&mut chip8, 100).unwrap();
+        assert_eq!(debugger.last_stop(), Some(Stop::Breakpoint(0x20A)));
+    }
+
+    #[test]
+    fn run_to_return() {
+        let (mut chip8, mut debugger) = (machine(), Debugger::new());
+        // not in a subroutine yet
+        assert!(!debugger.run_to_return(&chip8));
+        debugger.step(&mut chip8).unwrap();
+        debugger.step(&mut chip8).unwrap();
+        debugger.step(&mut chip8).unwrap();
+        assert!(debugger.run_to_return(&chip8));
+        debugger.run(&mut chip8, 100).unwrap();
+        assert_eq!(debugger.last_stop(), Some(Stop::Finished));
+        assert_eq!((chip8.program_counter(), chip8.registers()[1]), (0x204, 10));
+    }
+
+    #[test]
+    fn faults_come_back_from_run() {
+        let mut chip8 = Chip8::new(Platform::Chip8, Quirks::CHIP_48);
+        chip8.load_program(&[0x00, 0xEE]).unwrap();
+        assert_eq!(Debugger::new().run(&mut chip8, 10), Err(Chip8Error::StackUnderflow));
+    }
+
+    fn invalid(text: &str) -> &'static str {
+        match Condition::parse(text) {
+            Err(Chip8Error::InvalidExpression(reason)) => reason,
+            other => panic!("{}: expected an error, got {:?}", text, other),
+        }
+    }
+
+    #[test]
+    fn parses_conditions() {
+        let condition = |at, left, comparison, right| Condition { at, left, comparison, right };
+        assert_eq!(Condition::parse("V3 == 0x10"), Ok(condition(None, Operand::V(3), Comparison::Equal, Operand::Value(0x10))));
+        assert_eq!(Condition::parse("i>=0xF00"), Ok(condition(None, Operand::I, Comparison::GreaterOrEqual, Operand::Value(0xF00))));
+        assert_eq!(Condition::parse("0x2A4 if vf != 0"), Ok(condition(Some(0x2A4), Operand::V(0xF), Comparison::NotEqual, Operand::Value(0))));
+        assert_eq!(Condition::parse("[#300] < 20"), Ok(condition(None, Operand::Memory(0x300), Comparison::Less, Operand::Value(20))));
+        assert_eq!(Condition::parse("PC <= SP"), Ok(condition(None, Operand::Pc, Comparison::LessOrEqual, Operand::Sp)));
+        assert_eq!(Condition::parse("DT > ST"), Ok(condition(None, Operand::Dt, Comparison::Greater, Operand::St)));
+    }
+
+    #[test]
+    fn conditions_print_the_way_they_parse() {
+        for text in ["V3 == 0x10", "0x2A4 if [0x300] >= VA", "I < 0xF00", "DT > 0x00"] {
+            let condition = Condition::parse(text).unwrap();
+            assert_eq!(Condition::parse(&condition.to_string()), Ok(condition));
+        }
+        assert_eq!(Condition::parse("0x2A4 if V0 != 1").unwrap().to_string(), "0x2A4 if V0 != 0x01");
+    }
+
+    #[test]
+    fn bad_conditions() {
+        assert_eq!(invalid("V3 = 1"), "expected a comparison (==, !=, <, <=, > or >=)");
+        assert_eq!(invalid("VG == 1"), "registers are V0 to VF");
+        assert_eq!(invalid("[zz] == 1"), "expected an address inside [ ]");
+        assert_eq!(invalid("V10 == 1"), "expected a register, I, PC, SP, DT, ST, [address] or a number");
+        assert_eq!(invalid("V0 == "), "expected a register, I, PC, SP, DT, ST, [address] or a number");
+        assert_eq!(invalid("here if V0 == 1"), "expected an address before if");
+    }
+
+    // 200: V0 += 1, 202: jump 200
+    fn counter() -> Chip8 {
+        let mut chip8 = Chip8::new(Platform::Chip8, Quirks::CHIP_48);
+        chip8.load_program(&[0x70, 0x01, 0x12, 0x00]).unwrap();
+        chip8
+    }
+
+    #[test]
+    fn conditions_stop_when_they_become_true() {
+        let (mut chip8, mut debugger) = (counter(), Debugger::new());
+        debugger.add_condition(Condition::parse("V0 >= 2").unwrap());
+        debugger.run(&mut chip8, 100).unwrap();
+        assert_eq!(debugger.last_stop(), Some(Stop::Condition(0)));
+        assert_eq!((chip8.program_counter(), chip8.registers()[0]), (0x202, 2));
+
+        // it's still true, but that's not news
+        debugger.resume();
+        debugger.run(&mut chip8, 100).unwrap();
+        assert!(!debugger.is_paused());
+        assert_eq!(chip8.registers()[0], 52);
+
+        // once V0 wraps round to 0 and comes back up to 2 it's true again
+        debugger.run(&mut chip8, 500).unwrap();
+        assert_eq!(debugger.last_stop(), Some(Stop::Condition(0)));
+        assert_eq!(chip8.registers()[0], 2);
+    }
+
+    #[test]
+    fn conditions_at_an_address_stop_every_time_they_hold_there() {
+        let (mut chip8, mut debugger) = (counter(), Debugger::new());
+        debugger.add_condition(Condition::parse("0x202 if V0 >= 2").unwrap());
+        debugger.run(&mut chip8, 100).unwrap();
+        assert_eq!(debugger.last_stop(), Some(Stop::Condition(0)));
+        assert_eq!((chip8.program_counter(), chip8.registers()[0]), (0x202, 2));
+        debugger.resume();
+        debugger.run(&mut chip8, 100).unwrap();
+        assert_eq!((chip8.program_counter(), chip8.registers()[0]), (0x202, 3));
+        assert_eq!(debugger.conditions().count(), 1);
+        debugger.clear_conditions();
+        assert_eq!(debugger.conditions().count(), 0);
+    }
+
+    // 200: I := 300, 202: V0 := 42, 204: save V0, 206: load V0, 208: jump 208
+    fn saver() -> Chip8 {
+        let mut chip8 = Chip8::new(Platform::Chip8, Quirks::CHIP_48);
+        chip8.load_program(&[0xA3, 0x00, 0x60, 0x42, 0xF0, 0x55, 0xF0, 0x65, 0x12, 0x08]).unwrap();
+        chip8
+    }
+
+    #[test]
+    fn watchpoints_stop_after_the_access() {
+        let (mut chip8, mut debugger) = (saver(), Debugger::new());
+        chip8.add_watchpoint(Watchpoint::parse("0x300-0x30F", false, true).unwrap());
+        debugger.run(&mut chip8, 100).unwrap();
+        let hit = WatchHit { address: 0x300, access: Access::Write, value: 0x42 };
+        assert_eq!(debugger.last_stop(), Some(Stop::Watchpoint { pc: 0x204, hit }));
+        assert_eq!(chip8.program_counter(), 0x206);
+        // the load only reads, so it doesn't stop
+        debugger.resume();
+        debugger.run(&mut chip8, 100).unwrap();
+        assert!(!debugger.is_paused());
+
+        let (mut chip8, mut debugger) = (saver(), Debugger::new());
+        chip8.add_watchpoint(Watchpoint::parse("0x300", true, false).unwrap());
+        debugger.run(&mut chip8, 100).unwrap();
+        let hit = WatchHit { address: 0x300, access: Access::Read, value: 0x42 };
+        assert_eq!(debugger.last_stop(), Some(Stop::Watchpoint { pc: 0x206, hit }));
+    }
+
+    #[test]
+    fn stepping_reports_watchpoints() {
+        let (mut chip8, mut debugger) = (saver(), Debugger::new());
+        chip8.add_watchpoint(Watchpoint::parse("0x300", true, true).unwrap());
+        debugger.step(&mut chip8).unwrap();
+        debugger.step(&mut chip8).unwrap();
+        assert_eq!(debugger.last_stop(), None);
+        debugger.step(&mut chip8).unwrap();
+        assert!(matches!(debugger.last_stop(), Some(Stop::Watchpoint { pc: 0x204, .. })));
+    }
+}
@@ -0,0 +1,457 @@
+// disassembly -- turns machine code back into source, in Octo's syntax or Cowgod's mnemonics
+// (Cowgod's are the ones from his CHIP-8 technical reference, plus the SUPER-CHIP and XO-CHIP additions)
+//
+// a whole ROM is traced from the load address, following jumps, calls and both sides of every skip,
+// so only bytes that can actually run are treated as code. Everything else comes out as data, drawn as a
+// sprite bitmap in a comment, and jump, call and `i :=` targets get labels
+// anything that isn't an instruction on the platform is data too, the same as the CPU refusing it
+
+use crate::memory::Memory;
+use crate::opcode::Opcode;
+use crate::platform::Platform;
+
+use alloc::collections::BTreeMap;
+use alloc::format;
+use alloc::string::String;
+use alloc::vec;
+use alloc::vec::Vec;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Syntax {
+    Octo,   // v0 := 0x12, sprite v1 v2 5
+    Cowgod, // ld v0, 0x12, drw v1, v2, 5
+}
+
+impl Syntax {
+    pub fn from_name(name: &str) -> Option<Syntax> {
+        match name.to_ascii_lowercase().as_str() {
+            "octo" => Some(Syntax::Octo),
+            "cowgod" => Some(Syntax::Cowgod),
+            _ => None,
+        }
+    }
+
+    fn comment(&self) -> &'static str {
+        match self {
+            Syntax::Octo => "#",
+            Syntax::Cowgod => ";",
+        }
+    }
+}
+
+pub struct Instruction {
+    pub opcode: u16,
+    pub len: usize, // 2 bytes, or 4 for XO-CHIP's F000 NNNN
+    pub text: String,
+}
+
+// the one instruction at `address`, with addresses as plain numbers
+pub fn disassemble(memory: &Memory, address: usize, platform: Platform, syntax: Syntax) -> Instruction {
+    // enough bytes for the longest instruction, wrapping round the end of memory like the CPU does
+    let bytes: [u8; 4] = core::array::from_fn(|i| memory.data[memory.wrap(address + i)]);
+    let (opcode, long, len) = decode(&bytes, 0, platform).unwrap();
+    match text(&opcode, long, platform, syntax, &hex_address) {
+        Some(text) => Instruction { opcode: opcode.opcode, len, text },
+        None => Instruction { opcode: opcode.opcode, len: 2, text: raw_bytes(&bytes[..2], syntax) },
+    }
+}
+
+// the whole ROM as source, as if it was loaded at `load_address`
+pub fn disassemble_rom(rom: &[u8], load_address: usize, platform: Platform, syntax: Syntax) -> String {
+    let (code, labels) = trace(rom, load_address, platform);
+
+    // labels can only go where a line starts, a target in the middle of an instruction stays a number
+    let name = |address: u16| -> String {
+        let offset = (address as usize).wrapping_sub(load_address);
+        match labels.get(&address) {
+            Some(label) if offset < rom.len() && code[offset] != Byte::Operand => label.name(address),
+            _ => hex_address(address),
+        }
+    };
+
+    let comment = syntax.comment();
+    let mut out = format!("{} disassembled from a {} byte {} ROM\n", comment, rom.len(), platform.name());
+    let mut offset = 0;
+    while offset < rom.len() {
+        let address = (load_address + offset) as u16;
+        if let Some(label) = labels.get(&address) {
+            match syntax {
+                Syntax::Octo => out += &format!("\n: {}\n", label.name(address)),
+                Syntax::Cowgod => out += &format!("\n{}:\n", label.name(address)),
+            }
+        }
+
+        match code[offset] {
+            Byte::Code(len) => {
+                // trace only marks what decodes, so this can't fail
+                let (opcode, long, _) = decode(rom, offset, platform).unwrap();
+                let text = text(&opcode, long, platform, syntax, &name).unwrap_or_default();
+                out += &format!("    {:<28}{} {:#05x}  {}\n", text, comment, address, hex_bytes(&rom[offset..offset + len]));
+                offset += len;
+            },
+            _ => {
+                let byte = rom[offset];
+                let bitmap: String = (0..8).map(|bit| if byte & (0x80 >> bit) != 0 {'#'} else {'.'}).collect();
+                out += &format!("    {:<28}{} {:#05x}  {}\n", raw_bytes(&[byte], syntax), comment, address, bitmap);
+                offset += 1;
+            },
+        }
+    }
+    out
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Byte {
+    Data,
+    Code(usize), // the first byte of an instruction this long
+    Operand,     // the rest of one
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+enum Label {
+    Data,       // loaded into I
+    Jump,       // jumped to
+    Subroutine, // called
+    Main,       // the load address
+}
+
+impl Label {
+    fn name(&self, address: u16) -> String {
+        match self {
+            Label::Main => String::from("main"),
+            Label::Subroutine => format!("sub_{:03x}", address),
+            Label::Jump => format!("label_{:03x}", address),
+            Label::Data => format!("data_{:03x}", address),
+        }
+    }
+}
+
+// where control can go after an instruction
+enum Flow {
+    Next,
+    Skip,        // the next instruction or the one after it
+    Jump(u16),   // and nothing after it
+    Call(u16),   // and back to the next instruction
+    Stop,        // return or exit
+}
+
+// recursive descent from the load address, marking which bytes are code and which addresses need labels
+fn trace(rom: &[u8], load_address: usize, platform: Platform) -> (Vec<Byte>, BTreeMap<u16, Label>) {
+    let mut code = vec![Byte::Data; rom.len()];
+    let mut labels = BTreeMap::new();
+    // an address can be more than one kind of target, the name goes by the most important
+    fn label(labels: &mut BTreeMap<u16, Label>, address: u16, kind: Label) {
+        let entry = labels.entry(address).or_insert(kind);
+        *entry = (*entry).max(kind);
+    }
+    label(&mut labels, load_address as u16, Label::Main);
+
+    let mut pending = vec![load_address];
+    while let Some(address) = pending.pop() {
+        let Some(offset) = address.checked_sub(load_address) else { continue };
+        if offset >= rom.len() || code[offset] != Byte::Data {continue}
+        let Some((opcode, long, len)) = decode(rom, offset, platform) else { continue };
+        if text(&opcode, long, platform, Syntax::Octo, &hex_address).is_none() {continue}
+        // don't start an instruction on top of one we've already found
+        if code[offset..offset + len].iter().any(|&byte| byte != Byte::Data) {continue}
+
+        code[offset] = Byte::Code(len);
+        code[offset + 1..offset + len].fill(Byte::Operand);
+
+        let next = address + len;
+        match flow(&opcode, platform) {
+            Flow::Next => pending.push(next),
+            Flow::Skip => {
+                // a skip jumps the whole of a 4 byte F000 NNNN on XO-CHIP
+                let skipped = match decode(rom, offset + len, platform) {
+                    Some((_, _, skipped)) => skipped,
+                    None => 2,
+                };
+                pending.push(next);
+                pending.push(next + skipped);
+            },
+            Flow::Jump(target) => {
+                label(&mut labels, target, Label::Jump);
+                pending.push(target as usize);
+            },
+            Flow::Call(target) => {
+                label(&mut labels, target, Label::Subroutine);
+                pending.push(target as usize);
+                pending.push(next);
+            },
+            Flow::Stop => {},
+        }
+
+        // whatever I is pointed at is (most likely) data
+        if opcode.a == 0xA {
+            label(&mut labels, opcode.nnn, Label::Data);
+        }
+        if let Some(target) = long {
+            label(&mut labels, target, Label::Data);
+        }
+    }
+
+    // a label outside the ROM has nowhere to go
+    labels.retain(|&address, _| (address as usize).wrapping_sub(load_address) < rom.len());
+    (code, labels)
+}
+
+// the instruction at `offset`, its F000 NNNN address if it's that, and its length
+fn decode(bytes: &[u8], offset: usize, platform: Platform) -> Option<(Opcode, Option<u16>, usize)> {
+    let opcode = Opcode::new(*bytes.get(offset)?, *bytes.get(offset + 1)?);
+    if platform == Platform::XoChip && opcode.opcode == 0xF000 {
+        let long = u16::from_be_bytes([*bytes.get(offset + 2)?, *bytes.get(offset + 3)?]);
+        return Some((opcode, Some(long), 4));
+    }
+    Some((opcode, None, 2))
+}
+
+fn flow(opcode: &Opcode, platform: Platform) -> Flow {
+    match (opcode.a, opcode.nn) {
+        (0x0, 0xEE) => Flow::Stop,
+        (0x0, 0xFD) if platform != Platform::Chip8 => Flow::Stop,
+        (0x1, _) => Flow::Jump(opcode.nnn),
+        (0x2, _) => Flow::Call(opcode.nnn),
+        (0x3 | 0x4 | 0x5 | 0x9 | 0xE, _) => {
+            // 5XY2 and 5XY3 are XO-CHIP's save / load range, not skips
+            if opcode.a == 0x5 && opcode.n != 0 {Flow::Next} else {Flow::Skip}
+        },
+        // BNNN jumps through a table, we can only guess the first entry
+        (0xB, _) => Flow::Jump(opcode.nnn),
+        _ => Flow::Next,
+    }
+}
+
+// the source for one instruction, None if it isn't one on this platform
+// `name` turns an address into a label (or a number)
+fn text(opcode: &Opcode, long: Option<u16>, platform: Platform, syntax: Syntax, name: &dyn Fn(u16) -> String) -> Option<String> {
+    let schip = platform != Platform::Chip8;
+    let xo = platform == Platform::XoChip;
+    let octo = syntax == Syntax::Octo;
+
+    let (x, y, n, nn, nnn) = (opcode.x, opcode.y, opcode.n, opcode.nn, opcode.nnn);
+    let pick = |octo_text: String, cowgod_text: String| if octo {octo_text} else {cowgod_text};
+
+    let text = match opcode.a {
+        0x0 => match nn {
+            0xE0 => pick(String::from("clear"), String::from("cls")),
+            0xEE => pick(String::from("return"), String::from("ret")),
+            0xC0..=0xCF if schip => pick(format!("scroll-down {}", n), format!("scd {}", n)),
+            0xD0..=0xDF if xo => pick(format!("scroll-up {}", n), format!("scu {}", n)),
+            0xFB if schip => pick(String::from("scroll-right"), String::from("scr")),
+            0xFC if schip => pick(String::from("scroll-left"), String::from("scl")),
+            0xFD if schip => String::from("exit"),
+            0xFE if schip => pick(String::from("lores"), String::from("low")),
+            0xFF if schip => pick(String::from("hires"), String::from("high")),
+            _ => return None,
+        },
+        0x1 => pick(format!("jump {}", name(nnn)), format!("jp {}", name(nnn))),
+        // in Octo calling a subroutine is just writing its name
+        0x2 => pick(name(nnn), format!("call {}", name(nnn))),
+        // Octo's if ... then runs the next instruction when the condition holds, so the skips read backwards
+        0x3 => pick(format!("if v{:x} != {:#04x} then", x, nn), format!("se v{:x}, {:#04x}", x, nn)),
+        0x4 => pick(format!("if v{:x} == {:#04x} then", x, nn), format!("sne v{:x}, {:#04x}", x, nn)),
+        0x5 => match n {
+            0x0 => pick(format!("if v{:x} != v{:x} then", x, y), format!("se v{:x}, v{:x}", x, y)),
+            0x2 if xo => pick(format!("save v{:x} - v{:x}", x, y), format!("ld [i], v{:x}-v{:x}", x, y)),
+            0x3 if xo => pick(format!("load v{:x} - v{:x}", x, y), format!("ld v{:x}-v{:x}, [i]", x, y)),
+            _ => return None,
+        },
+        0x6 => pick(format!("v{:x} := {:#04x}", x, nn), format!("ld v{:x}, {:#04x}", x, nn)),
+        0x7 => pick(format!("v{:x} += {:#04x}", x, nn), format!("add v{:x}, {:#04x}", x, nn)),
+        0x8 => {
+            let (octo_operator, cowgod_operation) = match n {
+                0x0 => (":=", "ld"),
+                0x1 => ("|=", "or"),
+                0x2 => ("&=", "and"),
+                0x3 => ("^=", "xor"),
+                0x4 => ("+=", "add"),
+                0x5 => ("-=", "sub"),
+                0x6 => (">>=", "shr"),
+                0x7 => ("=-", "subn"),
+                0xE => ("<<=", "shl"),
+                _ => return None,
+            };
+            pick(format!("v{:x} {} v{:x}", x, octo_operator, y), format!("{} v{:x}, v{:x}", cowgod_operation, x, y))
+        },
+        0x9 if n == 0 => pick(format!("if v{:x} == v{:x} then", x, y), format!("sne v{:x}, v{:x}", x, y)),
+        0xA => pick(format!("i := {}", name(nnn)), format!("ld i, {}", name(nnn))),
+        0xB => pick(format!("jump0 {}", name(nnn)), format!("jp v0, {}", name(nnn))),
+        0xC => pick(format!("v{:x} := random {:#04x}", x, nn), format!("rnd v{:x}, {:#04x}", x, nn)),
+        0xD => pick(format!("sprite v{:x} v{:x} {}", x, y, n), format!("drw v{:x}, v{:x}, {}", x, y, n)),
+        0xE => match nn {
+            0x9E => pick(format!("if v{:x} -key then", x), format!("skp v{:x}", x)),
+            0xA1 => pick(format!("if v{:x} key then", x), format!("sknp v{:x}", x)),
+            _ => return None,
+        },
+        0xF => match nn {
+            0x00 if xo && x == 0 => {
+                let target = name(long?);
+                pick(format!("i := long {}", target), format!("ld i, {}", target))
+            },
+            0x01 if xo => pick(format!("plane {}", x), format!("plane {}", x)),
+            0x02 if xo && x == 0 => String::from("audio"),
+            0x07 => pick(format!("v{:x} := delay", x), format!("ld v{:x}, dt", x)),
+            0x0A => pick(format!("v{:x} := key", x), format!("ld v{:x}, k", x)),
+            0x15 => pick(format!("delay := v{:x}", x), format!("ld dt, v{:x}", x)),
+            0x18 => pick(format!("buzzer := v{:x}", x), format!("ld st, v{:x}", x)),
+            0x1E => pick(format!("i += v{:x}", x), format!("add i, v{:x}", x)),
+            0x29 => pick(format!("i := hex v{:x}", x), format!("ld f, v{:x}", x)),
+            0x30 if schip => pick(format!("i := bighex v{:x}", x), format!("ld hf, v{:x}", x)),
+            0x33 => pick(format!("bcd v{:x}", x), format!("ld b, v{:x}", x)),
+            0x3A if xo => pick(format!("pitch := v{:x}", x), format!("pitch v{:x}", x)),
+            0x55 => pick(format!("save v{:x}", x), format!("ld [i], v{:x}", x)),
+            0x65 => pick(format!("load v{:x}", x), format!("ld v{:x}, [i]", x)),
+            0x75 if schip => pick(format!("saveflags v{:x}", x), format!("ld r, v{:x}", x)),
+            0x85 if schip => pick(format!("loadflags v{:x}", x), format!("ld v{:x}, r", x)),
+            _ => return None,
+        },
+        _ => return None,
+    };
+    Some(text)
+}
+
+fn hex_address(address: u16) -> String {
+    format!("{:#05x}", address)
+}
+
+fn hex_bytes(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+// bytes that aren't code, as the syntax writes them
+fn raw_bytes(bytes: &[u8], syntax: Syntax) -> String {
+    let list: Vec<String> = bytes.iter().map(|byte| format!("{:#04x}", byte)).collect();
+    match syntax {
+        Syntax::Octo => list.join(" "),
+        Syntax::Cowgod => format!("db {}", list.join(", ")),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::asm::assemble;
+
+    // i := the sprite, call the subroutine, then loop on a skip; the subroutine draws and returns
+    const ROM: [u8; 17] = [0xA2, 0x0C, 0x22, 0x08, 0x30, 0x00, 0x12, 0x04, 0xD0, 0x15, 0x00, 0xEE, 0xF0, 0x90, 0xF0, 0x90, 0xF0];
+
+    #[test]
+    fn octo() {
+        assert_eq!(disassemble_rom(&ROM, 0x200, Platform::Chip8, Syntax::Octo), "\
+# disassembled from a 17 byte chip8 ROM
+
+: main
+    i := data_20c               # 0x200  a20c
+    sub_208                     # 0x202  2208
+
+: label_204
+    if v0 != 0x00 then          # 0x204  3000
+    jump label_204              # 0x206  1204
+
+: sub_208
+    sprite v0 v1 5              # 0x208  d015
+    return                      # 0x20a  00ee
+
+: data_20c
+    0xf0                        # 0x20c  ####....
+    0x90                        # 0x20d  #..#....
+    0xf0                        # 0x20e  ####....
+    0x90                        # 0x20f  #..#....
+    0xf0                        # 0x210  ####....
+");
+    }
+
+    #[test]
+    fn cowgod() {
+        assert_eq!(disassemble_rom(&ROM, 0x200, Platform::Chip8, Syntax::Cowgod), "\
+; disassembled from a 17 byte chip8 ROM
+
+main:
+    ld i, data_20c              ; 0x200  a20c
+    call sub_208                ; 0x202  2208
+
+label_204:
+    se v0, 0x00                 ; 0x204  3000
+    jp label_204                ; 0x206  1204
+
+sub_208:
+    drw v0, v1, 5               ; 0x208  d015
+    ret                         ; 0x20a  00ee
+
+data_20c:
+    db 0xf0                     ; 0x20c  ####....
+    db 0x90                     ; 0x20d  #..#....
+    db 0xf0                     ; 0x20e  ####....
+    db 0x90                     ; 0x20f  #..#....
+    db 0xf0                     ; 0x210  ####....
+");
+    }
+
+    #[test]
+    fn octo_output_assembles_back_to_the_rom() {
+        let source = disassemble_rom(&ROM, 0x200, Platform::Chip8, Syntax::Octo);
+        assert_eq!(assemble(&source).unwrap().rom, ROM);
+    }
+
+    // the lines that are code, without the comments
+    fn code(rom: &[u8], platform: Platform) -> Vec<String> {
+        disassemble_rom(rom, 0x200, platform, Syntax::Octo)
+            .lines()
+            .filter(|line| line.starts_with("    ") && !line.trim_start().starts_with("0x"))
+            .map(|line| String::from(line.split('#').next().unwrap().trim()))
+            .collect()
+    }
+
+    #[test]
+    fn unreachable_bytes_are_data() {
+        // a jump over 6001, which would be an instruction if anything ran it
+        let rom = [0x12, 0x04, 0x60, 0x01, 0x12, 0x04];
+        assert_eq!(code(&rom, Platform::Chip8), ["jump label_204", "jump label_204"]);
+        assert!(disassemble_rom(&rom, 0x200, Platform::Chip8, Syntax::Octo).contains("    0x60                        # 0x202  .##.....\n"));
+    }
+
+    #[test]
+    fn instructions_the_platform_lacks_are_data() {
+        // 00FF is hires on SUPER-CHIP, but plain CHIP-8 can't run it, so nothing after it is traced either
+        let rom = [0x00, 0xFF, 0x00, 0xE0];
+        assert!(code(&rom, Platform::Chip8).is_empty());
+        assert_eq!(code(&rom, Platform::SuperChip), ["hires", "clear"]);
+    }
+
+    #[test]
+    fn both_sides_of_a_skip_are_code() {
+        // 3000 then 00FD, so 0x204 is only reached by the skip
+        let rom = [0x30, 0x00, 0x00, 0xFD, 0x60, 0x01, 0x00, 0xFD];
+        assert_eq!(code(&rom, Platform::SuperChip), ["if v0 != 0x00 then", "exit", "v0 := 0x01", "exit"]);
+    }
+
+    #[test]
+    fn skips_step_over_all_of_long_index() {
+        // on XO-CHIP 3000 skips the whole of F000 0300
+        let rom = [0x30, 0x00, 0xF0, 0x00, 0x03, 0x00, 0x00, 0xFD];
+        assert_eq!(code(&rom, Platform::XoChip), ["if v0 != 0x00 then", "i := long 0x300", "exit"]);
+    }
+
+    #[test]
+    fn targets_inside_an_instruction_stay_numbers() {
+        // jump 0x203 lands in the middle of the jump at 0x202
+        let rom = [0x12, 0x02, 0x12, 0x03];
+        assert_eq!(code(&rom, Platform::Chip8), ["jump label_202", "jump 0x203"]);
+    }
+
+    #[test]
+    fn one_instruction() {
+        let mut memory = Memory::new(0x1000, 0x200);
+        memory.load_program(&[0xF0, 0x00, 0x12, 0x34, 0xA3, 0x00]).unwrap();
+        let instruction = disassemble(&memory, 0x200, Platform::XoChip, Syntax::Octo);
+        assert_eq!((instruction.opcode, instruction.len, instruction.text.as_str()), (0xF000, 4, "i := long 0x1234"));
+        let instruction = disassemble(&memory, 0x204, Platform::Chip8, Syntax::Cowgod);
+        assert_eq!((instruction.len, instruction.text.as_str()), (2, "ld i, 0x300"));
+        // not an instruction on CHIP-8, so it's shown as bytes
+        let instruction = disassemble(&memory, 0x200, Platform::Chip8, Syntax::Cowgod);
+        assert_eq!((instruction.len, instruction.text.as_str()), (2, "db 0xf0, 0x00"));
+        assert_eq!(Syntax::from_name("Cowgod"), Some(Syntax::Cowgod));
+        assert_eq!(Syntax::from_name("intel"), None);
+    }
+}
@@ -0,0 +1,142 @@
+// the screen -- 64x32 normally, 128x64 when SUPER-CHIP switches to hi-res
+// XO-CHIP has two bitplanes, so each pixel is a 2-bit colour: bit 0 is plane 1, bit 1 is plane 2
+
+use alloc::string::String;
+use alloc::vec;
+use alloc::vec::Vec;
+
+pub const LORES_WIDTH: usize = 64;
+pub const LORES_HEIGHT: usize = 32;
+pub const HIRES_WIDTH: usize = 128;
+pub const HIRES_HEIGHT: usize = 64;
+
+pub const ASCII: [char; 4] = ['.', '#', '+', '@']; // how to_ascii shows each colour: off, plane 1, plane 2, both
+
+#[derive(Debug)]
+pub struct Display {
+    hires: bool,
+    pixels: Vec<u8>, // row by row, width() * height() of them, one bit per plane
+}
+
+impl Display {
+    pub fn new() -> Self {
+        Display {
+            hires: false,
+            pixels: vec![0; LORES_WIDTH * LORES_HEIGHT],
+        }
+    }
+
+    pub fn width(&self) -> usize {
+        if self.hires {HIRES_WIDTH} else {LORES_WIDTH}
+    }
+
+    pub fn height(&self) -> usize {
+        if self.hires {HIRES_HEIGHT} else {LORES_HEIGHT}
+    }
+
+    pub fn is_hires(&self) -> bool {
+        self.hires
+    }
+
+    // switching resolution clears the screen (every plane), like SUPER-CHIP 1.1 does
+    pub fn set_hires(&mut self, hires: bool) {
+        self.hires = hires;
+        self.pixels = vec![0; self.width() * self.height()];
+    }
+
+    // clear the given planes (a bitmask, 1 for classic CHIP-8)
+    pub fn clear(&mut self, planes: u8) {
+        for pixel in self.pixels.iter_mut() {
+            *pixel &= !planes;
+        }
+    }
+
+    // put back a screen saved by a save state
+    pub(crate) fn restore(&mut self, hires: bool, pixels: Vec<u8>) {
+        self.hires = hires;
+        self.pixels = pixels;
+    }
+
+    // the colour of each pixel, 0-3
+    pub fn pixels(&self) -> &[u8] {
+        &self.pixels
+    }
+
+    // the screen as text, one line per row (what chip8-headless and chip8 --headless print, and the test goldens)
+    pub fn to_ascii(&self) -> String {
+        let mut text = String::with_capacity(self.pixels.len() + self.height());
+        for row in self.pixels.chunks_exact(self.width()) {
+            text.extend(row.iter().map(|&pixel| ASCII[pixel as usize & 3]));
+            text.push('\n');
+        }
+        text
+    }
+
+    // xor a pixel on in one plane, returns true if it was already on (a collision)
+    pub fn flip(&mut self, x: usize, y: usize, plane: u8) -> bool {
+        let i = y * self.width() + x;
+        let was_on = self.pixels[i] & plane != 0;
+        self.pixels[i] ^= plane;
+        was_on
+    }
+
+    // 00CN - move the given planes down n rows, blank rows come in at the top
+    pub fn scroll_down(&mut self, n: usize, planes: u8) {
+        let width = self.width();
+        let n = n.min(self.height());
+        self.shift_planes(planes, |pixels| {
+            pixels.rotate_right(n * width);
+            pixels[..n * width].fill(0);
+        });
+    }
+
+    // 00DN (XO-CHIP) - move the given planes up n rows
+    pub fn scroll_up(&mut self, n: usize, planes: u8) {
+        let width = self.width();
+        let len = self.pixels.len();
+        let n = n.min(self.height());
+        self.shift_planes(planes, |pixels| {
+            pixels.rotate_left(n * width);
+            pixels[len - n * width..].fill(0);
+        });
+    }
+
+    // 00FB - move the given planes right n pixels
+    pub fn scroll_right(&mut self, n: usize, planes: u8) {
+        let width = self.width();
+        let n = n.min(width);
+        self.shift_planes(planes, |pixels| {
+            for row in pixels.chunks_mut(width) {
+                row.rotate_right(n);
+                row[..n].fill(0);
+            }
+        });
+    }
+
+    // 00FC - move the given planes left n pixels
+    pub fn scroll_left(&mut self, n: usize, planes: u8) {
+        let width = self.width();
+        let n = n.min(width);
+        self.shift_planes(planes, |pixels| {
+            for row in pixels.chunks_mut(width) {
+                row.rotate_left(n);
+                row[width - n..].fill(0);
+            }
+        });
+    }
+
+    // scroll a copy of the selected planes, then merge it back over the planes that stay put
+    fn shift_planes(&mut self, planes: u8, shift: impl FnOnce(&mut [u8])) {
+        let mut moved: Vec<u8> = self.pixels.iter().map(|pixel| pixel & planes).collect();
+        shift(&mut moved);
+        for (pixel, moved) in self.pixels.iter_mut().zip(moved) {
+            *pixel = (*pixel & !planes) | moved;
+        }
+    }
+}
+
+impl Default for Display {
+    fn default() -> Self {
+        Self::new()
+    }
+}
@@ -0,0 +1,57 @@
+// everything that can go wrong while loading or running a program
+//
+// there's no memory out of bounds error: addresses wrap round the end of memory like the real address bus
+// (I + X past 0xFFF reads 0x000 on a 4K machine), so every access lands somewhere, and ROMs that rely on
+// that still run. it also means no instruction can fault half way through writing memory or drawing
+
+use core::fmt;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Chip8Error {
+    UnknownOpcode(u16),       // not an instruction on this platform (or a 0NNN machine code routine)
+    StackOverflow,            // 2NNN with all 16 stack levels already used
+    StackUnderflow,           // 00EE with nothing on the stack to return to
+    RomTooLarge { rom_size: usize, max_size: usize }, // the ROM doesn't fit between the load address and the end of memory
+    InvalidSaveState(&'static str), // a save state that can't be loaded, and why
+    InvalidMovie { line: usize, reason: &'static str }, // a movie file that can't be read, where and why
+    InvalidExpression(&'static str), // a debugger condition or watch range that can't be parsed, and why
+    InvalidDatabase(&'static str),   // a ROM database that isn't the JSON we expect, and why
+    InvalidMemory(&'static str),     // a memory size or load address that can't work, and why
+}
+
+impl fmt::Display for Chip8Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Chip8Error::UnknownOpcode(opcode) => write!(f, "unknown opcode {:04X}", opcode),
+            Chip8Error::StackOverflow => write!(f, "stack overflow (more than 16 nested calls)"),
+            Chip8Error::StackUnderflow => write!(f, "stack underflow (return with nothing on the stack)"),
+            Chip8Error::RomTooLarge { rom_size, max_size } => write!(f, "ROM too large ({} bytes, at most {} fit). Please check that it is a ROM for this platform", rom_size, max_size),
+            Chip8Error::InvalidSaveState(reason) => write!(f, "can't load save state: {}", reason),
+            Chip8Error::InvalidMovie { line, reason } => write!(f, "can't read movie, line {}: {}", line, reason),
+            Chip8Error::InvalidExpression(reason) => write!(f, "can't parse expression: {}", reason),
+            Chip8Error::InvalidDatabase(reason) => write!(f, "can't read the ROM database: {}", reason),
+            Chip8Error::InvalidMemory(reason) => write!(f, "bad memory layout: {}", reason),
+        }
+    }
+}
+
+// what run_cycle_once does when an instruction faults
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ErrorPolicy {
+    #[default]
+    Halt,   // stop on the faulting instruction and keep returning the error, so frontends can show it
+    Ignore, // skip the rest of the faulting instruction and carry on
+    Wrap,   // the stack wraps round its 16 levels and unknown opcodes are skipped (memory addresses always wrap)
+}
+
+impl ErrorPolicy {
+    // e.g. from a command line flag
+    pub fn from_name(name: &str) -> Option<ErrorPolicy> {
+        match name.to_ascii_lowercase().as_str() {
+            "halt" => Some(ErrorPolicy::Halt),
+            "ignore" => Some(ErrorPolicy::Ignore),
+            "wrap" => Some(ErrorPolicy::Wrap),
+            _ => None,
+        }
+    }
+}
@@ -0,0 +1,491 @@
+// gdb remote serial protocol -- lets gdb (or any IDE that speaks RSP) drive a Chip8 through the Debugger
+// this only turns packets into replies, the frontend owns the socket: it passes in what arrived with receive,
+// sends back what that returns, and calls poll every frame to report when a continue has stopped
+//
+// there's no CHIP-8 in gdb, so the registers are described in target.xml (qXfer:features:read):
+// v0-vf (8 bits each), i (16), pc (16), sp (8, the stack levels in use), sent little endian in that order
+
+use crate::chip8::Chip8;
+use crate::debugger::{Debugger, Stop};
+use crate::watch::{Access, Watchpoint};
+
+use alloc::format;
+use alloc::string::String;
+use alloc::vec::Vec;
+
+const TARGET_XML: &str = concat!(
+    r#"<?xml version="1.0"?><!DOCTYPE target SYSTEM "gdb-target.dtd"><target version="1.0"><feature name="org.chip8.core">"#,
+    r#"<reg name="v0" bitsize="8" type="uint8"/><reg name="v1" bitsize="8" type="uint8"/>"#,
+    r#"<reg name="v2" bitsize="8" type="uint8"/><reg name="v3" bitsize="8" type="uint8"/>"#,
+    r#"<reg name="v4" bitsize="8" type="uint8"/><reg name="v5" bitsize="8" type="uint8"/>"#,
+    r#"<reg name="v6" bitsize="8" type="uint8"/><reg name="v7" bitsize="8" type="uint8"/>"#,
+    r#"<reg name="v8" bitsize="8" type="uint8"/><reg name="v9" bitsize="8" type="uint8"/>"#,
+    r#"<reg name="va" bitsize="8" type="uint8"/><reg name="vb" bitsize="8" type="uint8"/>"#,
+    r#"<reg name="vc" bitsize="8" type="uint8"/><reg name="vd" bitsize="8" type="uint8"/>"#,
+    r#"<reg name="ve" bitsize="8" type="uint8"/><reg name="vf" bitsize="8" type="uint8"/>"#,
+    r#"<reg name="i" bitsize="16" type="data_ptr"/><reg name="pc" bitsize="16" type="code_ptr"/>"#,
+    r#"<reg name="sp" bitsize="8" type="uint8"/></feature></target>"#,
+);
+
+const REGISTER_COUNT: usize = 19; // v0-vf, i, pc, sp
+const PACKET_SIZE: usize = 0x1000; // the most we send or take in one packet, told to gdb in qSupported (in hex)
+
+// signals for the stop replies
+const SIGINT: u8 = 2;   // gdb interrupted us (ctrl-c)
+const SIGILL: u8 = 4;   // the program faulted
+const SIGTRAP: u8 = 5;  // breakpoint, watchpoint or step
+
+#[derive(Debug, Default)]
+pub struct GdbStub {
+    packet: Option<Vec<u8>>,  // the packet being received, from after the $
+    checksum: Option<String>, // the two hex digits after the #, as they arrive
+    running: bool,            // a continue is in progress, its stop reply goes out once the debugger pauses
+    detached: bool,           // gdb has said goodbye (D or k), the frontend should close the connection
+}
+
+impl GdbStub {
+    pub fn new() -> Self {
+        GdbStub::default()
+    }
+
+    pub fn is_detached(&self) -> bool {
+        self.detached
+    }
+
+    // handles the bytes that came in from gdb, returning the bytes to send back
+    pub fn receive(&mut self, bytes: &[u8], chip8: &mut Chip8, debugger: &mut Debugger) -> Vec<u8> {
+        let mut out = Vec::new();
+        for &byte in bytes {
+            if let Some(checksum) = &mut self.checksum {
+                checksum.push(byte as char);
+                if checksum.len() < 2 {continue}
+                let packet = self.packet.take().unwrap_or_default();
+                let expected = u8::from_str_radix(checksum, 16).ok();
+                self.checksum = None;
+                if expected != Some(sum(&packet)) {
+                    out.push(b'-'); // gdb sends it again
+                    continue;
+                }
+                out.push(b'+');
+                let text = String::from_utf8_lossy(&packet).into_owned();
+                if let Some(reply) = self.handle(&text, chip8, debugger) {
+                    out.extend(frame(&reply));
+                }
+            } else if let Some(packet) = &mut self.packet {
+                if byte == b'#' {
+                    self.checksum = Some(String::new());
+                } else if packet.len() < PACKET_SIZE {
+                    packet.push(byte);
+                } else {
+                    // gdb won't send more than we said we'd take, so this is junk: drop it and wait for the next $
+                    self.packet = None;
+                }
+            } else {
+                match byte {
+                    b'$' => self.packet = Some(Vec::new()),
+                    // ctrl-c, outside a packet
+                    0x03 => {
+                        debugger.pause();
+                        self.running = false;
+                        out.extend(frame(&format!("S{:02x}", SIGINT)));
+                    },
+                    _ => {}, // the + and - acknowledging our replies
+                }
+            }
+        }
+        out
+    }
+
+    // once a continue has stopped (breakpoint, watchpoint, fault or exit), the stop reply to send
+    pub fn poll(&mut self, chip8: &Chip8, debugger: &Debugger) -> Option<Vec<u8>> {
+        if !self.running || !(debugger.is_paused() || chip8.fault().is_some() || chip8.exited) {
+            return None;
+        }
+        self.running = false;
+        Some(frame(&stop_reply(chip8, debugger)))
+    }
+
+    // the reply to one packet, None for ones that don't get one (a continue replies when it stops)
+    fn handle(&mut self, packet: &str, chip8: &mut Chip8, debugger: &mut Debugger) -> Option<String> {
+        let (command, rest) = packet.split_at(packet.chars().next().map_or(0, char::len_utf8));
+        match command {
+            "c" => {
+                if let Some(reply) = jump_to(rest, chip8) {
+                    return Some(reply);
+                }
+                if chip8.fault().is_some() || chip8.exited {
+                    return Some(stop_reply(chip8, debugger));
+                }
+                debugger.resume();
+                self.running = true;
+                None
+            },
+            "k" => {
+                self.detached = true;
+                self.running = false;
+                debugger.resume();
+                None
+            },
+            // a packet we couldn't make sense of gets an error rather than leaving gdb waiting
+            _ => Some(self.reply(command, rest, chip8, debugger).unwrap_or_else(|| String::from("E01"))),
+        }
+    }
+
+    fn reply(&mut self, command: &str, rest: &str, chip8: &mut Chip8, debugger: &mut Debugger) -> Option<String> {
+        let reply = match command {
+            "?" => stop_reply(chip8, debugger),
+            "g" => {
+                let mut registers = String::new();
+                for n in 0..REGISTER_COUNT {
+                    registers += &register_hex(chip8, n);
+                }
+                registers
+            },
+            "G" => {
+                let bytes = decode_hex(rest)?;
+                let mut bytes = bytes.iter();
+                for n in 0..REGISTER_COUNT {
+                    let mut value = 0;
+                    for i in 0..register_size(n) {
+                        value |= (*bytes.next()? as u16) << (8 * i);
+                    }
+                    set_register(chip8, n, value);
+                }
+                String::from("OK")
+            },
+            "p" => match usize::from_str_radix(rest, 16) {
+                Ok(n) if n < REGISTER_COUNT => register_hex(chip8, n),
+                _ => String::from("E01"),
+            },
+            "P" => {
+                let (n, value) = rest.split_once('=')?;
+                let n = usize::from_str_radix(n, 16).ok().filter(|&n| n < REGISTER_COUNT);
+                match (n, decode_hex(value)) {
+                    (Some(n), Some(bytes)) if bytes.len() == register_size(n) => {
+                        let value = bytes.iter().enumerate().fold(0u16, |value, (i, &byte)| value | (byte as u16) << (8 * i));
+                        set_register(chip8, n, value);
+                        String::from("OK")
+                    },
+                    _ => String::from("E01"),
+                }
+            },
+            "m" => {
+                // a read that runs off the end of memory (or past what fits in a reply) is cut short, gdb asks again
+                let (address, length) = parse_range(rest)?;
+                let data = &chip8.memory().data;
+                if address >= data.len() {
+                    return None;
+                }
+                let end = address.saturating_add(length.min(PACKET_SIZE / 2)).min(data.len());
+                data[address..end].iter().map(|byte| format!("{:02x}", byte)).collect()
+            },
+            "M" => {
+                let (range, data) = rest.split_once(':')?;
+                let (address, length) = parse_range(range)?;
+                let bytes = decode_hex(data).filter(|bytes| bytes.len() == length)?;
+                let memory = &mut chip8.memory_mut().data;
+                let end = address.checked_add(length).filter(|&end| end <= memory.len())?;
+                memory[address..end].copy_from_slice(&bytes);
+                String::from("OK")
+            },
+            "s" => {
+                if let Some(reply) = jump_to(rest, chip8) {
+                    return Some(reply);
+                }
+                if chip8.fault().is_none() && !chip8.exited {
+                    // a fault is kept by the Chip8 and reported in the stop reply
+                    let _ = debugger.step(chip8);
+                }
+                stop_reply(chip8, debugger)
+            },
+            "Z" | "z" => {
+                let insert = command == "Z";
+                let mut fields = rest.split(',');
+                let kind = fields.next()?;
+                let address = usize::from_str_radix(fields.next()?, 16).ok()?;
+                let length = fields.next().and_then(|length| usize::from_str_radix(length, 16).ok()).unwrap_or(1).max(1);
+                // the last address covered, which has to be in memory
+                let end = address.checked_add(length - 1).filter(|&end| end < chip8.memory().size())?;
+                match kind {
+                    // software and hardware breakpoints are the same thing here (memory is never more than 64K)
+                    "0" | "1" => {
+                        if insert {debugger.add_breakpoint(address as u16)} else {debugger.remove_breakpoint(address as u16)}
+                    },
+                    "2" | "3" | "4" => {
+                        let watchpoint = Watchpoint { start: address, end, read: kind != "2", write: kind != "3" };
+                        if insert {chip8.add_watchpoint(watchpoint)} else {chip8.remove_watchpoint(&watchpoint)}
+                    },
+                    _ => return Some(String::new()),
+                }
+                String::from("OK")
+            },
+            "D" => {
+                self.detached = true;
+                self.running = false;
+                debugger.resume();
+                String::from("OK")
+            },
+            "H" => String::from("OK"),
+            "q" => query(rest),
+            _ => String::new(), // empty means "not supported"
+        };
+        Some(reply)
+    }
+
+}
+
+// c ADDR / s ADDR carry on from ADDR, the reply is an error if it's not an address
+fn jump_to(address: &str, chip8: &mut Chip8) -> Option<String> {
+    if address.is_empty() {
+        return None;
+    }
+    match u16::from_str_radix(address, 16) {
+        Ok(address) if (address as usize) < chip8.memory().size() => {
+            chip8.program_counter = address;
+            None
+        },
+        // not a number, or past the end of memory
+        _ => Some(String::from("E01")),
+    }
+}
+
+fn query(rest: &str) -> String {
+    if rest.starts_with("Supported") {
+        return format!("PacketSize={:x};qXfer:features:read+", PACKET_SIZE);
+    }
+    if let Some(range) = rest.strip_prefix("Xfer:features:read:target.xml:") {
+        let Some((offset, length)) = parse_range(range) else {return String::from("E01")};
+        let xml = TARGET_XML.as_bytes();
+        let start = offset.min(xml.len());
+        let end = offset.saturating_add(length.min(PACKET_SIZE - 1)).min(xml.len());
+        // m means there's more to come, l that this is the last of it
+        let marker = if end == xml.len() {'l'} else {'m'};
+        return format!("{}{}", marker, String::from_utf8_lossy(&xml[start..end]));
+    }
+    match rest {
+        "Attached" => String::from("1"),
+        "C" => String::from("QC1"),
+        "fThreadInfo" => String::from("m1"),
+        "sThreadInfo" => String::from("l"),
+        _ => String::new(),
+    }
+}
+
+// S05 for a breakpoint or step, T05watch:ADDR; for a watchpoint, S04 for a fault and W00 once the program's exited
+fn stop_reply(chip8: &Chip8, debugger: &Debugger) -> String {
+    if chip8.exited {
+        return String::from("W00");
+    }
+    if chip8.fault().is_some() {
+        return format!("S{:02x}", SIGILL);
+    }
+    match debugger.last_stop() {
+        Some(Stop::Watchpoint { hit, .. }) => {
+            let kind = match hit.access {
+                Access::Read => "rwatch",
+                Access::Write => "watch",
+            };
+            format!("T{:02x}{}:{:x};", SIGTRAP, kind, hit.address)
+        },
+        _ => format!("S{:02x}", SIGTRAP),
+    }
+}
+
+fn register_size(n: usize) -> usize {
+    match n {
+        16 | 17 => 2,
+        _ => 1,
+    }
+}
+
+fn register_value(chip8: &Chip8, n: usize) -> u16 {
+    match n {
+        0..=15 => chip8.v_reg[n] as u16,
+        16 => chip8.index,
+        17 => chip8.program_counter,
+        _ => chip8.stack_pointer as u16,
+    }
+}
+
+fn set_register(chip8: &mut Chip8, n: usize, value: u16) {
+    match n {
+        0..=15 => chip8.v_reg[n] = value as u8,
+        16 => chip8.index = value,
+        17 => chip8.program_counter = value,
+        _ => chip8.stack_pointer = (value as u8).min(chip8.stack.len() as u8),
+    }
+}
+
+// little endian, like gdb expects
+fn register_hex(chip8: &Chip8, n: usize) -> String {
+    let value = register_value(chip8, n);
+    (0..register_size(n)).map(|i| format!("{:02x}", (value >> (8 * i)) as u8)).collect()
+}
+
+// "ADDR,LENGTH" in hex
+fn parse_range(text: &str) -> Option<(usize, usize)> {
+    let (address, length) = text.split_once(',')?;
+    Some((usize::from_str_radix(address, 16).ok()?, usize::from_str_radix(length, 16).ok()?))
+}
+
+fn decode_hex(text: &str) -> Option<Vec<u8>> {
+    if !text.len().is_multiple_of(2) {
+        return None;
+    }
+    (0..text.len()).step_by(2).map(|i| u8::from_str_radix(text.get(i..i + 2)?, 16).ok()).collect()
+}
+
+fn sum(bytes: &[u8]) -> u8 {
+    bytes.iter().fold(0u8, |sum, &byte| sum.wrapping_add(byte))
+}
+
+// $data#checksum
+fn frame(reply: &str) -> Vec<u8> {
+    format!("${}#{:02x}", reply, sum(reply.as_bytes())).into_bytes()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::platform::Platform;
+    use crate::quirks::Quirks;
+
+    struct Session {
+        stub: GdbStub,
+        chip8: Chip8,
+        debugger: Debugger,
+    }
+
+    impl Session {
+        fn new() -> Self {
+            let mut chip8 = Chip8::new(Platform::Chip8, Quirks::COSMAC_VIP);
+            chip8.load_program(&[0x60, 0x2A, 0x12, 0x00]).unwrap();
+            Session { stub: GdbStub::new(), chip8, debugger: Debugger::new() }
+        }
+
+        fn send(&mut self, bytes: &[u8]) -> Vec<u8> {
+            self.stub.receive(bytes, &mut self.chip8, &mut self.debugger)
+        }
+
+        // sends one packet, and returns the reply without its framing (after checking the ack and checksum)
+        fn ask(&mut self, packet: &str) -> String {
+            let out = String::from_utf8(self.send(&frame(packet))).unwrap();
+            let reply = out.strip_prefix("+$").unwrap_or_else(|| panic!("{}: got {}", packet, out));
+            let (reply, checksum) = reply.split_once('#').unwrap();
+            assert_eq!(checksum, format!("{:02x}", sum(reply.as_bytes())));
+            String::from(reply)
+        }
+    }
+
+    #[test]
+    fn framing_and_checksums() {
+        let mut session = Session::new();
+        assert_eq!(frame("OK"), b"$OK#9a");
+        // a bad checksum gets a - and no reply, so gdb sends it again
+        assert_eq!(session.send(b"$?#00"), b"-");
+        assert_eq!(session.send(b"$?#zz"), b"-");
+        // split across reads, with acks in between
+        assert_eq!(session.send(b"+$?"), b"");
+        assert_eq!(session.send(b"#3"), b"");
+        assert_eq!(session.send(b"f"), b"+$S05#b8");
+        // ctrl-c outside a packet
+        assert_eq!(session.send(&[0x03]), b"$S02#b5");
+        assert!(session.debugger.is_paused());
+    }
+
+    #[test]
+    fn oversized_packets_are_dropped() {
+        let mut session = Session::new();
+        let mut junk = alloc::vec![b'$'];
+        junk.resize(PACKET_SIZE + 10, b'a');
+        junk.extend(b"#00");
+        assert_eq!(session.send(&junk), b"");
+        assert_eq!(session.ask("?"), "S05");
+    }
+
+    #[test]
+    fn registers() {
+        let mut session = Session::new();
+        session.chip8.v_reg[1] = 0xAB;
+        session.chip8.index = 0x1234;
+        let registers = session.ask("g");
+        assert_eq!(registers.len(), 2 * (16 + 2 + 2 + 1));
+        assert_eq!(&registers[2..4], "ab");
+        assert_eq!(&registers[32..36], "3412");
+        assert_eq!(&registers[36..40], "0002");
+        assert_eq!(session.ask("p10"), "3412");
+        assert_eq!(session.ask("P11=0003"), "OK");
+        assert_eq!(session.chip8.program_counter, 0x300);
+        // the wrong size, or a register that doesn't exist
+        assert_eq!(session.ask("P0=0102"), "E01");
+        assert_eq!(session.ask("P11=000300000000"), "E01");
+        assert_eq!(session.ask("p13"), "E01");
+        assert_eq!(session.ask("Pzz=00"), "E01");
+        assert_eq!(session.ask("G00"), "E01");
+    }
+
+    #[test]
+    fn memory() {
+        let mut session = Session::new();
+        assert_eq!(session.ask("m200,4"), "602a1200");
+        assert_eq!(session.ask("M300,2:beef"), "OK");
+        assert_eq!(session.ask("m300,2"), "beef");
+        // reads are cut short at the end of memory and at the packet size
+        assert_eq!(session.ask("mffe,10").len(), 4);
+        assert_eq!(session.ask("m0,ffffffff").len(), PACKET_SIZE);
+        assert_eq!(session.ask("m0,ffffffffffffffff").len(), PACKET_SIZE);
+        // anything starting past the end, or a write that would run off it, is an error
+        assert_eq!(session.ask("m1000,1"), "E01");
+        assert_eq!(session.ask("mffffffffffffffff,2"), "E01");
+        assert_eq!(session.ask("Mfff,2:0102"), "E01");
+        assert_eq!(session.ask("Mffffffffffffffff,2:0102"), "E01");
+        assert_eq!(session.ask("M300,2:01"), "E01");
+        assert_eq!(session.ask("m300"), "E01");
+        assert_eq!(session.ask("mxyz,2"), "E01");
+    }
+
+    #[test]
+    fn breakpoints_and_watchpoints() {
+        let mut session = Session::new();
+        assert_eq!(session.ask("Z0,202,2"), "OK");
+        assert!(session.debugger.has_breakpoint(0x202));
+        assert_eq!(session.ask("z0,202,2"), "OK");
+        assert!(!session.debugger.has_breakpoint(0x202));
+        assert_eq!(session.ask("Z2,300,4"), "OK");
+        assert_eq!(session.chip8.watchpoints(), [Watchpoint { start: 0x300, end: 0x303, read: false, write: true }]);
+        // past the end of memory, or so big the end overflows
+        assert_eq!(session.ask("Z0,10000,2"), "E01");
+        assert_eq!(session.ask("Z2,ffe,4"), "E01");
+        assert_eq!(session.ask("Z2,ffffffffffffffff,2"), "E01");
+        assert_eq!(session.ask("Z4,1,ffffffffffffffff"), "E01");
+        assert_eq!(session.ask("Z9,200,1"), "");
+    }
+
+    #[test]
+    fn continue_and_step() {
+        let mut session = Session::new();
+        assert_eq!(session.ask("s"), "S05");
+        assert_eq!(session.chip8.v_reg[0], 0x2A);
+        assert_eq!(session.ask("s2000"), "E01");
+        assert_eq!(session.ask("sxyz"), "E01");
+        assert_eq!(session.ask("Z0,202,2"), "OK");
+        assert_eq!(session.send(&frame("c200")), b"+");
+        assert!(session.stub.poll(&session.chip8, &session.debugger).is_none());
+        session.debugger.run(&mut session.chip8, 10).unwrap();
+        assert_eq!(session.stub.poll(&session.chip8, &session.debugger), Some(frame("S05")));
+        assert_eq!(session.chip8.program_counter, 0x202);
+    }
+
+    #[test]
+    fn queries() {
+        let mut session = Session::new();
+        assert_eq!(session.ask("qSupported:multiprocess+"), "PacketSize=1000;qXfer:features:read+");
+        let first = session.ask("qXfer:features:read:target.xml:0,10");
+        assert_eq!(first, format!("m{}", &TARGET_XML[..0x10]));
+        let rest = session.ask("qXfer:features:read:target.xml:10,ffffffffffffffff");
+        assert_eq!(rest, format!("l{}", &TARGET_XML[0x10..]));
+        assert_eq!(session.ask("qXfer:features:read:target.xml:ffffffffffffffff,ffffffffffffffff"), "l");
+        assert_eq!(session.ask("qAttached"), "1");
+        assert_eq!(session.ask("vMustReplyEmpty"), "");
+    }
+}
@@ -0,0 +1,51 @@
+// the emulator core -- everything needed to run a CHIP-8 program, with no window, audio or files
+// frontends feed it ROM bytes and key presses, tick the timers at 60Hz and draw `display`
+
+#![no_std]
+
+extern crate alloc;
+
+pub mod asm;
+pub mod chip8;
+pub mod coverage;
+pub mod database;
+pub mod debugger;
+pub mod disasm;
+pub mod display;
+pub mod error;
+pub mod gdb;
+pub mod hash;
+pub mod memory;
+pub mod movie;
+pub mod number;
+pub mod opcode;
+pub mod platform;
+pub mod png;
+pub mod profile;
+pub mod runner;
+pub mod quirks;
+pub mod rewind;
+pub mod rng;
+pub mod savestate;
+pub mod timer;
+pub mod trace;
+pub mod watch;
+
+pub use asm::{assemble, Assembly, AssemblyError};
+pub use chip8::Chip8;
+pub use coverage::Coverage;
+pub use database::{Database, RomInfo};
+pub use debugger::{Condition, Debugger, Stop};
+pub use display::Display;
+pub use error::{Chip8Error, ErrorPolicy};
+pub use memory::Memory;
+pub use movie::{Movie, Playback};
+pub use number::parse_number;
+pub use opcode::Opcode;
+pub use platform::Platform;
+pub use profile::Profile;
+pub use quirks::{MemoryIncrement, Quirks};
+pub use rewind::Rewind;
+pub use timer::Timer;
+pub use trace::Trace;
+pub use watch::Watchpoint;
@@ -0,0 +1,139 @@
+// RAM and stack handling (look up fontset)
+
+use crate::error::Chip8Error;
+
+use alloc::vec;
+use alloc::vec::Vec;
+
+// how much memory there is
+pub const CLASSIC_SIZE: usize = 0x1000;  // 4K, the COSMAC VIP and most CHIP-8 interpreters
+pub const XO_CHIP_SIZE: usize = 0x10000; // 64K, the whole 16 bit address space
+pub const MIN_SIZE: usize = 0x140;       // just the fonts, anything smaller and they don't fit
+
+// where programs are loaded and start running
+pub const DEFAULT_LOAD_ADDRESS: usize = 0x200;
+pub const ETI_660_LOAD_ADDRESS: usize = 0x600;
+
+// where the fonts live, below 0x200 where programs start
+pub const FONT_ADDRESS: usize = 0x050;
+pub const BIG_FONT_ADDRESS: usize = 0x0A0;
+
+#[derive(Debug)]
+pub struct Memory {
+    pub data: Vec<u8>,   // 4Kb of memory, or 64Kb for XO-CHIP
+    load_address: usize, // where the program goes, 0x200 except on the ETI-660
+}
+
+impl Memory {
+    // panics if check says no, so check first when the size or load address comes from outside
+    pub fn new(size: usize, load_address: usize) -> Self {
+        if let Err(err) = Memory::check(size, load_address) {
+            panic!("{} (size {:#X}, load address {:#X})", err, size, load_address);
+        }
+        let mut mem = Memory {
+            data: vec![0; size], // initialise with zeros
+            load_address,
+        };
+        mem.load_fonts(); // make sure fonts are always loaded
+        mem
+    }
+
+    // the size has to fit the fonts and no more than the 16 bit PC and I can reach, and the load address has to be inside it
+    pub fn check(size: usize, load_address: usize) -> Result<(), Chip8Error> {
+        if !(MIN_SIZE..=XO_CHIP_SIZE).contains(&size) {
+            return Err(Chip8Error::InvalidMemory("memory has to be 0x140 to 0x10000 bytes"));
+        }
+        if load_address >= size {
+            return Err(Chip8Error::InvalidMemory("the load address is past the end of memory"));
+        }
+        Ok(())
+    }
+
+    // this is the default font -- you can look into other fonts later if you'd like
+    fn load_fonts(&mut self) {
+        const CHIP8_FONT: [u8;80] = [
+        0xF0, 0x90, 0x90, 0x90, 0xF0, // 0
+        0x20, 0x60, 0x20, 0x20, 0x70, // 1
+        0xF0, 0x10, 0xF0, 0x80, 0xF0, // 2
+        0xF0, 0x10, 0xF0, 0x10, 0xF0, // 3
+        0x90, 0x90, 0xF0, 0x10, 0x10, // 4
+        0xF0, 0x80, 0xF0, 0x10, 0xF0, // 5
+        0xF0, 0x80, 0xF0, 0x90, 0xF0, // 6
+        0xF0, 0x10, 0x20, 0x40, 0x40, // 7
+        0xF0, 0x90, 0xF0, 0x90, 0xF0, // 8
+        0xF0, 0x90, 0xF0, 0x10, 0xF0, // 9
+        0xF0, 0x90, 0xF0, 0x90, 0x90, // A
+        0xE0, 0x90, 0xE0, 0x90, 0xE0, // B
+        0xF0, 0x80, 0x80, 0x80, 0xF0, // C
+        0xE0, 0x90, 0x90, 0x90, 0xE0, // D
+        0xF0, 0x80, 0xF0, 0x80, 0xF0, // E
+        0xF0, 0x80, 0xF0, 0x80, 0x80  // F
+        ];
+
+        // SUPER-CHIP's 8x10 font for FX30 (the same digits Octo uses, A-F included)
+        const BIG_FONT: [u8;160] = [
+        0xFF, 0xFF, 0xC3, 0xC3, 0xC3, 0xC3, 0xC3, 0xC3, 0xFF, 0xFF, // 0
+        0x18, 0x78, 0x78, 0x18, 0x18, 0x18, 0x18, 0x18, 0xFF, 0xFF, // 1
+        0xFF, 0xFF, 0x03, 0x03, 0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF, // 2
+        0xFF, 0xFF, 0x03, 0x03, 0xFF, 0xFF, 0x03, 0x03, 0xFF, 0xFF, // 3
+        0xC3, 0xC3, 0xC3, 0xC3, 0xFF, 0xFF, 0x03, 0x03, 0x03, 0x03, // 4
+        0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF, 0x03, 0x03, 0xFF, 0xFF, // 5
+        0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF, 0xC3, 0xC3, 0xFF, 0xFF, // 6
+        0xFF, 0xFF, 0x03, 0x03, 0x06, 0x0C, 0x18, 0x18, 0x18, 0x18, // 7
+        0xFF, 0xFF, 0xC3, 0xC3, 0xFF, 0xFF, 0xC3, 0xC3, 0xFF, 0xFF, // 8
+        0xFF, 0xFF, 0xC3, 0xC3, 0xFF, 0xFF, 0x03, 0x03, 0xFF, 0xFF, // 9
+        0x7E, 0xFF, 0xC3, 0xC3, 0xC3, 0xFF, 0xFF, 0xC3, 0xC3, 0xC3, // A
+        0xFC, 0xFC, 0xC3, 0xC3, 0xFC, 0xFC, 0xC3, 0xC3, 0xFC, 0xFC, // B
+        0x3C, 0xFF, 0xC3, 0xC0, 0xC0, 0xC0, 0xC0, 0xC3, 0xFF, 0x3C, // C
+        0xFC, 0xFE, 0xC3, 0xC3, 0xC3, 0xC3, 0xC3, 0xC3, 0xFE, 0xFC, // D
+        0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF, // E
+        0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF, 0xC0, 0xC0, 0xC0, 0xC0  // F
+        ];
+
+        // apparently this is the convention, no reason not to put it somewhere else below 0x200
+        self.data[FONT_ADDRESS..FONT_ADDRESS+80].copy_from_slice(&CHIP8_FONT);
+        self.data[BIG_FONT_ADDRESS..BIG_FONT_ADDRESS+160].copy_from_slice(&BIG_FONT);
+
+    }
+
+    pub fn size(&self) -> usize {
+        self.data.len()
+    }
+
+    pub fn load_address(&self) -> usize {
+        self.load_address
+    }
+
+    // addresses wrap round the end of memory like they do on real hardware (0x1000 is 0x000 again on a 4K machine)
+    // every read and write goes through this, so there's no such thing as an address past the end
+    pub fn wrap(&self, address: usize) -> usize {
+        address % self.data.len()
+    }
+
+    //read byte
+    pub fn read_byte(&self, address: usize) -> u8 {
+        self.data[self.wrap(address)]
+    }
+
+    //write byte
+    pub fn write_byte(&mut self, address: usize, byte: u8) {
+        let address = self.wrap(address);
+        self.data[address] = byte;
+    }
+    
+    //load program
+    pub fn load_program(&mut self, program: &[u8]) -> Result<(), Chip8Error> {
+        
+        //we want to start at the load address (normally 0x200), everything above this is usable memory
+        let start_pt = self.load_address;
+
+        // check it's small enough:
+        if program.len() > self.data.len().saturating_sub(start_pt) {
+            return Err(Chip8Error::RomTooLarge { rom_size: program.len(), max_size: self.data.len().saturating_sub(start_pt) });
+        }
+
+        self.data[start_pt..(start_pt+program.len())].copy_from_slice(program);
+        Ok(())
+    }
+}
+
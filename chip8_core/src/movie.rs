@@ -0,0 +1,389 @@
+// input movies -- every key press and release with the frame it happened on, so a run can be replayed exactly
+//
+// movies are plain text so they can be attached to bug reports and diffed:
+//
+//   chip8-movie 1
+//   rom 0123456789abcdef0123456789abcdef01234567   (SHA-1 of the ROM)
+//   seed 1234
+//   platform chip8
+//   quirks vf_reset memory_increment display_wait clipping   (memory_increment_by_x for CHIP-48's FX55/FX65)
+//   memory 0x1000 0x200                                      (size and load address, the platform's and 0x200 if it's missing)
+//   cycles-per-frame 10
+//   # frame key down|up
+//   120 5 down
+//   131 5 up
+//
+// a frame is one 60Hz timer tick: the inputs for frame N are applied, then the frame's cycles run, then the timers tick.
+// replaying needs the same ROM, seed, platform, quirks and cycles per frame, which is why they're in the header
+
+use crate::chip8::Chip8;
+use crate::error::Chip8Error;
+use crate::hash::{sha1, to_hex};
+use crate::memory::{Memory, DEFAULT_LOAD_ADDRESS};
+use crate::number::parse_number;
+use crate::platform::Platform;
+use crate::quirks::{MemoryIncrement, Quirks};
+
+use alloc::format;
+use alloc::string::String;
+use alloc::vec::Vec;
+
+pub const MOVIE_VERSION: u32 = 1;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Movie {
+    pub rom_hash: [u8; 20],
+    pub seed: u64,
+    pub platform: Platform,
+    pub quirks: Quirks,
+    pub memory_size: usize,
+    pub load_address: usize,
+    pub cycles_per_frame: u32,
+    pub events: Vec<InputEvent>, // in frame order
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct InputEvent {
+    pub frame: u64,
+    pub key: u8,
+    pub pressed: bool,
+}
+
+impl Movie {
+    // an empty movie for recording a run of `rom` on this machine
+    pub fn new(rom: &[u8], chip8: &Chip8, cycles_per_frame: u32) -> Self {
+        Movie {
+            rom_hash: sha1(rom),
+            seed: chip8.rng_seed(),
+            platform: chip8.platform(),
+            quirks: chip8.quirks(),
+            memory_size: chip8.memory().size(),
+            load_address: chip8.memory().load_address(),
+            cycles_per_frame,
+            events: Vec::new(),
+        }
+    }
+
+    pub fn record(&mut self, frame: u64, key: u8, pressed: bool) {
+        self.events.push(InputEvent { frame, key, pressed });
+    }
+
+    // forget everything from `frame` on, e.g. after rewinding to it while recording
+    pub fn truncate(&mut self, frame: u64) {
+        self.events.retain(|event| event.frame < frame);
+    }
+
+    pub fn matches_rom(&self, rom: &[u8]) -> bool {
+        self.rom_hash == sha1(rom)
+    }
+
+    // a fresh machine set up the way the movie was recorded (the ROM still needs loading)
+    pub fn new_machine(&self) -> Chip8 {
+        let mut chip8 = Chip8::with_memory(self.platform, self.quirks, Memory::new(self.memory_size, self.load_address));
+        chip8.seed_rng(self.seed);
+        chip8
+    }
+
+    pub fn to_text(&self) -> String {
+        let mut text = format!("chip8-movie {}\n", MOVIE_VERSION);
+        text += &format!("rom {}\n", to_hex(&self.rom_hash));
+        text += &format!("seed {}\n", self.seed);
+        text += &format!("platform {}\n", self.platform.name());
+        text += "quirks";
+        for (name, on) in quirk_flags(&self.quirks) {
+            if on {
+                text += " ";
+                text += name;
+            }
+        }
+        text += "\n";
+        text += &format!("memory {:#X} {:#X}\n", self.memory_size, self.load_address);
+        text += &format!("cycles-per-frame {}\n", self.cycles_per_frame);
+        text += "# frame key down|up\n";
+        for event in &self.events {
+            text += &format!("{} {:X} {}\n", event.frame, event.key, if event.pressed { "down" } else { "up" });
+        }
+        text
+    }
+
+    pub fn parse(text: &str) -> Result<Movie, Chip8Error> {
+        let mut lines = text.lines().enumerate().map(|(i, line)| (i + 1, line.trim())).filter(|(_, line)| !line.is_empty() && !line.starts_with('#'));
+        let invalid = |line, reason| Chip8Error::InvalidMovie { line, reason };
+
+        let (line, first) = lines.next().ok_or(invalid(1, "empty file"))?;
+        match first.strip_prefix("chip8-movie ") {
+            Some(version) if version.trim() == format!("{}", MOVIE_VERSION) => {}
+            Some(_) => return Err(invalid(line, "recorded by a different version")),
+            None => return Err(invalid(line, "not a movie file")),
+        }
+
+        let mut rom_hash = None;
+        let mut seed = None;
+        let mut platform = None;
+        let mut quirks = None;
+        let mut memory = None;
+        let mut cycles_per_frame = None;
+        let mut events = Vec::new();
+
+        for (line, text) in lines {
+            let mut words = text.split_whitespace();
+            let first = words.next().unwrap_or("");
+            match first {
+                "rom" => {
+                    let hex = words.next().ok_or(invalid(line, "missing ROM hash"))?;
+                    rom_hash = Some(parse_hash(hex).ok_or(invalid(line, "bad ROM hash"))?);
+                }
+                "seed" => seed = Some(words.next().and_then(|n| n.parse().ok()).ok_or(invalid(line, "bad seed"))?),
+                "platform" => platform = Some(words.next().and_then(Platform::from_name).ok_or(invalid(line, "unknown platform"))?),
+                "quirks" => {
+                    let mut parsed = Quirks { vf_reset: false, memory_increment: MemoryIncrement::Unchanged, display_wait: false, clipping: false, shifting: false, jumping: false };
+                    for name in words.by_ref() {
+                        match name {
+                            "vf_reset" => parsed.vf_reset = true,
+                            "memory_increment" => parsed.memory_increment = MemoryIncrement::ByXPlusOne,
+                            "memory_increment_by_x" => parsed.memory_increment = MemoryIncrement::ByX,
+                            "display_wait" => parsed.display_wait = true,
+                            "clipping" => parsed.clipping = true,
+                            "shifting" => parsed.shifting = true,
+                            "jumping" => parsed.jumping = true,
+                            _ => return Err(invalid(line, "unknown quirk")),
+                        }
+                    }
+                    quirks = Some(parsed);
+                }
+                "memory" => {
+                    let mut number = || words.next().and_then(parse_number).and_then(|n| usize::try_from(n).ok());
+                    let (size, load_address) = number().zip(number()).ok_or(invalid(line, "expected memory SIZE LOAD-ADDRESS"))?;
+                    Memory::check(size, load_address).map_err(|_| invalid(line, "bad memory size or load address"))?;
+                    memory = Some((size, load_address));
+                }
+                "cycles-per-frame" => cycles_per_frame = Some(words.next().and_then(|n| n.parse().ok()).ok_or(invalid(line, "bad cycles per frame"))?),
+                _ => {
+                    // an input event: frame key down|up
+                    let frame: u64 = first.parse().map_err(|_| invalid(line, "expected a header line or an input event"))?;
+                    let key = words.next().and_then(|k| u8::from_str_radix(k, 16).ok()).filter(|&k| k < 16).ok_or(invalid(line, "bad key (0-F)"))?;
+                    let pressed = match words.next() {
+                        Some("down") => true,
+                        Some("up") => false,
+                        _ => return Err(invalid(line, "expected down or up")),
+                    };
+                    if events.last().is_some_and(|last: &InputEvent| last.frame > frame) {
+                        return Err(invalid(line, "events out of order"));
+                    }
+                    events.push(InputEvent { frame, key, pressed });
+                }
+            }
+            if words.next().is_some() {
+                return Err(invalid(line, "unexpected text at the end of the line"));
+            }
+        }
+
+        let end = text.lines().count();
+        let platform = platform.ok_or(invalid(end, "no platform line"))?;
+        let (memory_size, load_address) = memory.unwrap_or((platform.memory_size(), DEFAULT_LOAD_ADDRESS));
+        Ok(Movie {
+            rom_hash: rom_hash.ok_or(invalid(end, "no rom line"))?,
+            seed: seed.ok_or(invalid(end, "no seed line"))?,
+            platform,
+            quirks: quirks.ok_or(invalid(end, "no quirks line"))?,
+            memory_size,
+            load_address,
+            cycles_per_frame: cycles_per_frame.ok_or(invalid(end, "no cycles-per-frame line"))?,
+            events,
+        })
+    }
+}
+
+// feeds a movie's inputs back in, call apply at the start of every frame
+#[derive(Debug)]
+pub struct Playback {
+    movie: Movie,
+    next: usize, // the next event to apply
+}
+
+impl Playback {
+    pub fn new(movie: Movie) -> Self {
+        Playback { movie, next: 0 }
+    }
+
+    pub fn movie(&self) -> &Movie {
+        &self.movie
+    }
+
+    pub fn apply(&mut self, chip8: &mut Chip8) {
+        let frame = chip8.frame_count();
+        while let Some(event) = self.movie.events.get(self.next) {
+            if event.frame > frame {
+                break;
+            }
+            chip8.update_keypad(event.key as usize, event.pressed);
+            self.next += 1;
+        }
+    }
+
+    // carry on from `frame`, e.g. after rewinding to it
+    pub fn seek(&mut self, frame: u64) {
+        self.next = self.movie.events.partition_point(|event| event.frame < frame);
+    }
+
+    pub fn is_finished(&self) -> bool {
+        self.next >= self.movie.events.len()
+    }
+}
+
+fn quirk_flags(quirks: &Quirks) -> [(&'static str, bool); 7] {
+    [
+        ("vf_reset", quirks.vf_reset),
+        ("memory_increment", quirks.memory_increment == MemoryIncrement::ByXPlusOne),
+        ("memory_increment_by_x", quirks.memory_increment == MemoryIncrement::ByX),
+        ("display_wait", quirks.display_wait),
+        ("clipping", quirks.clipping),
+        ("shifting", quirks.shifting),
+        ("jumping", quirks.jumping),
+    ]
+}
+
+fn parse_hash(hex: &str) -> Option<[u8; 20]> {
+    if hex.len() != 40 || !hex.is_ascii() {
+        return None;
+    }
+    let mut hash = [0; 20];
+    for (i, byte) in hash.iter_mut().enumerate() {
+        *byte = u8::from_str_radix(&hex[i * 2..i * 2 + 2], 16).ok()?;
+    }
+    Some(hash)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ROM: [u8; 2] = [0x12, 0x00];
+
+    fn movie() -> Movie {
+        let mut chip8 = Chip8::with_memory(Platform::Chip8, Quirks::CHIP_48, Memory::new(0x800, 0x300));
+        chip8.seed_rng(99);
+        let mut movie = Movie::new(&ROM, &chip8, 15);
+        movie.record(3, 0x5, true);
+        movie.record(3, 0xA, true);
+        movie.record(7, 0x5, false);
+        movie.record(12, 0xA, false);
+        movie
+    }
+
+    fn invalid(text: &str) -> (usize, &'static str) {
+        match Movie::parse(text) {
+            Err(Chip8Error::InvalidMovie { line, reason }) => (line, reason),
+            other => panic!("expected a parse error, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn text_round_trip() {
+        let movie = movie();
+        let text = movie.to_text();
+        assert!(text.starts_with("chip8-movie 1\nrom "));
+        assert!(text.contains("\nquirks memory_increment_by_x clipping shifting jumping\n"));
+        assert!(text.contains("\nmemory 0x800 0x300\n"));
+        assert!(text.ends_with("3 5 down\n3 A down\n7 5 up\n12 A up\n"));
+        assert_eq!(Movie::parse(&text).unwrap(), movie);
+        assert!(movie.matches_rom(&ROM));
+        assert!(!movie.matches_rom(&[0x12, 0x02]));
+    }
+
+    #[test]
+    fn memory_defaults_to_the_platforms() {
+        let text = movie().to_text().replace("memory 0x800 0x300\n", "");
+        let movie = Movie::parse(&text).unwrap();
+        assert_eq!((movie.memory_size, movie.load_address), (Platform::Chip8.memory_size(), DEFAULT_LOAD_ADDRESS));
+    }
+
+    #[test]
+    fn errors_say_which_line() {
+        let text = movie().to_text();
+        assert_eq!(invalid(""), (1, "empty file"));
+        assert_eq!(invalid("not a movie\n"), (1, "not a movie file"));
+        assert_eq!(invalid(&text.replace("chip8-movie 1", "chip8-movie 2")), (1, "recorded by a different version"));
+        // blank lines and comments still count
+        assert_eq!(invalid(&text.replace("seed 99\n", "\n# the seed\nseed lots\n")), (5, "bad seed"));
+        assert_eq!(invalid(&text.replace("platform chip8", "platform megachip")), (4, "unknown platform"));
+        assert_eq!(invalid(&text.replace("clipping", "wobbling")), (5, "unknown quirk"));
+        assert_eq!(invalid(&text.replace("memory 0x800 0x300", "memory 0x800 0x900")), (6, "bad memory size or load address"));
+        assert_eq!(invalid(&text.replace("7 5 up", "7 G up")), (11, "bad key (0-F)"));
+        assert_eq!(invalid(&text.replace("7 5 up", "7 5 sideways")), (11, "expected down or up"));
+        assert_eq!(invalid(&text.replace("7 5 up", "2 5 up")), (11, "events out of order"));
+        assert_eq!(invalid(&text.replace("12 A up", "12 A up now")), (12, "unexpected text at the end of the line"));
+        assert_eq!(invalid(&text.replace("12 A up", "soon")), (12, "expected a header line or an input event"));
+        // a missing header line is reported at the end of the file
+        assert_eq!(invalid(&text.replace("cycles-per-frame 15\n", "")), (11, "no cycles-per-frame line"));
+    }
+
+    // runs frames up to `frame` (applying the movie at the start of each) and returns the keypad
+    fn play_to(playback: &mut Playback, chip8: &mut Chip8, frame: u64) -> [bool; 16] {
+        while chip8.frame_count() < frame {
+            playback.apply(chip8);
+            chip8.update_timers();
+        }
+        playback.apply(chip8);
+        chip8.keypad()
+    }
+
+    fn held(keys: &[usize]) -> [bool; 16] {
+        let mut keypad = [false; 16];
+        for &key in keys {
+            keypad[key] = true;
+        }
+        keypad
+    }
+
+    #[test]
+    fn playback_presses_keys_on_their_frames() {
+        let movie = movie();
+        let mut chip8 = movie.new_machine();
+        assert_eq!((chip8.rng_seed(), chip8.memory().size(), chip8.memory().load_address()), (99, 0x800, 0x300));
+        let mut playback = Playback::new(movie);
+        assert_eq!(play_to(&mut playback, &mut chip8, 2), held(&[]));
+        assert_eq!(play_to(&mut playback, &mut chip8, 3), held(&[0x5, 0xA]));
+        assert_eq!(play_to(&mut playback, &mut chip8, 6), held(&[0x5, 0xA]));
+        assert_eq!(play_to(&mut playback, &mut chip8, 7), held(&[0xA]));
+        assert!(!playback.is_finished());
+        assert_eq!(play_to(&mut playback, &mut chip8, 12), held(&[]));
+        assert!(playback.is_finished());
+        // past the end nothing changes, and the real keyboard is left alone
+        chip8.update_keypad(3, true);
+        assert_eq!(play_to(&mut playback, &mut chip8, 40), held(&[3]));
+    }
+
+    #[test]
+    fn seek_picks_up_from_a_frame() {
+        let mut playback = Playback::new(movie());
+        let mut chip8 = movie().new_machine();
+        play_to(&mut playback, &mut chip8, 20);
+
+        // back to frame 7 (as rewinding would), with the keys as they were then
+        let mut rewound = movie().new_machine();
+        for _ in 0..7 {
+            rewound.update_timers();
+        }
+        rewound.update_keypad(0x5, true);
+        rewound.update_keypad(0xA, true);
+        playback.seek(7);
+        assert!(!playback.is_finished());
+        assert_eq!(play_to(&mut playback, &mut rewound, 7), held(&[0xA]));
+        assert_eq!(play_to(&mut playback, &mut rewound, 12), held(&[]));
+
+        playback.seek(100);
+        assert!(playback.is_finished());
+        playback.seek(0);
+        assert_eq!(play_to(&mut playback, &mut movie().new_machine(), 3), held(&[0x5, 0xA]));
+    }
+
+    #[test]
+    fn truncate_forgets_from_a_frame_on() {
+        let mut movie = movie();
+        movie.truncate(7);
+        assert_eq!(movie.events.len(), 2);
+        movie.truncate(0);
+        assert!(movie.events.is_empty());
+    }
+}
@@ -0,0 +1,36 @@
+// numbers as people type them on the command line, in movies and in Octo source: decimal, 0x hex or 0b binary
+
+pub fn parse_number(text: &str) -> Option<u64> {
+    // Rust's parsing takes a + sign, which nothing here should
+    if text.contains('+') {
+        return None;
+    }
+    if let Some(hex) = text.strip_prefix("0x").or_else(|| text.strip_prefix("0X")) {
+        u64::from_str_radix(hex, 16).ok()
+    } else if let Some(binary) = text.strip_prefix("0b").or_else(|| text.strip_prefix("0B")) {
+        u64::from_str_radix(binary, 2).ok()
+    } else {
+        text.parse().ok()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn bases() {
+        assert_eq!(parse_number("42"), Some(42));
+        assert_eq!(parse_number("0x2A"), Some(42));
+        assert_eq!(parse_number("0X2a"), Some(42));
+        assert_eq!(parse_number("0b101010"), Some(42));
+        assert_eq!(parse_number("0B101010"), Some(42));
+    }
+
+    #[test]
+    fn not_numbers() {
+        for text in ["", "0x", "0b2", "-1", "+1", "0x+1", "1.5", "x2A", "0x2A ", "18446744073709551616"] {
+            assert_eq!(parse_number(text), None, "{}", text);
+        }
+    }
+}
@@ -0,0 +1,39 @@
+// which instruction set the interpreter understands
+// (the quirks cover the smaller differences in how shared instructions behave)
+
+use crate::memory::{CLASSIC_SIZE, XO_CHIP_SIZE};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Platform {
+    Chip8,     // the 35 original opcodes (also CHIP-48)
+    SuperChip, // SUPER-CHIP 1.1: hi-res mode, scrolling, big sprites and font, RPL flags
+    XoChip,    // Octo's XO-CHIP: everything SUPER-CHIP has plus 64K memory, two bitplanes and audio patterns
+}
+
+impl Platform {
+    pub fn from_name(name: &str) -> Option<Platform> {
+        match name.to_ascii_lowercase().as_str() {
+            "vip" | "cosmac" | "cosmac-vip" | "chip8" | "chip-8" | "chip48" | "chip-48" => Some(Platform::Chip8),
+            "schip" | "superchip" | "super-chip" => Some(Platform::SuperChip),
+            "xochip" | "xo-chip" => Some(Platform::XoChip),
+            _ => None,
+        }
+    }
+
+    // the short name, from_name turns it back into the platform
+    pub fn name(&self) -> &'static str {
+        match self {
+            Platform::Chip8 => "chip8",
+            Platform::SuperChip => "schip",
+            Platform::XoChip => "xochip",
+        }
+    }
+
+    // how much memory the platform can address
+    pub fn memory_size(&self) -> usize {
+        match self {
+            Platform::XoChip => XO_CHIP_SIZE,
+            _ => CLASSIC_SIZE,
+        }
+    }
+}
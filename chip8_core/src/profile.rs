@@ -0,0 +1,275 @@
+// profiler -- where a program spends its cycles, for ROM authors working to a cycle budget
+//
+// counts every instruction by address and by opcode class, follows 2NNN / 00EE to charge cycles to subroutines
+// (self = its own instructions, total = including the ones it called), and counts the frames spent waiting in FX0A
+// report() is a text summary, folded() is one "main;sub_2a4;sub_300 1234" line per call stack for flamegraph.pl,
+// speedscope and the like
+
+use crate::disasm::{self, Syntax};
+use crate::memory::Memory;
+use crate::opcode::Opcode;
+use crate::platform::Platform;
+
+use alloc::collections::BTreeMap;
+use alloc::format;
+use alloc::string::String;
+use alloc::vec::Vec;
+
+const MAX_DEPTH: usize = 64;    // deeper than any real stack, so runaway recursion can't eat memory
+const HOTTEST: usize = 20;      // addresses listed in the report
+
+#[derive(Debug, Clone, Default)]
+pub struct Profile {
+    instructions: u64,
+    addresses: BTreeMap<u16, u64>,
+    classes: BTreeMap<&'static str, u64>,
+    stack: Vec<u16>,                      // the subroutines we're in, outermost first (main isn't on it)
+    stacks: BTreeMap<Vec<u16>, u64>,      // instructions run with exactly this call stack
+    calls: BTreeMap<u16, u64>,            // how many times each subroutine was called
+    pub(crate) waiting: bool,             // the last instruction was an FX0A still waiting for a key
+    key_wait_frames: u64,
+}
+
+impl Profile {
+    pub fn new() -> Self {
+        Profile::default()
+    }
+
+    // called by decode_execute for every instruction
+    pub(crate) fn count(&mut self, address: u16, opcode: &Opcode) {
+        self.instructions += 1;
+        *self.addresses.entry(address).or_default() += 1;
+        *self.classes.entry(class(opcode)).or_default() += 1;
+        // most instructions are in the same subroutine as the last one, so only allocate for a new stack
+        match self.stacks.get_mut(&self.stack) {
+            Some(count) => *count += 1,
+            None => {
+                self.stacks.insert(self.stack.clone(), 1);
+            },
+        }
+        self.waiting = false;
+    }
+
+    // 2NNN
+    pub(crate) fn enter(&mut self, address: u16) {
+        *self.calls.entry(address).or_default() += 1;
+        if self.stack.len() < MAX_DEPTH {
+            self.stack.push(address);
+        }
+    }
+
+    // 00EE
+    pub(crate) fn leave(&mut self) {
+        self.stack.pop();
+    }
+
+    // called by update_timers
+    pub(crate) fn frame(&mut self) {
+        if self.waiting {
+            self.key_wait_frames += 1;
+        }
+    }
+
+    // flamegraph-compatible folded stacks, one line per call stack
+    pub fn folded(&self) -> String {
+        let mut out = String::new();
+        for (stack, count) in &self.stacks {
+            let mut names = Vec::with_capacity(stack.len() + 1);
+            names.push(String::from("main"));
+            names.extend(stack.iter().map(|&address| name(address)));
+            out += &format!("{} {}\n", names.join(";"), count);
+        }
+        out
+    }
+
+    // the text report, `memory` is only used to disassemble the hottest addresses
+    pub fn report(&self, memory: &Memory, platform: Platform) -> String {
+        let percent = |count: u64| if self.instructions == 0 {0.0} else {count as f64 * 100.0 / self.instructions as f64};
+        let mut out = String::from("# chip8 profile\n");
+        out += &format!("instructions           {}\n", self.instructions);
+        out += &format!("frames waiting (FX0A)  {}\n", self.key_wait_frames);
+
+        out += "\nhottest addresses\n     count       %  address  instruction\n";
+        let mut addresses: Vec<(&u16, &u64)> = self.addresses.iter().collect();
+        addresses.sort_by(|a, b| b.1.cmp(a.1).then(a.0.cmp(b.0)));
+        for (&address, &count) in addresses.iter().take(HOTTEST) {
+            let instruction = disasm::disassemble(memory, address as usize, platform, Syntax::Octo);
+            out += &format!("{:>10}  {:>5.1}%  {:#05x}    {}\n", count, percent(count), address, instruction.text);
+        }
+
+        out += "\nopcode classes\n     count       %  class\n";
+        let mut classes: Vec<(&&str, &u64)> = self.classes.iter().collect();
+        classes.sort_by(|a, b| b.1.cmp(a.1).then(a.0.cmp(b.0)));
+        for (class, &count) in classes {
+            out += &format!("{:>10}  {:>5.1}%  {}\n", count, percent(count), class);
+        }
+
+        // self is a subroutine's own instructions, total includes the subroutines it called
+        let mut routines: BTreeMap<Option<u16>, (u64, u64)> = BTreeMap::new();
+        for (stack, &count) in &self.stacks {
+            routines.entry(stack.last().copied()).or_default().0 += count;
+            routines.entry(None).or_default().1 += count;
+            let mut seen: Vec<u16> = Vec::new();
+            for &address in stack {
+                // a recursive routine is only charged once per stack
+                if !seen.contains(&address) {
+                    routines.entry(Some(address)).or_default().1 += count;
+                    seen.push(address);
+                }
+            }
+        }
+        out += "\nsubroutines\n      self       %       total       %     calls  name\n";
+        let mut routines: Vec<(Option<u16>, (u64, u64))> = routines.into_iter().collect();
+        routines.sort_by(|a, b| (b.1).0.cmp(&(a.1).0).then(a.0.cmp(&b.0)));
+        for (address, (own, total)) in routines {
+            let (calls, label) = match address {
+                Some(address) => (self.calls.get(&address).copied().unwrap_or(0), name(address)),
+                None => (0, String::from("main")),
+            };
+            out += &format!("{:>10}  {:>5.1}%  {:>10}  {:>5.1}%  {:>8}  {}\n", own, percent(own), total, percent(total), calls, label);
+        }
+        out
+    }
+}
+
+// the same names the disassembler gives subroutines
+fn name(address: u16) -> String {
+    format!("sub_{:03x}", address)
+}
+
+// e.g. "8XY4", "DXYN" or "FX0A"
+fn class(opcode: &Opcode) -> &'static str {
+    match (opcode.a, opcode.x, opcode.y, opcode.n) {
+        (0x0, 0x0, 0xE, 0x0) => "00E0",
+        (0x0, 0x0, 0xE, 0xE) => "00EE",
+        (0x0, 0x0, 0xC, _) => "00CN",
+        (0x0, 0x0, 0xD, _) => "00DN",
+        (0x0, 0x0, 0xF, 0xB) => "00FB",
+        (0x0, 0x0, 0xF, 0xC) => "00FC",
+        (0x0, 0x0, 0xF, 0xD) => "00FD",
+        (0x0, 0x0, 0xF, 0xE) => "00FE",
+        (0x0, 0x0, 0xF, 0xF) => "00FF",
+        (0x0, _, _, _) => "0NNN",
+        (0x1, _, _, _) => "1NNN",
+        (0x2, _, _, _) => "2NNN",
+        (0x3, _, _, _) => "3XNN",
+        (0x4, _, _, _) => "4XNN",
+        (0x5, _, _, 0x2) => "5XY2",
+        (0x5, _, _, 0x3) => "5XY3",
+        (0x5, _, _, _) => "5XY0",
+        (0x6, _, _, _) => "6XNN",
+        (0x7, _, _, _) => "7XNN",
+        (0x8, _, _, 0x0) => "8XY0",
+        (0x8, _, _, 0x1) => "8XY1",
+        (0x8, _, _, 0x2) => "8XY2",
+        (0x8, _, _, 0x3) => "8XY3",
+        (0x8, _, _, 0x4) => "8XY4",
+        (0x8, _, _, 0x5) => "8XY5",
+        (0x8, _, _, 0x6) => "8XY6",
+        (0x8, _, _, 0x7) => "8XY7",
+        (0x8, _, _, 0xE) => "8XYE",
+        (0x8, _, _, _) => "8XY?",
+        (0x9, _, _, _) => "9XY0",
+        (0xA, _, _, _) => "ANNN",
+        (0xB, _, _, _) => "BNNN",
+        (0xC, _, _, _) => "CXNN",
+        (0xD, _, _, _) => "DXYN",
+        (0xE, _, 0x9, 0xE) => "EX9E",
+        (0xE, _, 0xA, 0x1) => "EXA1",
+        (0xE, _, _, _) => "EX??",
+        (0xF, 0x0, 0x0, 0x0) => "F000",
+        (0xF, _, _, _) => match opcode.nn {
+            0x01 => "FN01",
+            0x02 => "F002",
+            0x07 => "FX07",
+            0x0A => "FX0A",
+            0x15 => "FX15",
+            0x18 => "FX18",
+            0x1E => "FX1E",
+            0x29 => "FX29",
+            0x30 => "FX30",
+            0x33 => "FX33",
+            0x3A => "FX3A",
+            0x55 => "FX55",
+            0x65 => "FX65",
+            0x75 => "FX75",
+            0x85 => "FX85",
+            _ => "FX??",
+        },
+        _ => "????",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chip8::Chip8;
+    use crate::quirks::Quirks;
+
+    fn profiled(program: &[u8], cycles: usize) -> Chip8 {
+        let mut chip8 = Chip8::new(Platform::Chip8, Quirks::CHIP_48);
+        chip8.load_program(program).unwrap();
+        chip8.set_profile(Some(Profile::new()));
+        for _ in 0..cycles {
+            chip8.run_cycle_once().unwrap();
+        }
+        chip8
+    }
+
+    // main calls sub_206, which calls sub_20c, then both return and main loops
+    const NESTED: [u8; 16] = [
+        0x22, 0x06, 0x12, 0x02, 0x00, 0x00,
+        0x60, 0x01, 0x22, 0x0C, 0x00, 0xEE,
+        0x70, 0x01, 0x00, 0xEE,
+    ];
+
+    fn report(chip8: &Chip8) -> String {
+        chip8.profile().unwrap().report(chip8.memory(), Platform::Chip8)
+    }
+
+    #[test]
+    fn charges_cycles_to_the_call_stack() {
+        let chip8 = profiled(&NESTED, 8);
+        let profile = chip8.profile().unwrap();
+        assert_eq!(profile.instructions, 8);
+        assert_eq!(profile.addresses[&0x202], 2);
+        assert_eq!(profile.addresses[&0x20E], 1);
+        assert_eq!(profile.folded(), "main 3\nmain;sub_206 3\nmain;sub_206;sub_20c 2\n");
+    }
+
+    #[test]
+    fn report_lists_addresses_classes_and_subroutines() {
+        let chip8 = profiled(&NESTED, 8);
+        let report = report(&chip8);
+        assert!(report.starts_with("# chip8 profile\ninstructions           8\nframes waiting (FX0A)  0\n"));
+        // the most run address first, ties in address order
+        assert!(report.contains("     count       %  address  instruction\n         2   25.0%  0x202    jump 0x202\n         1   12.5%  0x200    0x206\n"), "{}", report);
+        assert!(report.contains("     count       %  class\n         2   25.0%  00EE\n         2   25.0%  1NNN\n         2   25.0%  2NNN\n         1   12.5%  6XNN\n         1   12.5%  7XNN\n"), "{}", report);
+        // self, then total including the subroutines called
+        assert!(report.ends_with(concat!(
+            "      self       %       total       %     calls  name\n",
+            "         3   37.5%           8  100.0%         0  main\n",
+            "         3   37.5%           5   62.5%         1  sub_206\n",
+            "         2   25.0%           2   25.0%         1  sub_20c\n",
+        )), "{}", report);
+    }
+
+    #[test]
+    fn recursion_is_charged_once_per_stack() {
+        // sub_204 calls itself, three calls in three cycles
+        let chip8 = profiled(&[0x22, 0x04, 0x12, 0x02, 0x22, 0x04], 3);
+        assert_eq!(chip8.profile().unwrap().folded(), "main 1\nmain;sub_204 1\nmain;sub_204;sub_204 1\n");
+        assert!(report(&chip8).contains("         2   66.7%           2   66.7%         3  sub_204\n"), "{}", report(&chip8));
+    }
+
+    #[test]
+    fn counts_frames_waiting_for_a_key() {
+        let mut chip8 = profiled(&[0xF0, 0x0A], 0);
+        for _ in 0..3 {
+            chip8.run_cycle_once().unwrap();
+            chip8.update_timers();
+        }
+        assert_eq!(chip8.profile().unwrap().key_wait_frames, 3);
+        assert_eq!(chip8.profile().unwrap().classes[&"FX0A"], 3);
+    }
+}
@@ -0,0 +1,89 @@
+// quirks -- the small behaviour differences between CHIP-8 interpreters
+// the names follow Timendus' quirks test, so you can check each one against it
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Quirks {
+    pub vf_reset: bool,         // 8XY1, 8XY2, 8XY3 reset VF to 0
+    pub memory_increment: MemoryIncrement, // how far FX55 and FX65 move I
+    pub display_wait: bool,     // DXYN waits for the next frame (vblank) before drawing
+    pub clipping: bool,         // sprites are clipped at the screen edge instead of wrapping round
+    pub shifting: bool,         // 8XY6 and 8XYE shift VX in place and ignore VY
+    pub jumping: bool,          // BXNN jumps to XNN + VX instead of BNNN jumping to NNN + V0
+}
+
+// what FX55 and FX65 leave in I after storing or loading V0 to VX
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MemoryIncrement {
+    Unchanged, // I is left alone (SUPER-CHIP)
+    ByX,       // I + X, one short of the last register (CHIP-48)
+    ByXPlusOne, // I + X + 1, just past the last register (the COSMAC VIP)
+}
+
+impl MemoryIncrement {
+    pub fn amount(&self, x: u8) -> u16 {
+        match self {
+            MemoryIncrement::Unchanged => 0,
+            MemoryIncrement::ByX => x as u16,
+            MemoryIncrement::ByXPlusOne => x as u16 + 1,
+        }
+    }
+}
+
+impl Quirks {
+    // the original interpreter on the COSMAC VIP
+    pub const COSMAC_VIP: Quirks = Quirks {
+        vf_reset: true,
+        memory_increment: MemoryIncrement::ByXPlusOne,
+        display_wait: true,
+        clipping: true,
+        shifting: false,
+        jumping: false,
+    };
+
+    // CHIP-48 on the HP-48 calculators
+    pub const CHIP_48: Quirks = Quirks {
+        vf_reset: false,
+        memory_increment: MemoryIncrement::ByX,
+        display_wait: false,
+        clipping: true,
+        shifting: true,
+        jumping: true,
+    };
+
+    // SUPER-CHIP 1.1, the version most SCHIP games were written for
+    pub const SUPER_CHIP: Quirks = Quirks {
+        vf_reset: false,
+        memory_increment: MemoryIncrement::Unchanged,
+        display_wait: false,
+        clipping: true,
+        shifting: true,
+        jumping: true,
+    };
+
+    // XO-CHIP as implemented by Octo
+    pub const XO_CHIP: Quirks = Quirks {
+        vf_reset: false,
+        memory_increment: MemoryIncrement::ByXPlusOne,
+        display_wait: false,
+        clipping: false,
+        shifting: false,
+        jumping: false,
+    };
+
+    // look up a preset by name, e.g. from a command line flag
+    pub fn preset(name: &str) -> Option<Quirks> {
+        match name.to_ascii_lowercase().as_str() {
+            "vip" | "cosmac" | "cosmac-vip" | "chip8" | "chip-8" => Some(Quirks::COSMAC_VIP),
+            "chip48" | "chip-48" => Some(Quirks::CHIP_48),
+            "schip" | "superchip" | "super-chip" => Some(Quirks::SUPER_CHIP),
+            "xochip" | "xo-chip" => Some(Quirks::XO_CHIP),
+            _ => None,
+        }
+    }
+}
+
+impl Default for Quirks {
+    fn default() -> Self {
+        Quirks::COSMAC_VIP
+    }
+}
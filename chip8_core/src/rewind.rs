@@ -0,0 +1,250 @@
+// rewind buffer -- the last few seconds of save states, so play can be stepped backwards frame by frame
+//
+// only the newest snapshot is kept whole. Each older one is stored as the difference from the one after it
+// (the two XORed together, then run-length encoded), which is mostly zeros as only a few bytes change per frame
+
+use crate::chip8::Chip8;
+
+use alloc::collections::VecDeque;
+use alloc::vec::Vec;
+
+#[derive(Debug)]
+pub struct Rewind {
+    capacity: usize,        // how many frames we can go back
+    latest: Vec<u8>,        // the newest snapshot, whole
+    deltas: VecDeque<Delta>, // oldest first, each one turns a snapshot into the one before it
+}
+
+#[derive(Debug)]
+enum Delta {
+    Xor(Vec<u8>),  // run-length encoded XOR against the following snapshot
+    Full(Vec<u8>), // the whole snapshot, when the two aren't the same size (e.g. switching to hi-res)
+}
+
+impl Rewind {
+    pub fn new(capacity: usize) -> Self {
+        Rewind { capacity, latest: Vec::new(), deltas: VecDeque::new() }
+    }
+
+    // how many frames we can step back
+    pub fn len(&self) -> usize {
+        self.deltas.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.deltas.is_empty()
+    }
+
+    pub fn clear(&mut self) {
+        self.latest.clear();
+        self.deltas.clear();
+    }
+
+    // record the machine as it is now, call once per frame
+    pub fn push(&mut self, chip8: &Chip8) {
+        let snapshot = chip8.save_state();
+        if !self.latest.is_empty() {
+            let delta = if snapshot.len() == self.latest.len() {
+                Delta::Xor(encode(&snapshot, &self.latest))
+            } else {
+                Delta::Full(core::mem::take(&mut self.latest))
+            };
+            self.deltas.push_back(delta);
+            if self.deltas.len() > self.capacity {
+                self.deltas.pop_front();
+            }
+        }
+        self.latest = snapshot;
+    }
+
+    // put the machine back one frame, false once there's nothing older left
+    pub fn step_back(&mut self, chip8: &mut Chip8) -> bool {
+        let Some(delta) = self.deltas.pop_back() else { return false };
+        match delta {
+            Delta::Xor(encoded) => decode_into(&encoded, &mut self.latest),
+            Delta::Full(snapshot) => self.latest = snapshot,
+        }
+        // these are our own snapshots, so they always load
+        chip8.load_state(&self.latest).is_ok()
+    }
+}
+
+// XOR the two snapshots and squash the result into (zero run, literal length, literal bytes) chunks
+// lengths are written as little endian base-128 varints
+fn encode(new: &[u8], old: &[u8]) -> Vec<u8> {
+    let mut out = Vec::new();
+    let mut i = 0;
+    while i < new.len() {
+        let zeros_start = i;
+        while i < new.len() && new[i] == old[i] {
+            i += 1;
+        }
+        let literal_start = i;
+        // a literal run ends at the next pair of matching bytes (a single one isn't worth breaking for)
+        while i < new.len() && !(new[i] == old[i] && (i + 1 == new.len() || new[i + 1] == old[i + 1])) {
+            i += 1;
+        }
+        write_varint(&mut out, literal_start - zeros_start);
+        write_varint(&mut out, i - literal_start);
+        out.extend(new[literal_start..i].iter().zip(&old[literal_start..i]).map(|(a, b)| a ^ b));
+    }
+    out
+}
+
+fn decode_into(encoded: &[u8], snapshot: &mut [u8]) {
+    let mut pos = 0;
+    let mut i = 0;
+    while pos < encoded.len() {
+        i += read_varint(encoded, &mut pos);
+        let literals = read_varint(encoded, &mut pos);
+        for byte in &encoded[pos..pos + literals] {
+            snapshot[i] ^= byte;
+            i += 1;
+        }
+        pos += literals;
+    }
+}
+
+fn write_varint(out: &mut Vec<u8>, mut value: usize) {
+    while value >= 0x80 {
+        out.push((value as u8 & 0x7F) | 0x80);
+        value >>= 7;
+    }
+    out.push(value as u8);
+}
+
+fn read_varint(data: &[u8], pos: &mut usize) -> usize {
+    let mut value = 0;
+    let mut shift = 0;
+    loop {
+        let byte = data[*pos];
+        *pos += 1;
+        value |= ((byte & 0x7F) as usize) << shift;
+        if byte & 0x80 == 0 {
+            return value;
+        }
+        shift += 7;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::platform::Platform;
+    use crate::quirks::Quirks;
+
+    fn round_trip(new: &[u8], old: &[u8]) -> Vec<u8> {
+        let encoded = encode(new, old);
+        let mut snapshot = old.to_vec();
+        decode_into(&encoded, &mut snapshot);
+        assert_eq!(snapshot, new);
+        encoded
+    }
+
+    #[test]
+    fn identical_snapshots_encode_to_one_zero_run() {
+        let snapshot: Vec<u8> = (0..1000).map(|i| i as u8).collect();
+        // 1000 zeros is a two byte varint, then no literals
+        assert_eq!(round_trip(&snapshot, &snapshot), [0xE8, 0x07, 0x00]);
+        assert!(round_trip(&[], &[]).is_empty());
+    }
+
+    #[test]
+    fn sparse_changes_stay_small() {
+        let old = alloc::vec![0x55; 4096];
+        let mut new = old.clone();
+        new[0] = 0x00;
+        new[300] = 0xAA;
+        new[301] = 0xAB;
+        new[4095] = 0x54;
+        let encoded = round_trip(&new, &old);
+        assert!(encoded.len() < 16, "{} bytes", encoded.len());
+        // a lone matching byte between changes stays in the literal run
+        new[303] = 0x00;
+        round_trip(&new, &old);
+    }
+
+    #[test]
+    fn fully_different_snapshots_round_trip() {
+        let old: Vec<u8> = (0..300).map(|i| i as u8).collect();
+        let new: Vec<u8> = old.iter().map(|byte| !byte).collect();
+        // no zeros, then all 300 bytes as literals (300 is a two byte varint)
+        assert_eq!(round_trip(&new, &old).len(), 1 + 2 + 300);
+    }
+
+    #[test]
+    fn varints_round_trip() {
+        for value in [0, 1, 0x7F, 0x80, 0x3FFF, 0x4000, usize::MAX >> 1] {
+            let mut out = Vec::new();
+            write_varint(&mut out, value);
+            let mut pos = 0;
+            assert_eq!(read_varint(&out, &mut pos), value);
+            assert_eq!(pos, out.len());
+        }
+    }
+
+    // 00FF (hi-res), then 6001, 6102, ... so each step changes a register
+    fn machine() -> Chip8 {
+        let mut chip8 = Chip8::new(Platform::SuperChip, Quirks::SUPER_CHIP);
+        chip8.load_program(&[0x00, 0xFF, 0x60, 0x01, 0x61, 0x02, 0x62, 0x03, 0x63, 0x04, 0x12, 0x0A]).unwrap();
+        chip8
+    }
+
+    #[test]
+    fn steps_back_through_every_frame() {
+        let mut chip8 = machine();
+        let mut rewind = Rewind::new(10);
+        let mut states = Vec::new();
+        for _ in 0..5 {
+            rewind.push(&chip8);
+            states.push(chip8.save_state());
+            chip8.run_cycle_once().unwrap();
+        }
+        assert_eq!(rewind.len(), 4);
+        // the first step is from lo-res to hi-res, so that delta is a whole snapshot
+        assert!(matches!(rewind.deltas[0], Delta::Full(_)));
+        assert!(rewind.deltas.iter().skip(1).all(|delta| matches!(delta, Delta::Xor(_))));
+
+        for expected in states.iter().rev().skip(1) {
+            assert!(rewind.step_back(&mut chip8));
+            assert_eq!(&chip8.save_state(), expected);
+        }
+        assert!(!chip8.display.is_hires());
+        assert!(rewind.is_empty());
+        assert!(!rewind.step_back(&mut chip8));
+        assert_eq!(&chip8.save_state(), &states[0]);
+    }
+
+    #[test]
+    fn forgets_the_oldest_frames_past_its_capacity() {
+        let mut chip8 = machine();
+        let mut rewind = Rewind::new(2);
+        let mut states = Vec::new();
+        for _ in 0..5 {
+            rewind.push(&chip8);
+            states.push(chip8.save_state());
+            chip8.run_cycle_once().unwrap();
+        }
+        assert_eq!(rewind.len(), 2);
+        assert!(rewind.step_back(&mut chip8));
+        assert_eq!(chip8.save_state(), states[3]);
+        assert!(rewind.step_back(&mut chip8));
+        assert_eq!(chip8.save_state(), states[2]);
+        assert!(!rewind.step_back(&mut chip8));
+        assert_eq!(chip8.save_state(), states[2]);
+    }
+
+    #[test]
+    fn clear_forgets_everything() {
+        let mut chip8 = machine();
+        let mut rewind = Rewind::new(4);
+        rewind.push(&chip8);
+        chip8.run_cycle_once().unwrap();
+        rewind.push(&chip8);
+        rewind.clear();
+        assert!(rewind.is_empty());
+        // the next push starts again rather than diffing against the forgotten snapshot
+        rewind.push(&chip8);
+        assert!(!rewind.step_back(&mut chip8));
+    }
+}
@@ -0,0 +1,87 @@
+// random number generator for CXNN
+// a tiny xorshift so the core doesn't need std or an outside crate, and so a seed always gives the same numbers
+// (not the COSMAC VIP's routine: that mixes in bytes of the VIP's own interpreter code, which we don't have a copy of)
+
+#[derive(Debug, Clone, Copy)]
+pub struct Rng {
+    state: u64,
+}
+
+impl Rng {
+    pub fn new(seed: u64) -> Self {
+        // xorshift gets stuck on zero, so mix the seed up first (splitmix64) and never let it be 0
+        let mut z = seed.wrapping_add(0x9E37_79B9_7F4A_7C15);
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        z ^= z >> 31;
+        Rng { state: if z == 0 { 1 } else { z } }
+    }
+
+    // the raw state, so save states can carry on the same sequence
+    pub fn state(&self) -> u64 {
+        self.state
+    }
+
+    pub fn from_state(state: u64) -> Self {
+        Rng { state: if state == 0 { 1 } else { state } }
+    }
+
+    pub fn next_u8(&mut self) -> u8 {
+        // xorshift64*
+        self.state ^= self.state >> 12;
+        self.state ^= self.state << 25;
+        self.state ^= self.state >> 27;
+        (self.state.wrapping_mul(0x2545_F491_4F6C_DD1D) >> 56) as u8
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chip8::Chip8;
+    use crate::platform::Platform;
+    use crate::quirks::Quirks;
+
+    use alloc::vec::Vec;
+
+    // CXFF into every register, then CX0F into every register
+    fn cxnn_sequence(seed: u64) -> Vec<u8> {
+        let mut chip8 = Chip8::new(Platform::Chip8, Quirks::CHIP_48);
+        let program: Vec<u8> = (0..16).flat_map(|x| [0xC0 | x, 0xFF]).chain((0..16).flat_map(|x| [0xC0 | x, 0x0F])).collect();
+        chip8.load_program(&program).unwrap();
+        chip8.seed_rng(seed);
+        let mut values = Vec::new();
+        for _ in 0..2 {
+            for _ in 0..16 {
+                chip8.run_cycle_once().unwrap();
+            }
+            values.extend_from_slice(&chip8.v_reg);
+        }
+        values
+    }
+
+    #[test]
+    fn the_same_seed_gives_the_same_cxnn_sequence() {
+        let values = cxnn_sequence(1234);
+        assert_eq!(values, cxnn_sequence(1234));
+        assert_ne!(values, cxnn_sequence(1235));
+        // NN masks the random byte
+        assert!(values[16..].iter().all(|&value| value <= 0x0F));
+        // it isn't stuck on one value
+        assert!(values[..16].iter().any(|&value| value != values[0]));
+    }
+
+    #[test]
+    fn carries_on_from_a_saved_state() {
+        let mut rng = Rng::new(0);
+        for _ in 0..10 {
+            rng.next_u8();
+        }
+        let mut copy = Rng::from_state(rng.state());
+        for _ in 0..100 {
+            assert_eq!(rng.next_u8(), copy.next_u8());
+        }
+        // zero would get xorshift stuck
+        assert_ne!(Rng::from_state(0).state(), 0);
+    }
+}
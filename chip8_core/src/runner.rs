@@ -0,0 +1,33 @@
+// running with no window or sound -- the frame loop chip8-headless and chip8 --headless share
+// a frame is the same as in the app (the movie's inputs, then the instructions, then the timers),
+// so a recording plays back the same whichever one runs it
+
+use crate::chip8::Chip8;
+use crate::movie::Playback;
+
+pub struct Run {
+    pub frames: u64,           // stop once the frame count reaches this
+    pub cycles: u64,           // or once this many instructions have run
+    pub cycles_per_frame: u32,
+}
+
+impl Run {
+    // runs until a limit is reached, the program faults or it exits (00FD), calling after_frame once each frame
+    // (for writing a trace as it goes). returns how many instructions ran
+    pub fn run(&self, chip8: &mut Chip8, mut playback: Option<&mut Playback>, mut after_frame: impl FnMut(&mut Chip8)) -> u64 {
+        let mut cycles = 0;
+        'frames: while chip8.frame_count() < self.frames {
+            if let Some(playback) = &mut playback {
+                playback.apply(chip8);
+            }
+            for _ in 0..self.cycles_per_frame {
+                if cycles == self.cycles || chip8.exited {break 'frames}
+                cycles += 1;
+                if chip8.run_cycle_once().is_err() {break 'frames}
+            }
+            chip8.update_timers();
+            after_frame(chip8);
+        }
+        cycles
+    }
+}
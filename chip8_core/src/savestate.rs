@@ -0,0 +1,369 @@
+// save states -- a snapshot of the whole machine as bytes, so it can be written to disk and loaded back later
+//
+// the format (all numbers big endian, like CHIP-8 itself):
+//   "C8SS", version (u16)
+//   platform (u8), quirks (u8, one bit each in the order they're declared, then bit 6 for memory_increment by X)
+//   error policy (u8), ROM loaded (u8), ROM SHA-1 (20, zeros if none)
+//   load address (u32), memory size (u32), memory
+//   hi-res (u8), pixel count (u32), pixels (one byte each, a bit per plane)
+//   PC (u16), I (u16), stack (16 x u16), stack pointer (u8), V0-VF
+//   delay timer (u8), sound timer (u8)
+//   keypad (u16, bit n is key n), key being waited on (u8, 0xFF for none), vblank (u8)
+//   RPL flags (16), exited (u8), planes (u8), audio pattern set (u8), audio pattern (16), pitch (u8)
+//   RNG seed (u64), RNG state (u64), frame count (u64)
+//
+// bump STATE_VERSION whenever this changes, old states are refused rather than misread
+// a state saved with a different ROM is refused too, it would carry on running the other ROM's code
+
+use crate::chip8::Chip8;
+use crate::error::{Chip8Error, ErrorPolicy};
+use crate::memory::Memory;
+use crate::platform::Platform;
+use crate::quirks::{MemoryIncrement, Quirks};
+use crate::rng::Rng;
+
+use alloc::vec::Vec;
+
+const MAGIC: &[u8; 4] = b"C8SS";
+pub const STATE_VERSION: u16 = 3;
+
+impl Chip8 {
+    pub fn save_state(&self) -> Vec<u8> {
+        let mut out = Vec::with_capacity(self.memory.size() + self.display.pixels().len() + 128);
+        out.extend_from_slice(MAGIC);
+        out.extend_from_slice(&STATE_VERSION.to_be_bytes());
+
+        out.push(match self.platform {
+            Platform::Chip8 => 0,
+            Platform::SuperChip => 1,
+            Platform::XoChip => 2,
+        });
+        out.push(quirks_to_bits(self.quirks));
+        out.push(match self.error_policy {
+            ErrorPolicy::Halt => 0,
+            ErrorPolicy::Ignore => 1,
+            ErrorPolicy::Wrap => 2,
+        });
+        out.push(self.rom_hash.is_some() as u8);
+        out.extend_from_slice(&self.rom_hash.unwrap_or([0; 20]));
+
+        out.extend_from_slice(&(self.memory.load_address() as u32).to_be_bytes());
+        out.extend_from_slice(&(self.memory.size() as u32).to_be_bytes());
+        out.extend_from_slice(&self.memory.data);
+
+        out.push(self.display.is_hires() as u8);
+        out.extend_from_slice(&(self.display.pixels().len() as u32).to_be_bytes());
+        out.extend_from_slice(self.display.pixels());
+
+        out.extend_from_slice(&self.program_counter.to_be_bytes());
+        out.extend_from_slice(&self.index.to_be_bytes());
+        for address in self.stack {
+            out.extend_from_slice(&address.to_be_bytes());
+        }
+        out.push(self.stack_pointer);
+        out.extend_from_slice(&self.v_reg);
+
+        out.push(self.delay_timer.current_time);
+        out.push(self.sound_timer.current_time);
+
+        let keys = self.keypad.iter().enumerate().fold(0u16, |keys, (i, &down)| keys | ((down as u16) << i));
+        out.extend_from_slice(&keys.to_be_bytes());
+        out.push(self.temp_key.unwrap_or(0xFF));
+        out.push(self.vblank as u8);
+
+        out.extend_from_slice(&self.rpl);
+        out.push(self.exited as u8);
+        out.push(self.planes);
+        out.push(self.audio_pattern.is_some() as u8);
+        out.extend_from_slice(&self.audio_pattern.unwrap_or([0; 16]));
+        out.push(self.pitch);
+
+        out.extend_from_slice(&self.seed.to_be_bytes());
+        out.extend_from_slice(&self.rng.state().to_be_bytes());
+        out.extend_from_slice(&self.frame_count.to_be_bytes());
+        out
+    }
+
+    // replaces the whole machine with the saved one (including its platform, quirks and error policy)
+    // nothing is changed if the state can't be read, or was saved with a different ROM to the one loaded
+    pub fn load_state(&mut self, state: &[u8]) -> Result<(), Chip8Error> {
+        let mut reader = Reader { data: state };
+
+        if reader.take(4)? != MAGIC {
+            return Err(Chip8Error::InvalidSaveState("not a save state"));
+        }
+        if reader.u16()? != STATE_VERSION {
+            return Err(Chip8Error::InvalidSaveState("saved by a different version"));
+        }
+
+        let platform = match reader.u8()? {
+            0 => Platform::Chip8,
+            1 => Platform::SuperChip,
+            2 => Platform::XoChip,
+            _ => return Err(Chip8Error::InvalidSaveState("unknown platform")),
+        };
+        let quirks = quirks_from_bits(reader.u8()?);
+        let error_policy = match reader.u8()? {
+            0 => ErrorPolicy::Halt,
+            1 => ErrorPolicy::Ignore,
+            2 => ErrorPolicy::Wrap,
+            _ => return Err(Chip8Error::InvalidSaveState("unknown error policy")),
+        };
+        let has_rom = reader.u8()? != 0;
+        let mut hash = [0; 20];
+        hash.copy_from_slice(reader.take(20)?);
+        let rom_hash = if has_rom {Some(hash)} else {None};
+        // with no ROM loaded yet, any state will do
+        if self.rom_hash.is_some() && rom_hash != self.rom_hash {
+            return Err(Chip8Error::InvalidSaveState("saved with a different ROM"));
+        }
+
+        let load_address = reader.u32()? as usize;
+        let memory_size = reader.u32()? as usize;
+        let saved_memory = reader.take(memory_size)?;
+        if Memory::check(memory_size, load_address).is_err() {
+            return Err(Chip8Error::InvalidSaveState("corrupt save state"));
+        }
+        let mut memory = Memory::new(memory_size, load_address);
+        memory.data.copy_from_slice(saved_memory);
+
+        let hires = reader.u8()? != 0;
+        let pixel_count = reader.u32()? as usize;
+        let pixels = reader.take(pixel_count)?.to_vec();
+
+        let program_counter = reader.u16()?;
+        let index = reader.u16()?;
+        let mut stack = [0; 16];
+        for address in stack.iter_mut() {
+            *address = reader.u16()?;
+        }
+        let stack_pointer = reader.u8()?;
+        let mut v_reg = [0; 16];
+        v_reg.copy_from_slice(reader.take(16)?);
+
+        let delay = reader.u8()?;
+        let sound = reader.u8()?;
+
+        let keys = reader.u16()?;
+        let temp_key = reader.u8()?;
+        let vblank = reader.u8()? != 0;
+
+        let mut rpl = [0; 16];
+        rpl.copy_from_slice(reader.take(16)?);
+        let exited = reader.u8()? != 0;
+        let planes = reader.u8()?;
+        let has_pattern = reader.u8()? != 0;
+        let mut pattern = [0; 16];
+        pattern.copy_from_slice(reader.take(16)?);
+        let pitch = reader.u8()?;
+
+        let seed = reader.u64()?;
+        let rng_state = reader.u64()?;
+        let frame_count = reader.u64()?;
+
+        // sanity checks, so a corrupt state can't make the CPU index out of bounds later
+        let expected_pixels = if hires {128 * 64} else {64 * 32};
+        if pixel_count != expected_pixels || stack_pointer > 16 || !reader.data.is_empty() {
+            return Err(Chip8Error::InvalidSaveState("corrupt save state"));
+        }
+
+        // everything read fine, so now swap it all in
+        self.platform = platform;
+        self.quirks = quirks;
+        self.error_policy = error_policy;
+        self.rom_hash = rom_hash;
+        self.memory = memory;
+        self.display.restore(hires, pixels);
+        self.program_counter = program_counter;
+        self.index = index;
+        self.stack = stack;
+        self.stack_pointer = stack_pointer;
+        self.v_reg = v_reg;
+        self.delay_timer.current_time = delay;
+        self.sound_timer.current_time = sound;
+        for (i, key) in self.keypad.iter_mut().enumerate() {
+            *key = keys & (1 << i) != 0;
+        }
+        self.temp_key = if temp_key == 0xFF {None} else {Some(temp_key & 0xF)};
+        self.vblank = vblank;
+        self.rpl = rpl;
+        self.exited = exited;
+        self.planes = planes & 0x3;
+        self.audio_pattern = if has_pattern {Some(pattern)} else {None};
+        self.pitch = pitch;
+        self.seed = seed;
+        self.rng = Rng::from_state(rng_state);
+        self.frame_count = frame_count;
+        self.fault = None;
+        Ok(())
+    }
+}
+
+fn quirks_to_bits(quirks: Quirks) -> u8 {
+    let by_x = quirks.memory_increment == MemoryIncrement::ByX;
+    let increment = quirks.memory_increment != MemoryIncrement::Unchanged;
+    [quirks.vf_reset, increment, quirks.display_wait, quirks.clipping, quirks.shifting, quirks.jumping, by_x]
+        .iter()
+        .enumerate()
+        .fold(0, |bits, (i, &on)| bits | ((on as u8) << i))
+}
+
+fn quirks_from_bits(bits: u8) -> Quirks {
+    let bit = |i: u8| bits & (1 << i) != 0;
+    Quirks {
+        vf_reset: bit(0),
+        memory_increment: match (bit(1), bit(6)) {
+            (false, _) => MemoryIncrement::Unchanged,
+            (true, false) => MemoryIncrement::ByXPlusOne,
+            (true, true) => MemoryIncrement::ByX,
+        },
+        display_wait: bit(2),
+        clipping: bit(3),
+        shifting: bit(4),
+        jumping: bit(5),
+    }
+}
+
+// walks through the saved bytes, running out is an error rather than a panic
+struct Reader<'a> {
+    data: &'a [u8],
+}
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, len: usize) -> Result<&'a [u8], Chip8Error> {
+        if self.data.len() < len {
+            return Err(Chip8Error::InvalidSaveState("save state is cut short"));
+        }
+        let (taken, rest) = self.data.split_at(len);
+        self.data = rest;
+        Ok(taken)
+    }
+
+    fn u8(&mut self) -> Result<u8, Chip8Error> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn u16(&mut self) -> Result<u16, Chip8Error> {
+        let bytes = self.take(2)?;
+        Ok(u16::from_be_bytes([bytes[0], bytes[1]]))
+    }
+
+    fn u32(&mut self) -> Result<u32, Chip8Error> {
+        let mut bytes = [0; 4];
+        bytes.copy_from_slice(self.take(4)?);
+        Ok(u32::from_be_bytes(bytes))
+    }
+
+    fn u64(&mut self) -> Result<u64, Chip8Error> {
+        let mut bytes = [0; 8];
+        bytes.copy_from_slice(self.take(8)?);
+        Ok(u64::from_be_bytes(bytes))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::quirks::Quirks;
+
+    // CXNN into V0..V3, draw with them, loop
+    const PROGRAM: [u8; 14] = [0xC0, 0xFF, 0xC1, 0x3F, 0xC2, 0x1F, 0xA2, 0x00, 0xD1, 0x24, 0x70, 0x01, 0x12, 0x00];
+
+    fn machine() -> Chip8 {
+        let mut chip8 = Chip8::new(Platform::Chip8, Quirks::CHIP_48);
+        chip8.seed_rng(1234);
+        chip8.load_program(&PROGRAM).unwrap();
+        chip8
+    }
+
+    fn run_frames(chip8: &mut Chip8, frames: usize) {
+        for _ in 0..frames {
+            for _ in 0..10 {
+                chip8.run_cycle_once().unwrap();
+            }
+            chip8.update_timers();
+        }
+    }
+
+    #[test]
+    fn round_trip_carries_on_the_same_run() {
+        let mut chip8 = machine();
+        chip8.update_keypad(5, true);
+        run_frames(&mut chip8, 30);
+        let state = chip8.save_state();
+        run_frames(&mut chip8, 30);
+        let expected = chip8.save_state();
+
+        let mut loaded = Chip8::new(Platform::XoChip, Quirks::XO_CHIP);
+        loaded.load_state(&state).unwrap();
+        assert_eq!(loaded.save_state(), state);
+        assert_eq!(loaded.frame_count(), 30);
+        assert_eq!(loaded.rng_seed(), 1234);
+        assert_eq!(loaded.platform(), Platform::Chip8);
+        assert_eq!(loaded.quirks(), Quirks::CHIP_48);
+        assert!(loaded.keypad()[5]);
+        // the RNG and frame count pick up where they were, so CXNN gives the same numbers
+        run_frames(&mut loaded, 30);
+        assert_eq!(loaded.save_state(), expected);
+    }
+
+    #[test]
+    fn refuses_a_different_version() {
+        let mut state = machine().save_state();
+        state[4..6].copy_from_slice(&(STATE_VERSION - 1).to_be_bytes());
+        assert_eq!(machine().load_state(&state), Err(Chip8Error::InvalidSaveState("saved by a different version")));
+    }
+
+    #[test]
+    fn refuses_something_that_isnt_a_state() {
+        assert_eq!(machine().load_state(b"not a save state"), Err(Chip8Error::InvalidSaveState("not a save state")));
+        assert_eq!(machine().load_state(&[]), Err(Chip8Error::InvalidSaveState("save state is cut short")));
+    }
+
+    #[test]
+    fn truncated_state_leaves_the_machine_alone() {
+        let state = machine().save_state();
+        let mut chip8 = machine();
+        run_frames(&mut chip8, 5);
+        let before = chip8.save_state();
+        for len in [6, 20, state.len() / 2, state.len() - 1] {
+            assert_eq!(chip8.load_state(&state[..len]), Err(Chip8Error::InvalidSaveState("save state is cut short")));
+            assert_eq!(chip8.save_state(), before);
+        }
+    }
+
+    #[test]
+    fn refuses_a_state_from_a_different_rom() {
+        let state = machine().save_state();
+        let mut other = Chip8::new(Platform::Chip8, Quirks::CHIP_48);
+        other.load_program(&[0x12, 0x00]).unwrap();
+        let before = other.save_state();
+        assert_eq!(other.load_state(&state), Err(Chip8Error::InvalidSaveState("saved with a different ROM")));
+        assert_eq!(other.save_state(), before);
+        // a machine with no ROM takes the state's
+        let mut empty = Chip8::new(Platform::Chip8, Quirks::CHIP_48);
+        empty.load_state(&state).unwrap();
+        assert_eq!(empty.rom_hash(), machine().rom_hash());
+        assert_eq!(other.load_state(&empty.save_state()), Err(Chip8Error::InvalidSaveState("saved with a different ROM")));
+    }
+
+    #[test]
+    fn carries_the_error_policy() {
+        let mut chip8 = machine();
+        chip8.set_error_policy(ErrorPolicy::Wrap);
+        let state = chip8.save_state();
+        let mut loaded = machine();
+        loaded.load_state(&state).unwrap();
+        assert_eq!(loaded.error_policy, ErrorPolicy::Wrap);
+        let mut state = state;
+        state[8] = 3;
+        assert_eq!(loaded.load_state(&state), Err(Chip8Error::InvalidSaveState("unknown error policy")));
+    }
+
+    #[test]
+    fn extra_bytes_are_corrupt() {
+        let mut state = machine().save_state();
+        state.push(0);
+        assert_eq!(machine().load_state(&state), Err(Chip8Error::InvalidSaveState("corrupt save state")));
+    }
+}
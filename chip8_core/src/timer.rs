@@ -7,11 +7,9 @@ pub struct Timer {
 
 impl Timer {
     pub fn new(start_time: u8) -> Self {
-        
-        let t = Self {
+        Self {
             current_time: start_time,
-        };
-        return t;
+        }
     }
     
     pub fn tick(&mut self) {
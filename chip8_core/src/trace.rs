@@ -0,0 +1,160 @@
+// execution trace -- one line per instruction, for diffing against other emulators
+//
+// the format (version 1) is plain text, one instruction per line, hex in lower case and fixed width columns:
+//
+//   # chip8 trace 1
+//   #    cycle  pc    op    instruction               i       vf     changes
+//           42  0204  f055  save v0                   i=0301  vf=00  v0=05>06
+//
+// cycle counts every instruction since tracing started (including ones outside the range, so the numbers line up
+// between a filtered and an unfiltered trace), pc and opcode are from before it ran (4 byte instructions show the
+// first 2), the instruction is Octo syntax, i and vf are after it ran, and changes lists the V registers it
+// changed as old>new, then "fault: ..." if it faulted
+
+use crate::chip8::Chip8;
+use crate::disasm::{self, Syntax};
+use crate::error::Chip8Error;
+
+use alloc::collections::VecDeque;
+use alloc::format;
+use alloc::string::String;
+use alloc::vec::Vec;
+
+pub const HEADER: &str = "# chip8 trace 1\n#    cycle  pc    op    instruction               i       vf     changes\n";
+
+#[derive(Debug, Clone)]
+pub struct Trace {
+    range: Option<(usize, usize)>, // only instructions at these addresses (inclusive), all of them if None
+    last: Option<usize>,           // ring buffer mode: keep only the last N lines (e.g. to dump on a fault)
+    cycle: u64,
+    lines: VecDeque<String>,
+}
+
+// what an instruction looked like before it ran
+pub(crate) struct Pending {
+    cycle: u64,
+    pc: u16,
+    opcode: u16,
+    text: String,
+    registers: [u8; 16],
+}
+
+impl Trace {
+    pub fn new(range: Option<(usize, usize)>, last: Option<usize>) -> Self {
+        Trace { range, last, cycle: 0, lines: VecDeque::new() }
+    }
+
+    // the lines so far (or the last N of them), without the header
+    pub fn take_lines(&mut self) -> Vec<String> {
+        self.lines.drain(..).collect()
+    }
+
+    // called by run_cycle_once before it runs an instruction, None if it isn't wanted
+    pub(crate) fn begin(&mut self, chip8: &Chip8) -> Option<Pending> {
+        self.cycle += 1;
+        let pc = chip8.program_counter;
+        if let Some((start, end)) = self.range {
+            if !(start..=end).contains(&(pc as usize)) {
+                return None;
+            }
+        }
+        // disassembled now, the instruction might overwrite itself
+        let instruction = disasm::disassemble(&chip8.memory, pc as usize, chip8.platform, Syntax::Octo);
+        Some(Pending { cycle: self.cycle, pc, opcode: instruction.opcode, text: instruction.text, registers: chip8.v_reg })
+    }
+
+    pub(crate) fn finish(&mut self, pending: Pending, chip8: &Chip8, fault: Option<Chip8Error>) {
+        let mut changes: Vec<String> = pending.registers.iter().zip(chip8.v_reg.iter()).enumerate()
+            .filter(|(_, (before, after))| before != after)
+            .map(|(x, (before, after))| format!("v{:x}={:02x}>{:02x}", x, before, after))
+            .collect();
+        if let Some(err) = fault {
+            changes.push(format!("fault: {}", err));
+        }
+        let line = format!(
+            "{:>10}  {:04x}  {:04x}  {:<24}  i={:04x}  vf={:02x}  {}",
+            pending.cycle, pending.pc, pending.opcode, pending.text, chip8.index, chip8.v_reg[0xF], changes.join(" "),
+        );
+        self.lines.push_back(String::from(line.trim_end()));
+        if let Some(last) = self.last {
+            while self.lines.len() > last {
+                self.lines.pop_front();
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::platform::Platform;
+    use crate::quirks::Quirks;
+
+    use alloc::vec;
+
+    // v0 := 5, v0 += 1, i := 0x300, v1 := v0, then loop
+    fn traced(trace: Trace) -> Chip8 {
+        let mut chip8 = Chip8::new(Platform::Chip8, Quirks::CHIP_48);
+        chip8.load_program(&[0x60, 0x05, 0x70, 0x01, 0xA3, 0x00, 0x81, 0x00, 0x12, 0x08]).unwrap();
+        chip8.set_trace(Some(trace));
+        chip8
+    }
+
+    fn run(chip8: &mut Chip8, cycles: usize) -> Vec<String> {
+        for _ in 0..cycles {
+            chip8.run_cycle_once().unwrap();
+        }
+        chip8.trace_mut().unwrap().take_lines()
+    }
+
+    #[test]
+    fn one_line_per_instruction() {
+        let mut chip8 = traced(Trace::new(None, None));
+        assert_eq!(run(&mut chip8, 5), vec![
+            "         1  0200  6005  v0 := 0x05                i=0000  vf=00  v0=00>05",
+            "         2  0202  7001  v0 += 0x01                i=0000  vf=00  v0=05>06",
+            "         3  0204  a300  i := 0x300                i=0300  vf=00",
+            "         4  0206  8100  v1 := v0                  i=0300  vf=00  v1=00>06",
+            "         5  0208  1208  jump 0x208                i=0300  vf=00",
+        ]);
+        // take_lines empties it, and the cycle count carries on
+        assert_eq!(run(&mut chip8, 1), vec!["         6  0208  1208  jump 0x208                i=0300  vf=00"]);
+        assert!(HEADER.starts_with("# chip8 trace 1\n"));
+    }
+
+    #[test]
+    fn a_range_keeps_the_cycle_numbers() {
+        let mut chip8 = traced(Trace::new(Some((0x202, 0x204)), None));
+        let lines = run(&mut chip8, 5);
+        assert_eq!(lines.len(), 2);
+        assert!(lines[0].starts_with("         2  0202"));
+        assert!(lines[1].starts_with("         3  0204"));
+    }
+
+    #[test]
+    fn the_ring_buffer_keeps_the_last_lines() {
+        let mut chip8 = traced(Trace::new(None, Some(2)));
+        let lines = run(&mut chip8, 7);
+        assert_eq!(lines, vec![
+            "         6  0208  1208  jump 0x208                i=0300  vf=00",
+            "         7  0208  1208  jump 0x208                i=0300  vf=00",
+        ]);
+        // wrapping round again after a take
+        let lines = run(&mut chip8, 3);
+        assert_eq!(lines.len(), 2);
+        assert!(lines[0].starts_with("         9  ") && lines[1].starts_with("        10  "));
+    }
+
+    #[test]
+    fn a_fault_is_the_last_change() {
+        // return with nothing on the stack
+        let mut chip8 = Chip8::new(Platform::Chip8, Quirks::CHIP_48);
+        chip8.load_program(&[0x00, 0xEE]).unwrap();
+        chip8.set_trace(Some(Trace::new(None, None)));
+        let err = chip8.run_cycle_once().unwrap_err();
+        let lines = chip8.trace_mut().unwrap().take_lines();
+        assert_eq!(lines.len(), 1);
+        assert!(lines[0].starts_with("         1  0200  00ee  return"));
+        assert!(lines[0].ends_with(&format!("vf=00  fault: {}", err)), "{}", lines[0]);
+    }
+}
@@ -0,0 +1,104 @@
+// watchpoints -- stop when an instruction reads or writes a range of memory
+// checked in Chip8's read_byte / write_byte, so they see every data access an instruction makes (not the fetches)
+
+use crate::error::Chip8Error;
+use crate::number::parse_number;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Watchpoint {
+    pub start: usize,
+    pub end: usize, // inclusive
+    pub read: bool,
+    pub write: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Access {
+    Read,
+    Write,
+}
+
+// the first watched access an instruction made
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct WatchHit {
+    pub address: usize,
+    pub access: Access,
+    pub value: u8, // the byte read, or the byte written
+}
+
+impl Watchpoint {
+    // an address or an inclusive range, e.g. "0x300" or "0x300-0x3FF"
+    pub fn parse(range: &str, read: bool, write: bool) -> Result<Watchpoint, Chip8Error> {
+        let (start, end) = parse_range(range)?;
+        Ok(Watchpoint { start, end, read, write })
+    }
+
+    pub fn matches(&self, address: usize, access: Access) -> bool {
+        let wanted = match access {
+            Access::Read => self.read,
+            Access::Write => self.write,
+        };
+        wanted && (self.start..=self.end).contains(&address)
+    }
+}
+
+// an address or an inclusive range, e.g. "0x300" or "0x300-0x3FF" (also used for trace ranges)
+pub fn parse_range(range: &str) -> Result<(usize, usize), Chip8Error> {
+    let bad = Chip8Error::InvalidExpression("expected an address or a range like 0x300-0x3FF");
+    let (start, end) = match range.split_once('-') {
+        Some((start, end)) => (parse_address(start).ok_or(bad)?, parse_address(end).ok_or(bad)?),
+        None => {
+            let address = parse_address(range).ok_or(bad)?;
+            (address, address)
+        },
+    };
+    if end < start {
+        return Err(Chip8Error::InvalidExpression("the range ends before it starts"));
+    }
+    Ok((start, end))
+}
+
+// hex with 0x or # in front, otherwise anything parse_number takes
+pub(crate) fn parse_address(text: &str) -> Option<usize> {
+    let text = text.trim();
+    match text.strip_prefix('#') {
+        Some(hex) => usize::from_str_radix(hex, 16).ok(),
+        None => parse_number(text).and_then(|n| usize::try_from(n).ok()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_addresses_and_ranges() {
+        assert_eq!(parse_range("0x300"), Ok((0x300, 0x300)));
+        assert_eq!(parse_range("0x300-0x3FF"), Ok((0x300, 0x3FF)));
+        assert_eq!(parse_range(" #2A0 - 700 "), Ok((0x2A0, 700)));
+        assert_eq!(parse_address("0b101"), Some(5));
+        assert_eq!(parse_address("#zz"), None);
+    }
+
+    #[test]
+    fn bad_ranges() {
+        let bad = Err(Chip8Error::InvalidExpression("expected an address or a range like 0x300-0x3FF"));
+        assert_eq!(parse_range(""), bad);
+        assert_eq!(parse_range("0x300-"), bad);
+        assert_eq!(parse_range("here"), bad);
+        assert_eq!(parse_range("0x3FF-0x300"), Err(Chip8Error::InvalidExpression("the range ends before it starts")));
+        assert_eq!(Watchpoint::parse("0x3FF-0x300", true, true), Err(Chip8Error::InvalidExpression("the range ends before it starts")));
+    }
+
+    #[test]
+    fn matches_its_range_and_access() {
+        let watchpoint = Watchpoint::parse("0x300-0x30F", false, true).unwrap();
+        assert!(watchpoint.matches(0x300, Access::Write));
+        assert!(watchpoint.matches(0x30F, Access::Write));
+        assert!(!watchpoint.matches(0x310, Access::Write));
+        assert!(!watchpoint.matches(0x2FF, Access::Write));
+        assert!(!watchpoint.matches(0x300, Access::Read));
+        let watchpoint = Watchpoint::parse("0x300", true, true).unwrap();
+        assert!(watchpoint.matches(0x300, Access::Read) && watchpoint.matches(0x300, Access::Write));
+    }
+}
@@ -0,0 +1,49 @@
+// looks up the checked in test ROMs by their SHA-1 in tests/data/programs.json, which is laid out like the
+// community database's file, so the whole path from ROM bytes to platform and quirks is covered
+//
+// the hashes are of the ROMs chip8 asm builds from tests/roms, so a change to either source means updating them there
+
+use std::{fs, path::PathBuf};
+use chip8_core::{assemble, hash, Database, MemoryIncrement, Platform, Quirks};
+
+fn database() -> Database {
+    let path = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("tests/data/programs.json");
+    Database::parse(&fs::read_to_string(&path).unwrap()).unwrap_or_else(|err| panic!("{}: {}", path.display(), err))
+}
+
+fn sha1(file: &str) -> [u8; 20] {
+    let path = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("tests/roms").join(file);
+    let assembly = assemble(&fs::read_to_string(&path).unwrap()).unwrap_or_else(|err| panic!("{}: {}", path.display(), err));
+    hash::sha1(&assembly.rom)
+}
+
+#[test]
+fn finds_a_vip_rom() {
+    let database = database();
+    let info = database.lookup(&sha1("opcodes.8o")).expect("opcodes.8o isn't in tests/data/programs.json, has its hash changed?");
+    assert_eq!(info.title, "opcodes");
+    assert_eq!(info.platform, Platform::Chip8);
+    assert_eq!(info.quirks, Quirks::COSMAC_VIP);
+    assert_eq!(info.cycles_per_frame, None);
+    assert!(info.keys.is_empty() && info.colors.is_empty());
+}
+
+#[test]
+fn finds_a_superchip_rom_with_its_quirks_and_settings() {
+    let database = database();
+    let info = database.lookup(&sha1("quirks.8o")).expect("quirks.8o isn't in tests/data/programs.json, has its hash changed?");
+    assert_eq!(info.title, "quirks");
+    // megachip8 comes first but can't be run, so it's the next one
+    assert_eq!(info.platform, Platform::SuperChip);
+    assert_eq!(info.quirks, Quirks { shifting: false, display_wait: true, ..Quirks::SUPER_CHIP });
+    assert_eq!(info.quirks.memory_increment, MemoryIncrement::Unchanged);
+    assert_eq!(info.cycles_per_frame, Some(30));
+    assert_eq!(info.keys.get("up"), Some(&5));
+    assert_eq!(info.keys.get("a"), Some(&6));
+    assert_eq!(info.colors, [0x101020, 0xE0E0FF]);
+}
+
+#[test]
+fn misses_an_unknown_rom() {
+    assert!(database().lookup(&hash::sha1(&[0x12, 0x00])).is_none());
+}
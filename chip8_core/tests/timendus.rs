@@ -0,0 +1,133 @@
+// regression tests against Timendus' CHIP-8 test suite (rom/timendus_test_suite, a git submodule)
+// and tests/roms/opcodes.8o and quirks.8o, which are checked in so there's always something to compare
+//
+// each ROM runs for a fixed number of cycles, then the screen is compared with a golden image in tests/golden
+// (one line per row, . for off, # plane 1, + plane 2, @ both -- the same as chip8-headless --ascii)
+//
+// Timendus' ROMs aren't checked in. build.rs turns on the timendus_suite cfg when the submodule is there,
+// and then those tests run with the rest; without it they're ignored (and fail if they're run anyway):
+//   git submodule update --init rom/timendus_test_suite
+//   cargo test -p chip8_core --test timendus
+// after a deliberate change to what a test shows (or to create a missing one), look at the new screens
+// and bless them with:
+//   UPDATE_GOLDEN=1 cargo test -p chip8_core --test timendus -- --include-ignored
+
+use std::{env, fs, path::PathBuf};
+use chip8_core::{assemble, Chip8, Platform, Quirks};
+
+const CYCLES_PER_FRAME: usize = 10;
+const NEEDS_SUITE: &str = "needs rom/timendus_test_suite (git submodule update --init rom/timendus_test_suite)";
+
+fn suite_path(file: &str) -> PathBuf {
+    PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("../rom/timendus_test_suite/bin").join(file)
+}
+
+fn golden_path(name: &str) -> PathBuf {
+    PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("tests/golden").join(format!("{}.txt", name))
+}
+
+// `platform_byte` goes in 0x1FF, which the quirks test reads to skip its menu
+fn check(name: &str, file: &str, platform: Platform, quirks: Quirks, platform_byte: Option<u8>, cycles: usize) {
+    let path = suite_path(file);
+    let rom = fs::read(&path).unwrap_or_else(|_| panic!("{}: {} not found, this test {}", name, path.display(), NEEDS_SUITE));
+    check_rom(name, &rom, platform, quirks, platform_byte, cycles);
+}
+
+fn check_rom(name: &str, rom: &[u8], platform: Platform, quirks: Quirks, platform_byte: Option<u8>, cycles: usize) {
+    let mut chip8 = Chip8::new(platform, quirks);
+    chip8.load_program(rom).unwrap();
+    if let Some(byte) = platform_byte {
+        // the byte just before the program, so it's poked in rather than loaded
+        chip8.memory_mut().write_byte(0x1FF, byte);
+    }
+
+    for cycle in 0..cycles {
+        if let Err(err) = chip8.run_cycle_once() {
+            panic!("{}: halted at {:#05X}: {}", name, chip8.program_counter(), err);
+        }
+        if cycle % CYCLES_PER_FRAME == CYCLES_PER_FRAME - 1 {
+            chip8.update_timers();
+        }
+    }
+
+    let screen = chip8.display.to_ascii();
+    let golden = golden_path(name);
+    if env::var_os("UPDATE_GOLDEN").is_some() {
+        fs::create_dir_all(golden.parent().unwrap()).unwrap();
+        fs::write(&golden, &screen).unwrap();
+        return;
+    }
+    let expected = fs::read_to_string(&golden)
+        .unwrap_or_else(|_| panic!("{}: no golden image at {}, run with UPDATE_GOLDEN=1 to create it", name, golden.display()));
+    assert!(screen == expected, "{}: the screen doesn't match {}\n\nexpected:\n{}\ngot:\n{}", name, golden.display(), expected, screen);
+}
+
+// assembles one of the checked in ROMs in tests/roms
+fn assemble_rom(file: &str) -> Vec<u8> {
+    let path = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("tests/roms").join(file);
+    let source = fs::read_to_string(&path).unwrap();
+    assemble(&source).unwrap_or_else(|err| panic!("{}: {}", path.display(), err)).rom
+}
+
+#[test]
+fn opcodes() {
+    check_rom("opcodes", &assemble_rom("opcodes.8o"), Platform::Chip8, Quirks::COSMAC_VIP, None, 2_000);
+}
+
+#[test]
+fn quirks_rom_chip8() {
+    check_rom("quirks-chip8", &assemble_rom("quirks.8o"), Platform::Chip8, Quirks::COSMAC_VIP, None, 5_000);
+}
+
+#[test]
+fn quirks_rom_superchip() {
+    check_rom("quirks-schip", &assemble_rom("quirks.8o"), Platform::SuperChip, Quirks::SUPER_CHIP, None, 5_000);
+}
+
+#[test]
+fn quirks_rom_xochip() {
+    check_rom("quirks-xochip", &assemble_rom("quirks.8o"), Platform::XoChip, Quirks::XO_CHIP, None, 5_000);
+}
+
+#[test]
+#[cfg_attr(not(timendus_suite), ignore = "needs rom/timendus_test_suite")]
+fn chip8_logo() {
+    check("1-chip8-logo", "1-chip8-logo.ch8", Platform::Chip8, Quirks::COSMAC_VIP, None, 1_000);
+}
+
+#[test]
+#[cfg_attr(not(timendus_suite), ignore = "needs rom/timendus_test_suite")]
+fn ibm_logo() {
+    check("2-ibm-logo", "2-ibm-logo.ch8", Platform::Chip8, Quirks::COSMAC_VIP, None, 1_000);
+}
+
+#[test]
+#[cfg_attr(not(timendus_suite), ignore = "needs rom/timendus_test_suite")]
+fn corax_plus() {
+    check("3-corax+", "3-corax+.ch8", Platform::Chip8, Quirks::COSMAC_VIP, None, 10_000);
+}
+
+#[test]
+#[cfg_attr(not(timendus_suite), ignore = "needs rom/timendus_test_suite")]
+fn flags() {
+    check("4-flags", "4-flags.ch8", Platform::Chip8, Quirks::COSMAC_VIP, None, 10_000);
+}
+
+// the quirks test waits on the display, so it needs a good number of frames
+#[test]
+#[cfg_attr(not(timendus_suite), ignore = "needs rom/timendus_test_suite")]
+fn quirks_chip8() {
+    check("5-quirks-chip8", "5-quirks.ch8", Platform::Chip8, Quirks::COSMAC_VIP, Some(1), 200_000);
+}
+
+#[test]
+#[cfg_attr(not(timendus_suite), ignore = "needs rom/timendus_test_suite")]
+fn quirks_superchip() {
+    check("5-quirks-schip", "5-quirks.ch8", Platform::SuperChip, Quirks::SUPER_CHIP, Some(2), 200_000);
+}
+
+#[test]
+#[cfg_attr(not(timendus_suite), ignore = "needs rom/timendus_test_suite")]
+fn quirks_xochip() {
+    check("5-quirks-xochip", "5-quirks.ch8", Platform::XoChip, Quirks::XO_CHIP, Some(3), 200_000);
+}
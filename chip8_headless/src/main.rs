@@ -0,0 +1,337 @@
+// chip8-headless -- runs a ROM with no window or sound, then dumps the screen and registers
+// for CI and batch testing, where there's no display or audio device to open
+//
+//   chip8-headless ROM [--frames N | --cycles N] [--platform NAME] [--seed N] [--ipf N] [--on-fault MODE]
+//                      [--memory SIZE] [--load-address ADDRESS]
+//                      [--input MOVIE] [--png FILE] [--scale N] [--ascii FILE] [--json FILE]
+//                      [--trace FILE] [--trace-range RANGE] [--trace-last N]
+//                      [--profile FILE] [--profile-folded FILE] [--coverage FILE]
+//
+// FILE can be - for stdout. Exits with 1 if the program faulted, 2 for bad arguments or files
+
+use std::{env, fs, process};
+use std::io::{self, BufWriter, Write};
+use chip8_core::{memory::DEFAULT_LOAD_ADDRESS, parse_number, png, runner::Run, trace, watch, Chip8, Coverage, ErrorPolicy, Memory, Movie, Platform, Playback, Profile, Quirks, Trace};
+
+const DEFAULT_FRAMES: u64 = 600; // 10 seconds
+const DEFAULT_CYCLES_PER_FRAME: u32 = 10; // the same as the app
+const PALETTE: [u32; 4] = [0xF2EEDD, 0x2A7B6F, 0xE07A5F, 0x3D405B]; // the same as the app
+const MAX_SCALE: u64 = 64;
+
+const USAGE: &str = "usage: chip8-headless ROM [--frames N | --cycles N] [--platform NAME] [--seed N] [--ipf N] [--on-fault MODE]
+                      [--memory SIZE] [--load-address ADDRESS]
+                      [--input MOVIE] [--png FILE] [--scale N] [--ascii FILE] [--json FILE]
+                      [--trace FILE] [--trace-range RANGE] [--trace-last N]
+                      [--profile FILE] [--profile-folded FILE] [--coverage FILE]
+
+  --frames N       run for N frames (60 a second), 600 by default
+  --cycles N       run for N instructions instead
+  --platform NAME  vip, chip48, schip or xochip (also picks the quirks), vip by default
+  --seed N         RNG seed for CXNN, 0 by default
+  --ipf N          instructions per frame, 10 by default
+  --on-fault MODE  halt (the default) stops at a bad instruction, ignore skips it, wrap wraps the stack round
+  --memory SIZE    bytes of memory, 0x1000 (0x10000 for XO-CHIP) by default
+  --load-address ADDRESS
+                   where the ROM goes and starts running, 0x200 by default (0x600 for the ETI-660)
+  --input MOVIE    key presses to feed in, recorded with --record (sets the platform, memory, seed and ipf too)
+  --png FILE       write the final screen as a PNG
+  --scale N        make each pixel of the PNG N pixels square (1 to 64), 1 by default
+  --ascii FILE     write the final screen as text (. # + @ for off, plane 1, plane 2, both)
+  --json FILE      write the registers and timers as JSON
+  --trace FILE     log every instruction as it runs (the format is described in chip8_core's trace.rs)
+  --trace-range R  only log instructions at these addresses, e.g. 0x200-0x2FF
+  --trace-last N   only keep the last N, written to the trace file if the program faults
+  --profile FILE   write a report of where the cycles went (hottest addresses, opcodes, subroutines)
+  --profile-folded FILE
+                   write the call stacks in the folded format flamegraph tools read
+  --coverage FILE  write which ROM bytes ran, were read or were written (a memory map image if FILE ends in .png)
+
+FILE can be - for stdout. Without any outputs the screen is printed as text.";
+
+struct Options {
+    rom_path: String,
+    frames: Option<u64>,
+    cycles: Option<u64>,
+    platform: String,
+    seed: u64,
+    cycles_per_frame: u32,
+    on_fault: ErrorPolicy,
+    memory_size: Option<usize>,
+    load_address: Option<usize>,
+    input: Option<String>,
+    png: Option<String>,
+    scale: usize,
+    ascii: Option<String>,
+    json: Option<String>,
+    trace: Option<String>,
+    trace_range: Option<(usize, usize)>,
+    trace_last: Option<usize>,
+    profile: Option<String>,
+    profile_folded: Option<String>,
+    coverage: Option<String>,
+}
+
+fn main() {
+    let options = parse_args(env::args().skip(1).collect()).unwrap_or_else(|err| fail(&format!("{}\n\n{}", err, USAGE)));
+
+    let rom = fs::read(&options.rom_path).unwrap_or_else(|err| fail(&format!("can't read {}: {}", options.rom_path, err)));
+
+    let mut playback = options.input.as_ref().map(|path| {
+        let text = fs::read_to_string(path).unwrap_or_else(|err| fail(&format!("can't read {}: {}", path, err)));
+        let movie = Movie::parse(&text).unwrap_or_else(|err| fail(&format!("{}: {}", path, err)));
+        if !movie.matches_rom(&rom) {
+            fail(&format!("{} was recorded with a different ROM", path));
+        }
+        Playback::new(movie)
+    });
+
+    let (mut chip8, cycles_per_frame) = match &playback {
+        Some(playback) => (playback.movie().new_machine(), playback.movie().cycles_per_frame),
+        None => {
+            let platform = Platform::from_name(&options.platform).unwrap_or_else(|| fail("unknown platform, try vip, chip48, schip or xochip"));
+            let quirks = Quirks::preset(&options.platform).unwrap_or_else(|| fail("unknown platform, try vip, chip48, schip or xochip"));
+            let memory_size = options.memory_size.unwrap_or(platform.memory_size());
+            let load_address = options.load_address.unwrap_or(DEFAULT_LOAD_ADDRESS);
+            Memory::check(memory_size, load_address).unwrap_or_else(|err| fail(&err.to_string()));
+            let mut chip8 = Chip8::with_memory(platform, quirks, Memory::new(memory_size, load_address));
+            chip8.seed_rng(options.seed);
+            (chip8, options.cycles_per_frame)
+        },
+    };
+
+    if cycles_per_frame == 0 {
+        fail("there has to be at least one instruction per frame");
+    }
+
+    chip8.set_error_policy(options.on_fault);
+    if let Err(err) = chip8.load_program(&rom) {
+        fail(&format!("can't load {}: {}", options.rom_path, err));
+    }
+
+    if options.profile.is_some() || options.profile_folded.is_some() {
+        chip8.set_profile(Some(Profile::new()));
+    }
+    if options.coverage.is_some() {
+        chip8.set_coverage(Some(Coverage::new(chip8.memory().size())));
+    }
+    // a whole trace is written as it goes, a --trace-last one only at the end (if it faulted)
+    if options.trace.is_some() {
+        chip8.set_trace(Some(Trace::new(options.trace_range, options.trace_last)));
+    }
+    // the file's opened once, straight away for a whole trace and only if the program faults with --trace-last
+    let mut trace_out = match (&options.trace, options.trace_last) {
+        (Some(path), None) => Some(open_trace(path)),
+        _ => None,
+    };
+
+    let run = Run {
+        frames: options.frames.unwrap_or(if options.cycles.is_some() {u64::MAX} else {DEFAULT_FRAMES}),
+        cycles: options.cycles.unwrap_or(u64::MAX),
+        cycles_per_frame,
+    };
+    let cycles = run.run(&mut chip8, playback.as_mut(), |chip8| {
+        if let Some(out) = &mut trace_out {
+            write_trace(out, chip8);
+        }
+    });
+    if let (Some(path), Some(_), Some(_)) = (&options.trace, options.trace_last, chip8.fault()) {
+        trace_out = Some(open_trace(path));
+    }
+    if let Some(out) = &mut trace_out {
+        // the frame that stopped early (or the last N lines), and flushed now because process::exit won't
+        write_trace(out, &mut chip8);
+        if out.flush().is_err() {
+            fail("can't write the trace");
+        }
+    }
+
+    if let Some(err) = chip8.fault() {
+        eprintln!("halted at {:#05X}: {}", chip8.program_counter(), err);
+    }
+
+    let nothing_asked_for = [&options.png, &options.ascii, &options.json, &options.trace, &options.profile, &options.profile_folded, &options.coverage]
+        .iter()
+        .all(|output| output.is_none());
+    if let Some(path) = &options.png {
+        write_output(path, &screen_png(&chip8, options.scale));
+    }
+    if let Some(path) = options.ascii.as_deref().or(if nothing_asked_for {Some("-")} else {None}) {
+        write_output(path, chip8.display.to_ascii().as_bytes());
+    }
+    if let Some(path) = &options.json {
+        write_output(path, state_json(&chip8, cycles).as_bytes());
+    }
+    if let (Some(coverage), Some(path)) = (chip8.coverage(), &options.coverage) {
+        let load_address = chip8.memory().load_address();
+        if path.ends_with(".png") {
+            write_output(path, &coverage.png(load_address, rom.len()));
+        } else {
+            write_output(path, coverage.report(load_address, rom.len()).as_bytes());
+        }
+    }
+    if let Some(profile) = chip8.profile() {
+        if let Some(path) = &options.profile {
+            write_output(path, profile.report(chip8.memory(), chip8.platform()).as_bytes());
+        }
+        if let Some(path) = &options.profile_folded {
+            write_output(path, profile.folded().as_bytes());
+        }
+    }
+
+    if chip8.fault().is_some() {
+        process::exit(1);
+    }
+}
+
+fn parse_args(args: Vec<String>) -> Result<Options, String> {
+    let mut options = Options {
+        rom_path: String::new(),
+        frames: None,
+        cycles: None,
+        platform: String::from("vip"),
+        seed: 0,
+        cycles_per_frame: DEFAULT_CYCLES_PER_FRAME,
+        on_fault: ErrorPolicy::Halt,
+        memory_size: None,
+        load_address: None,
+        input: None,
+        png: None,
+        scale: 1,
+        ascii: None,
+        json: None,
+        trace: None,
+        trace_range: None,
+        trace_last: None,
+        profile: None,
+        profile_folded: None,
+        coverage: None,
+    };
+    let mut rom_path = None;
+
+    let mut args = args.into_iter();
+    while let Some(arg) = args.next() {
+        if arg == "--help" || arg == "-h" {
+            println!("{}", USAGE);
+            process::exit(0);
+        }
+        if !arg.starts_with('-') {
+            if rom_path.replace(arg).is_some() {
+                return Err(String::from("only one ROM can be run at a time"));
+            }
+            continue;
+        }
+        let value = args.next().ok_or(format!("{} needs a value", arg))?;
+        let number = || parse_number(&value).ok_or(format!("{} needs a number, not {}", arg, value));
+        match arg.as_str() {
+            "--frames" => options.frames = Some(number()?),
+            "--cycles" => options.cycles = Some(number()?),
+            "--platform" => options.platform = value,
+            "--seed" => options.seed = number()?,
+            "--ipf" => options.cycles_per_frame = number()?.try_into().map_err(|_| String::from("--ipf is too big"))?,
+            "--memory" => options.memory_size = Some(number()? as usize),
+            "--load-address" => options.load_address = Some(number()? as usize),
+            "--on-fault" => options.on_fault = ErrorPolicy::from_name(&value).ok_or(format!("unknown --on-fault {}, try halt, ignore or wrap", value))?,
+            "--input" => options.input = Some(value),
+            "--png" => options.png = Some(value),
+            "--scale" => {
+                let scale = number()?;
+                if !(1..=MAX_SCALE).contains(&scale) {
+                    return Err(format!("--scale needs to be 1 to {}, not {}", MAX_SCALE, scale));
+                }
+                options.scale = scale as usize;
+            },
+            "--ascii" => options.ascii = Some(value),
+            "--json" => options.json = Some(value),
+            "--trace" => options.trace = Some(value),
+            "--profile" => options.profile = Some(value),
+            "--profile-folded" => options.profile_folded = Some(value),
+            "--coverage" => options.coverage = Some(value),
+            "--trace-range" => options.trace_range = Some(watch::parse_range(&value).map_err(|err| format!("--trace-range {}: {}", value, err))?),
+            "--trace-last" => options.trace_last = Some(number()?.try_into().map_err(|_| String::from("--trace-last is too big"))?),
+            _ => return Err(format!("unknown option {}", arg)),
+        }
+    }
+
+    if options.trace.is_none() && (options.trace_range.is_some() || options.trace_last.is_some()) {
+        return Err(String::from("--trace-range and --trace-last need --trace FILE"));
+    }
+    options.rom_path = rom_path.ok_or("no ROM given")?;
+    Ok(options)
+}
+
+fn screen_png(chip8: &Chip8, scale: usize) -> Vec<u8> {
+    let width = chip8.display.width();
+    let pixels = chip8.display.pixels();
+    let mut rgb = Vec::with_capacity(pixels.len() * scale * scale * 3);
+    for row in pixels.chunks_exact(width) {
+        for _ in 0..scale {
+            for &pixel in row {
+                let colour = PALETTE[pixel as usize & 3].to_be_bytes();
+                for _ in 0..scale {
+                    rgb.extend_from_slice(&colour[1..]);
+                }
+            }
+        }
+    }
+    png::encode_rgb(width * scale, chip8.display.height() * scale, &rgb)
+}
+
+fn state_json(chip8: &Chip8, cycles: u64) -> String {
+    let list = |values: Vec<String>| values.join(", ");
+    let fault = match chip8.fault() {
+        Some(err) => format!("\"{}\"", err.to_string().replace('\\', "\\\\").replace('"', "\\\"")),
+        None => String::from("null"),
+    };
+    format!(
+        "{{\n  \"platform\": \"{}\",\n  \"frames\": {},\n  \"cycles\": {},\n  \"pc\": {},\n  \"i\": {},\n  \"v\": [{}],\n  \"stack\": [{}],\n  \"delay_timer\": {},\n  \"sound_timer\": {},\n  \"hires\": {},\n  \"exited\": {},\n  \"fault\": {}\n}}\n",
+        chip8.platform().name(),
+        chip8.frame_count(),
+        cycles,
+        chip8.program_counter(),
+        chip8.index(),
+        list(chip8.registers().iter().map(u8::to_string).collect()),
+        list(chip8.stack().iter().map(u16::to_string).collect()),
+        chip8.delay_timer.current_time,
+        chip8.sound_timer.current_time,
+        chip8.display.is_hires(),
+        chip8.exited,
+        fault,
+    )
+}
+
+fn open_trace(path: &str) -> Box<dyn Write> {
+    let mut out: Box<dyn Write> = if path == "-" {
+        Box::new(io::stdout())
+    } else {
+        Box::new(BufWriter::new(fs::File::create(path).unwrap_or_else(|err| fail(&format!("can't write {}: {}", path, err)))))
+    };
+    if out.write_all(trace::HEADER.as_bytes()).is_err() {
+        fail(&format!("can't write {}", path));
+    }
+    out
+}
+
+fn write_trace(out: &mut Box<dyn Write>, chip8: &mut Chip8) {
+    let lines = chip8.trace_mut().map(Trace::take_lines).unwrap_or_default();
+    for line in lines {
+        if writeln!(out, "{}", line).is_err() {
+            fail("can't write the trace");
+        }
+    }
+}
+
+fn write_output(path: &str, data: &[u8]) {
+    let result = if path == "-" {
+        io::stdout().write_all(data)
+    } else {
+        fs::write(path, data)
+    };
+    if let Err(err) = result {
+        fail(&format!("can't write {}: {}", path, err));
+    }
+}
+
+fn fail(message: &str) -> ! {
+    eprintln!("chip8-headless: {}", message);
+    process::exit(2);
+}
@@ -0,0 +1,87 @@
+// runs the chip8-headless binary on small assembled ROMs and checks what it prints
+
+use std::fs;
+use std::path::PathBuf;
+use std::process::{Command, Output};
+
+use chip8_core::assemble;
+
+// draws the font's 0 in the top left corner, then loops
+const ZERO: &str = "
+: main
+  v0 := 0
+  i := hex v0
+  sprite v0 v0 5
+  v1 := 7
+  loop again
+";
+
+// assembled into the test's temp directory, as the binary takes a file
+fn rom(name: &str, source: &str) -> PathBuf {
+    let path = PathBuf::from(env!("CARGO_TARGET_TMPDIR")).join(name);
+    fs::write(&path, assemble(source).unwrap().rom).unwrap();
+    path
+}
+
+fn headless(rom: &PathBuf, args: &[&str]) -> Output {
+    Command::new(env!("CARGO_BIN_EXE_chip8-headless")).arg(rom).args(args).output().unwrap()
+}
+
+fn stdout(output: &Output) -> String {
+    String::from_utf8(output.stdout.clone()).unwrap()
+}
+
+#[test]
+fn prints_the_screen() {
+    let output = headless(&rom("zero.ch8", ZERO), &["--frames", "3", "--ascii", "-"]);
+    assert!(output.status.success());
+    let screen = stdout(&output);
+    let lines: Vec<&str> = screen.lines().collect();
+    assert_eq!(lines.len(), 32);
+    let blank = ".".repeat(60);
+    for (line, row) in lines.iter().zip(["####", "#..#", "#..#", "#..#", "####"]) {
+        assert_eq!(*line, format!("{}{}", row, blank));
+    }
+    assert!(lines[5..].iter().all(|line| *line == ".".repeat(64)));
+}
+
+#[test]
+fn writes_the_registers_as_json() {
+    // 10 instructions a frame
+    let output = headless(&rom("zero-json.ch8", ZERO), &["--frames", "3", "--json", "-"]);
+    assert!(output.status.success());
+    let json = stdout(&output);
+    for field in [
+        "\"frames\": 3,",
+        "\"cycles\": 30,",
+        "\"pc\": 520,",
+        "\"i\": 80,", // the font starts at 0x50
+        "\"v\": [0, 7, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0],",
+        "\"fault\": null",
+    ] {
+        assert!(json.contains(field), "{} isn't in\n{}", field, json);
+    }
+}
+
+#[test]
+fn cycles_stop_part_way_through_a_frame() {
+    let output = headless(&rom("zero-cycles.ch8", ZERO), &["--cycles", "2", "--json", "-"]);
+    let json = stdout(&output);
+    assert!(json.contains("\"frames\": 0,") && json.contains("\"cycles\": 2,") && json.contains("\"pc\": 516,"), "{}", json);
+    assert!(json.contains("\"v\": [0, 0, 0,"), "{}", json);
+}
+
+#[test]
+fn a_fault_exits_with_1() {
+    let output = headless(&rom("underflow.ch8", ": main\n  return\n"), &["--frames", "3", "--json", "-"]);
+    assert_eq!(output.status.code(), Some(1));
+    assert!(stdout(&output).contains("\"fault\": \"stack underflow (return with nothing on the stack)\""));
+    assert!(String::from_utf8_lossy(&output.stderr).contains("halted at 0x200: stack underflow"));
+}
+
+#[test]
+fn bad_arguments_exit_with_2() {
+    let output = headless(&rom("zero-args.ch8", ZERO), &["--scale", "65"]);
+    assert_eq!(output.status.code(), Some(2));
+    assert!(output.stdout.is_empty());
+}
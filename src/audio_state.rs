@@ -1,10 +1,17 @@
+use std::time::Duration;
+
 use rodio::{source::SineWave, OutputStream, Sink, Source};
 
 pub struct AudioState {
+    output: Option<Output>, // None when muted
+    playing: bool,
+    pattern: Option<([u8; 16], u8)>, // the XO-CHIP pattern (and pitch) currently playing, if any
+}
+
+struct Output {
     sink: Sink,
     _stream: OutputStream,
     stream_handle: rodio::OutputStreamHandle,
-    playing: bool,
 }
 
 impl AudioState {
@@ -15,20 +22,82 @@ impl AudioState {
         let tone = SineWave::new(440.0).repeat_infinite();
         sink.append(tone);
         sink.set_volume(1.0);
-        AudioState { sink, _stream, stream_handle, playing: false }
+        AudioState { output: Some(Output { sink, _stream, stream_handle }), playing: false, pattern: None }
+    }
+
+    // --mute, no audio device is opened at all
+    pub fn muted() -> Self {
+        AudioState { output: None, playing: false, pattern: None }
     }
 
     /// Enable or disable the beep based on sound timer value
-    pub fn update(&mut self, sound_timer: u8) {
+    /// XO-CHIP ROMs can swap the beep for their own 1-bit pattern, played at a rate set by the pitch
+    pub fn update(&mut self, sound_timer: u8, pattern: Option<([u8; 16], u8)>) {
+        let Some(output) = &mut self.output else {return};
+        // a new pattern (or pitch) while we're playing restarts the sound with it
+        if self.playing && pattern != self.pattern {
+            output.sink.stop();
+            self.playing = false;
+        }
+        self.pattern = pattern;
+
         if sound_timer > 0 && !self.playing {
-            let tone = SineWave::new(440.0).repeat_infinite();
-            self.sink = Sink::try_new(&self.stream_handle).expect("Failed to create new sink");
-            self.sink.append(tone);
-            self.sink.set_volume(1.0);
+            output.sink = Sink::try_new(&output.stream_handle).expect("Failed to create new sink");
+            match pattern {
+                Some((bits, pitch)) => output.sink.append(PatternWave::new(bits, pitch)),
+                None => output.sink.append(SineWave::new(440.0).repeat_infinite()),
+            }
+            output.sink.set_volume(1.0);
             self.playing = true;
         } else if sound_timer == 0 && self.playing {
-            self.sink.stop();
+            output.sink.stop();
             self.playing = false;
         }
     }
-}
\ No newline at end of file
+}
+
+/// Loops the 128 bits of an XO-CHIP audio pattern as a square-ish wave
+struct PatternWave {
+    bits: [u8; 16],
+    position: f32, // which bit we're on, fractional so any playback rate works
+    step: f32,     // bits to move forward per output sample
+}
+
+impl PatternWave {
+    const SAMPLE_RATE: u32 = 48000;
+
+    fn new(bits: [u8; 16], pitch: u8) -> Self {
+        // 4000 bits per second at the default pitch of 64, up or down an octave every 48
+        let rate = 4000.0 * 2f32.powf((pitch as f32 - 64.0) / 48.0);
+        PatternWave { bits, position: 0.0, step: rate / Self::SAMPLE_RATE as f32 }
+    }
+}
+
+impl Iterator for PatternWave {
+    type Item = f32;
+
+    fn next(&mut self) -> Option<f32> {
+        let bit = self.position as usize % 128;
+        let on = (self.bits[bit / 8] >> (7 - bit % 8)) & 1 == 1;
+        self.position = (self.position + self.step) % 128.0;
+        Some(if on { 0.25 } else { -0.25 })
+    }
+}
+
+impl Source for PatternWave {
+    fn current_frame_len(&self) -> Option<usize> {
+        None
+    }
+
+    fn channels(&self) -> u16 {
+        1
+    }
+
+    fn sample_rate(&self) -> u32 {
+        Self::SAMPLE_RATE
+    }
+
+    fn total_duration(&self) -> Option<Duration> {
+        None
+    }
+}
@@ -0,0 +1,495 @@
+// the command line -- subcommands, flags and --help
+// everything is checked here, so a typo gets a message and exit code 2 rather than a panic half way through starting up
+//
+//   chip8 [run] ROM [PLATFORM] [options]     play a ROM (or Octo source, .8o)
+//   chip8 disasm ROM [PLATFORM] [--syntax octo|cowgod]
+//   chip8 asm SOURCE [-o ROM] [--source-map FILE]
+//   chip8 info ROM [--config FILE]
+
+use std::process;
+
+use chip8_core::{disasm::Syntax, parse_number, watch, Condition, ErrorPolicy, Platform, Quirks, Watchpoint};
+
+pub const USAGE: &str = "usage: chip8 [run] ROM [PLATFORM] [options]
+       chip8 disasm ROM [PLATFORM] [--syntax octo|cowgod]
+       chip8 asm SOURCE [-o ROM] [--source-map FILE]
+       chip8 info ROM [--config FILE]
+
+run plays a ROM (the default when the first argument isn't a command), disasm prints a ROM as source,
+asm assembles Octo source into a ROM, and info shows a ROM's hash and the settings it would run with.
+`chip8 COMMAND --help` has the options for each.";
+
+pub const RUN_USAGE: &str = "usage: chip8 [run] ROM [PLATFORM] [options]
+
+ROM is a CHIP-8 program, or Octo source (.8o) to assemble and run. PLATFORM is vip, chip48, schip or xochip.
+
+  --platform NAME      the same as PLATFORM, picks the instruction set and its quirks
+  --quirks PRESET      just the quirks, vip, chip48, schip or xochip
+  --ipf N              instructions per frame (the speed), 10 unless the config or ROM database says otherwise
+  --scale N            window pixels per CHIP-8 pixel (1 to 64), 10 by default
+  --palette NAME       default, classic, green, amber, lcd, high-contrast or one from the config, or colours
+                       for the background, plane 1, plane 2 and both planes, e.g. \"#000000,#FFFFFF\" (F4 changes it)
+  --fullscreen         a borderless window as big as fits the screen
+  --mute               no sound (no audio device is opened)
+  --seed N             RNG seed for CXNN, so runs can be repeated (random by default, and printed)
+  --on-fault MODE      halt (the default) stops and shows the error, ignore skips the bad instruction,
+                       wrap wraps the stack round and skips unknown opcodes
+  --memory SIZE        bytes of memory, 0x1000 (0x10000 for XO-CHIP) by default
+  --load-address ADDRESS
+                       where the ROM goes and starts running, 0x200 by default (0x600 for the ETI-660)
+  --config FILE        the config file, instead of ./chip8.toml or ~/.config/chip8/config.toml
+  --record MOVIE       write every key press to MOVIE on exit
+  --replay MOVIE       play back a recording (sets the platform, memory, seed and ipf too)
+  --headless           run with no window or sound, then print the screen as text (for scripts)
+  --frames N           how long --headless runs, 600 frames (10 seconds) by default
+
+debugging:
+  --debug              show the debugger panel
+  --start-paused       open paused in the debugger (F8 to run)
+  --break ADDRESS      stop at ADDRESS (as many as you like)
+  --watch RANGE        stop when an instruction reads or writes these bytes, e.g. 0x300-0x3FF
+  --watch-read RANGE   ... reads them
+  --watch-write RANGE  ... writes them
+  --break-if COND      stop when COND becomes true, e.g. \"V3 == 0x10\" or \"0x2A4 if V3 == 0x10\"
+  --gdb PORT           let gdb connect on localhost:PORT
+  --trace FILE         log every instruction (the format is described in chip8_core's trace.rs)
+  --trace-range RANGE  only log instructions at these addresses
+  --trace-last N       only keep the last N, written out if the program faults (to stderr without --trace)
+  --profile FILE       write where the cycles went on exit
+  --profile-folded FILE
+                       write the call stacks for flamegraph tools on exit
+  --coverage FILE      write which ROM bytes ran, were read or were written on exit (an image if FILE ends in .png)";
+
+pub const DISASM_USAGE: &str = "usage: chip8 disasm ROM [PLATFORM] [--syntax octo|cowgod]
+
+  PLATFORM             vip, chip48, schip or xochip, for the SUPER-CHIP and XO-CHIP instructions
+  --syntax NAME        octo (the default, it can be assembled again) or cowgod (the usual reference's mnemonics)";
+
+pub const ASM_USAGE: &str = "usage: chip8 asm SOURCE [-o ROM] [--source-map FILE]
+
+  -o ROM               where to write the ROM, SOURCE with .ch8 by default
+  --source-map FILE    write an \"address line\" pair for every instruction";
+
+pub const INFO_USAGE: &str = "usage: chip8 info ROM [--config FILE]
+
+shows the ROM's size and SHA-1, what the ROM database knows about it, and the settings `chip8 run` would use.
+
+  --config FILE        the config file, instead of ./chip8.toml or ~/.config/chip8/config.toml";
+
+const DEFAULT_SCALE: usize = 10;
+const MAX_SCALE: u64 = 64;
+const DEFAULT_HEADLESS_FRAMES: u64 = 600;
+
+pub enum Command {
+    Run(Box<RunOptions>),
+    Disasm { rom_path: String, platform: Platform, syntax: Syntax },
+    Asm { source_path: String, output: Option<String>, source_map: Option<String> },
+    Info { rom_path: String, config: Option<String> },
+    Help(&'static str), // --help, with the usage to print
+}
+
+pub struct RunOptions {
+    pub rom_path: String,
+    pub platform: Option<(Platform, Quirks)>, // from PLATFORM or --platform
+    pub quirks: Option<Quirks>,
+    pub ipf: Option<usize>,
+    pub scale: usize,
+    pub palette: Option<String>, // checked once the config's palettes are known
+    pub fullscreen: bool,
+    pub mute: bool,
+    pub seed: Option<u64>,
+    pub on_fault: ErrorPolicy,
+    pub memory_size: Option<usize>,
+    pub load_address: Option<usize>,
+    pub config: Option<String>,
+    pub record: Option<String>,
+    pub replay: Option<String>,
+    pub headless: bool,
+    pub frames: u64,
+    pub debug: bool,
+    pub start_paused: bool,
+    pub breakpoints: Vec<u16>,
+    pub watchpoints: Vec<Watchpoint>,
+    pub conditions: Vec<Condition>,
+    pub gdb: Option<u16>,
+    pub trace: Option<String>,
+    pub trace_range: Option<(usize, usize)>,
+    pub trace_last: Option<usize>,
+    pub profile: Option<String>,
+    pub profile_folded: Option<String>,
+    pub coverage: Option<String>,
+}
+
+impl Default for RunOptions {
+    fn default() -> Self {
+        RunOptions {
+            rom_path: String::new(),
+            platform: None,
+            quirks: None,
+            ipf: None,
+            scale: DEFAULT_SCALE,
+            palette: None,
+            fullscreen: false,
+            mute: false,
+            seed: None,
+            on_fault: ErrorPolicy::Halt,
+            memory_size: None,
+            load_address: None,
+            config: None,
+            record: None,
+            replay: None,
+            headless: false,
+            frames: DEFAULT_HEADLESS_FRAMES,
+            debug: false,
+            start_paused: false,
+            breakpoints: Vec::new(),
+            watchpoints: Vec::new(),
+            conditions: Vec::new(),
+            gdb: None,
+            trace: None,
+            trace_range: None,
+            trace_last: None,
+            profile: None,
+            profile_folded: None,
+            coverage: None,
+        }
+    }
+}
+
+impl RunOptions {
+    // anything that needs the debugger panel
+    pub fn debugging(&self) -> bool {
+        self.debug || self.start_paused || self.gdb.is_some() || !self.breakpoints.is_empty() || !self.watchpoints.is_empty() || !self.conditions.is_empty()
+    }
+}
+
+// bad arguments: say what's wrong and how it's used (just the usage lines, not every option), then exit with 2
+pub fn fail_with_usage(message: &str, usage: &str) -> ! {
+    let lines = usage.split("\n\n").next().unwrap_or(usage);
+    eprintln!("chip8: {}\n\n{}\n\n--help lists the options", message, lines);
+    process::exit(2);
+}
+
+// bad files (a missing ROM, a broken config...), exit with 2 too
+pub fn fail(message: &str) -> ! {
+    eprintln!("chip8: {}", message);
+    process::exit(2);
+}
+
+// errors come back with the usage for the command they were in
+pub fn parse(args: Vec<String>) -> Result<Command, (String, &'static str)> {
+    let mut args = args.into_iter().peekable();
+    let command = match args.peek().map(String::as_str) {
+        Some(name @ ("run" | "disasm" | "asm" | "info")) => {
+            let name = String::from(name);
+            args.next();
+            name
+        },
+        Some("--help" | "-h") => return Ok(Command::Help(USAGE)),
+        None => return Err((String::from("no ROM given"), USAGE)),
+        _ => String::from("run"),
+    };
+    let args: Vec<String> = args.collect();
+    match command.as_str() {
+        "disasm" => parse_disasm(args).map_err(|err| (err, DISASM_USAGE)),
+        "asm" => parse_asm(args).map_err(|err| (err, ASM_USAGE)),
+        "info" => parse_info(args).map_err(|err| (err, INFO_USAGE)),
+        _ => parse_run(args).map_err(|err| (err, RUN_USAGE)),
+    }
+}
+
+fn parse_run(args: Vec<String>) -> Result<Command, String> {
+    let mut options = RunOptions::default();
+    let mut frames = None;
+    let mut positional = Vec::new();
+
+    let mut args = args.into_iter();
+    while let Some(arg) = args.next() {
+        let switch = match arg.as_str() {
+            "--help" | "-h" => return Ok(Command::Help(RUN_USAGE)),
+            "--fullscreen" => &mut options.fullscreen,
+            "--mute" => &mut options.mute,
+            "--headless" => &mut options.headless,
+            "--debug" => &mut options.debug,
+            "--start-paused" => &mut options.start_paused,
+            _ if !arg.starts_with('-') => {
+                positional.push(arg);
+                continue;
+            },
+            _ if !arg.starts_with("--") => return Err(format!("unknown option {}", arg)),
+            _ => {
+                let value = args.next().ok_or(format!("{} needs a value", arg))?;
+                take_run_value(&mut options, &mut frames, &arg, value)?;
+                continue;
+            },
+        };
+        *switch = true;
+    }
+
+    let mut positional = positional.into_iter();
+    options.rom_path = positional.next().ok_or("no ROM given")?;
+    if let Some(name) = positional.next() {
+        if options.platform.is_some() {
+            return Err(String::from("the platform is given twice"));
+        }
+        options.platform = Some(platform_named(&name)?);
+    }
+    if let Some(extra) = positional.next() {
+        return Err(format!("didn't expect {} (only one ROM can be run at a time)", extra));
+    }
+
+    if options.record.is_some() && options.replay.is_some() {
+        return Err(String::from("--record and --replay can't be used together"));
+    }
+    if options.headless {
+        let windowed = [
+            ("--record", options.record.is_some()),
+            ("--fullscreen", options.fullscreen),
+            ("--debug", options.debug),
+            ("--start-paused", options.start_paused),
+            ("--break", !options.breakpoints.is_empty()),
+            ("--watch", !options.watchpoints.is_empty()),
+            ("--break-if", !options.conditions.is_empty()),
+            ("--gdb", options.gdb.is_some()),
+        ];
+        if let Some((flag, _)) = windowed.iter().find(|(_, used)| *used) {
+            return Err(format!("{} needs a window, it can't be used with --headless", flag));
+        }
+        options.frames = frames.unwrap_or(DEFAULT_HEADLESS_FRAMES);
+    } else if frames.is_some() {
+        return Err(String::from("--frames is only for --headless"));
+    }
+    Ok(Command::Run(Box::new(options)))
+}
+
+fn take_run_value(options: &mut RunOptions, frames: &mut Option<u64>, flag: &str, value: String) -> Result<(), String> {
+    let number = || parse_number(&value).ok_or(format!("{} needs a number, not {}", flag, value));
+    let at_least_one = || number().and_then(|n| if n >= 1 {Ok(n)} else {Err(format!("{} needs to be at least 1", flag))});
+    let range = || watch::parse_range(&value).map_err(|err| format!("{} {}: {}", flag, value, err));
+    match flag {
+        "--platform" => {
+            if options.platform.is_some() {
+                return Err(String::from("the platform is given twice"));
+            }
+            options.platform = Some(platform_named(&value)?);
+        },
+        "--quirks" => options.quirks = Some(Quirks::preset(&value).ok_or(format!("unknown quirks preset {}, try vip, chip48, schip or xochip", value))?),
+        "--ipf" => options.ipf = Some(at_least_one()?.try_into().map_err(|_| String::from("--ipf is too big"))?),
+        "--scale" => {
+            let scale = at_least_one()?;
+            if scale > MAX_SCALE {
+                return Err(format!("--scale can be at most {}, not {}", MAX_SCALE, scale));
+            }
+            options.scale = scale as usize;
+        },
+        "--palette" => options.palette = Some(value),
+        "--seed" => options.seed = Some(number()?),
+        "--memory" => options.memory_size = Some(number()? as usize),
+        "--load-address" => options.load_address = Some(number()? as usize),
+        "--on-fault" => options.on_fault = ErrorPolicy::from_name(&value).ok_or(format!("unknown --on-fault {}, try halt, ignore or wrap", value))?,
+        "--config" => options.config = Some(value),
+        "--record" => options.record = Some(value),
+        "--replay" => options.replay = Some(value),
+        "--frames" => *frames = Some(at_least_one()?),
+        "--break" => options.breakpoints.push(u16::try_from(number()?).map_err(|_| format!("{} is past the end of memory", value))?),
+        "--watch" => options.watchpoints.push(Watchpoint::parse(&value, true, true).map_err(|err| format!("{} {}: {}", flag, value, err))?),
+        "--watch-read" => options.watchpoints.push(Watchpoint::parse(&value, true, false).map_err(|err| format!("{} {}: {}", flag, value, err))?),
+        "--watch-write" => options.watchpoints.push(Watchpoint::parse(&value, false, true).map_err(|err| format!("{} {}: {}", flag, value, err))?),
+        "--break-if" => options.conditions.push(Condition::parse(&value).map_err(|err| format!("{} {}: {}", flag, value, err))?),
+        "--gdb" => options.gdb = Some(value.parse().map_err(|_| format!("--gdb needs a port, e.g. --gdb 1234, not {}", value))?),
+        "--trace" => options.trace = Some(value),
+        "--trace-range" => options.trace_range = Some(range()?),
+        "--trace-last" => options.trace_last = Some(at_least_one()?.try_into().map_err(|_| String::from("--trace-last is too big"))?),
+        "--profile" => options.profile = Some(value),
+        "--profile-folded" => options.profile_folded = Some(value),
+        "--coverage" => options.coverage = Some(value),
+        _ => return Err(format!("unknown option {}", flag)),
+    }
+    Ok(())
+}
+
+fn parse_disasm(args: Vec<String>) -> Result<Command, String> {
+    let mut syntax = Syntax::Octo;
+    let mut positional = Vec::new();
+    let mut args = args.into_iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--help" | "-h" => return Ok(Command::Help(DISASM_USAGE)),
+            "--syntax" => {
+                let name = args.next().ok_or("--syntax needs a value, octo or cowgod")?;
+                syntax = Syntax::from_name(&name).ok_or(format!("unknown syntax {}, try octo or cowgod", name))?;
+            },
+            _ if arg.starts_with('-') => return Err(format!("unknown option {}", arg)),
+            _ => positional.push(arg),
+        }
+    }
+    let mut positional = positional.into_iter();
+    let rom_path = positional.next().ok_or("no ROM given")?;
+    let platform = match positional.next() {
+        Some(name) => platform_named(&name)?.0,
+        None => Platform::Chip8,
+    };
+    if let Some(extra) = positional.next() {
+        return Err(format!("didn't expect {}", extra));
+    }
+    Ok(Command::Disasm { rom_path, platform, syntax })
+}
+
+fn parse_asm(args: Vec<String>) -> Result<Command, String> {
+    let (mut output, mut source_map, mut source_path) = (None, None, None);
+    let mut args = args.into_iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--help" | "-h" => return Ok(Command::Help(ASM_USAGE)),
+            "-o" => output = Some(args.next().ok_or("-o needs a file name")?),
+            "--source-map" => source_map = Some(args.next().ok_or("--source-map needs a file name")?),
+            _ if arg.starts_with('-') => return Err(format!("unknown option {}", arg)),
+            _ if source_path.is_some() => return Err(format!("didn't expect {} (one source file at a time)", arg)),
+            _ => source_path = Some(arg),
+        }
+    }
+    Ok(Command::Asm { source_path: source_path.ok_or("no source file given")?, output, source_map })
+}
+
+fn parse_info(args: Vec<String>) -> Result<Command, String> {
+    let (mut config, mut rom_path) = (None, None);
+    let mut args = args.into_iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--help" | "-h" => return Ok(Command::Help(INFO_USAGE)),
+            "--config" => config = Some(args.next().ok_or("--config needs a file name")?),
+            _ if arg.starts_with('-') => return Err(format!("unknown option {}", arg)),
+            _ if rom_path.is_some() => return Err(format!("didn't expect {} (one ROM at a time)", arg)),
+            _ => rom_path = Some(arg),
+        }
+    }
+    Ok(Command::Info { rom_path: rom_path.ok_or("no ROM given")?, config })
+}
+
+fn platform_named(name: &str) -> Result<(Platform, Quirks), String> {
+    match (Platform::from_name(name), Quirks::preset(name)) {
+        (Some(platform), Some(quirks)) => Ok((platform, quirks)),
+        _ => Err(format!("unknown platform {}, try vip, chip48, schip or xochip", name)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parsed(line: &str) -> Result<Command, (String, &'static str)> {
+        parse(line.split_whitespace().map(String::from).collect())
+    }
+
+    fn run(line: &str) -> RunOptions {
+        match parsed(line) {
+            Ok(Command::Run(options)) => *options,
+            _ => panic!("{} isn't a run", line),
+        }
+    }
+
+    fn error(line: &str) -> (String, &'static str) {
+        match parsed(line) {
+            Err(err) => err,
+            Ok(_) => panic!("{} was accepted", line),
+        }
+    }
+
+    fn help(line: &str) -> &'static str {
+        match parsed(line) {
+            Ok(Command::Help(usage)) => usage,
+            _ => panic!("{} isn't --help", line),
+        }
+    }
+
+    #[test]
+    fn run_is_the_default_command() {
+        let options = run("game.ch8");
+        assert_eq!(options.rom_path, "game.ch8");
+        assert_eq!((options.scale, options.on_fault, options.platform), (DEFAULT_SCALE, ErrorPolicy::Halt, None));
+        assert!(!options.debugging());
+        let options = run("run game.ch8 schip --ipf 0x20 --scale 4 --mute --seed 7 --on-fault wrap");
+        assert_eq!(options.platform, Some((Platform::SuperChip, Quirks::SUPER_CHIP)));
+        assert_eq!((options.ipf, options.scale, options.mute, options.seed), (Some(32), 4, true, Some(7)));
+        assert_eq!(options.on_fault, ErrorPolicy::Wrap);
+    }
+
+    #[test]
+    fn run_debugging_options() {
+        let options = run("game.ch8 --break 0x2A4 --break 0x300 --watch-write 0x300-0x3FF --break-if v3==16 --trace-range 0x200-0x210");
+        assert_eq!(options.breakpoints, [0x2A4, 0x300]);
+        assert_eq!(options.watchpoints, [Watchpoint { start: 0x300, end: 0x3FF, read: false, write: true }]);
+        assert_eq!(options.conditions.len(), 1);
+        assert_eq!(options.trace_range, Some((0x200, 0x210)));
+        assert!(options.debugging());
+        assert_eq!(run("game.ch8 --headless").frames, DEFAULT_HEADLESS_FRAMES);
+        assert_eq!(run("game.ch8 --headless --frames 60").frames, 60);
+    }
+
+    #[test]
+    fn disasm_asm_and_info() {
+        match parsed("disasm game.ch8 xochip --syntax cowgod") {
+            Ok(Command::Disasm { rom_path, platform, syntax }) => {
+                assert_eq!((rom_path.as_str(), platform, syntax), ("game.ch8", Platform::XoChip, Syntax::Cowgod));
+            },
+            _ => panic!("not a disasm"),
+        }
+        match parsed("disasm game.ch8") {
+            Ok(Command::Disasm { platform, syntax, .. }) => assert_eq!((platform, syntax), (Platform::Chip8, Syntax::Octo)),
+            _ => panic!("not a disasm"),
+        }
+        match parsed("asm game.8o -o out.ch8 --source-map out.map") {
+            Ok(Command::Asm { source_path, output, source_map }) => {
+                assert_eq!(source_path, "game.8o");
+                assert_eq!((output.as_deref(), source_map.as_deref()), (Some("out.ch8"), Some("out.map")));
+            },
+            _ => panic!("not an asm"),
+        }
+        match parsed("info game.ch8 --config my.toml") {
+            Ok(Command::Info { rom_path, config }) => assert_eq!((rom_path.as_str(), config.as_deref()), ("game.ch8", Some("my.toml"))),
+            _ => panic!("not an info"),
+        }
+    }
+
+    #[test]
+    fn help_for_each_command() {
+        assert_eq!(help("--help"), USAGE);
+        assert_eq!(help("-h"), USAGE);
+        assert_eq!(help("game.ch8 --help"), RUN_USAGE);
+        assert_eq!(help("run -h"), RUN_USAGE);
+        assert_eq!(help("disasm --help"), DISASM_USAGE);
+        assert_eq!(help("asm --help"), ASM_USAGE);
+        assert_eq!(help("info --help"), INFO_USAGE);
+    }
+
+    #[test]
+    fn rejects_bad_scales() {
+        assert_eq!(error("game.ch8 --scale 0"), (String::from("--scale needs to be at least 1"), RUN_USAGE));
+        assert_eq!(error("game.ch8 --scale 65").0, "--scale can be at most 64, not 65");
+        assert_eq!(error("game.ch8 --scale big").0, "--scale needs a number, not big");
+        assert_eq!(error("game.ch8 --scale").0, "--scale needs a value");
+        assert_eq!(run("game.ch8 --scale 64").scale, 64);
+    }
+
+    #[test]
+    fn rejects_unknown_single_dash_options() {
+        // rather than taking them as the ROM
+        assert_eq!(error("-x game.ch8"), (String::from("unknown option -x"), RUN_USAGE));
+        assert_eq!(error("game.ch8 -v").0, "unknown option -v");
+        assert_eq!(error("disasm game.ch8 -x"), (String::from("unknown option -x"), DISASM_USAGE));
+        assert_eq!(error("asm game.8o -x"), (String::from("unknown option -x"), ASM_USAGE));
+        assert_eq!(error("info game.ch8 -x"), (String::from("unknown option -x"), INFO_USAGE));
+    }
+
+    #[test]
+    fn rejects_conflicting_and_missing_arguments() {
+        assert_eq!(error("").0, "no ROM given");
+        assert_eq!(error("run").0, "no ROM given");
+        assert_eq!(error("game.ch8 schip --platform xochip").0, "the platform is given twice");
+        assert_eq!(error("game.ch8 nes").0, "unknown platform nes, try vip, chip48, schip or xochip");
+        assert_eq!(error("game.ch8 --record a --replay b").0, "--record and --replay can't be used together");
+        assert_eq!(error("game.ch8 --headless --debug").0, "--debug needs a window, it can't be used with --headless");
+        assert_eq!(error("game.ch8 --frames 10").0, "--frames is only for --headless");
+        assert_eq!(error("asm").0, "no source file given");
+    }
+}
@@ -0,0 +1,329 @@
+// the config file -- enough TOML for settings: [tables], key = value, "strings" (with TOML's backslash escapes,
+// so a backslash is "\\"), 'literal strings', numbers, true/false and arrays of those, with # comments.
+// no inline tables or multi-line strings, we don't need them
+//
+// it's read from --config FILE, or ./chip8.toml, or ~/.config/chip8/config.toml, whichever is found first
+
+use std::collections::BTreeMap;
+use std::{env, fs};
+
+use chip8_core::database::parse_color;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Value {
+    String(String),
+    Integer(i64),
+    Boolean(bool),
+    Array(Vec<Value>),
+}
+
+// each [table] by its full name (e.g. "keymap" or "rom.3c5a...keymap"), the keys before any table are in ""
+#[derive(Debug, Default)]
+pub struct Config {
+    tables: BTreeMap<String, BTreeMap<String, Value>>,
+}
+
+impl Config {
+    // a missing file is fine (everything's default), a broken one isn't
+    pub fn load(path: Option<&str>) -> Result<Config, String> {
+        let path = match path {
+            Some(path) => path.to_string(),
+            None => match default_path() {
+                Some(path) => path,
+                None => return Ok(Config::default()),
+            },
+        };
+        let text = fs::read_to_string(&path).map_err(|err| format!("can't read {}: {}", path, err))?;
+        Config::parse(&text).map_err(|err| format!("{}:{}", path, err))
+    }
+
+    pub fn parse(text: &str) -> Result<Config, String> {
+        let mut config = Config::default();
+        let mut table = String::new();
+        for (i, line) in text.lines().enumerate() {
+            let error = |message: &str| format!("{}: {}", i + 1, message);
+            let line = strip_comment(line).trim();
+            if line.is_empty() {continue}
+
+            if let Some(name) = line.strip_prefix('[') {
+                let name = name.strip_suffix(']').ok_or_else(|| error("expected ] at the end of the table name"))?;
+                table = name.split('.').map(|part| unquote(part.trim())).collect::<Vec<_>>().join(".");
+                config.tables.entry(table.clone()).or_default();
+                continue;
+            }
+
+            let (key, value) = line.split_once('=').ok_or_else(|| error("expected key = value"))?;
+            let key = unquote(key.trim());
+            let value = parse_value(value.trim()).ok_or_else(|| error(&format!("can't read the value for {}", key)))?;
+            config.tables.entry(table.clone()).or_default().insert(key, value);
+        }
+        Ok(config)
+    }
+
+    pub fn table(&self, name: &str) -> Option<&BTreeMap<String, Value>> {
+        self.tables.get(name)
+    }
+}
+
+// [rom.<sha1>] -- settings for one ROM, these win over the ROM database
+//
+//   [rom.0c9bd4c3d9d7d2cd4a5d5a3e7c2f1b6f0a8e7d12]
+//   platform = "schip"
+//   cycles_per_frame = 30
+//   colors = ["#000000", "#FFAA00"]   # background, plane 1, plane 2, both planes
+//   palette = "amber"                 # or one of the palettes by name, instead of colors
+#[derive(Debug, Default)]
+pub struct RomSettings {
+    pub platform: Option<String>,
+    pub cycles_per_frame: Option<u32>,
+    pub colors: Option<Vec<u32>>,
+    pub palette: Option<Value>, // checked against the palettes later
+}
+
+impl RomSettings {
+    pub fn from_config(config: &Config, rom_hash: &str) -> Result<RomSettings, String> {
+        let mut settings = RomSettings::default();
+        let Some(table) = config.table(&format!("rom.{}", rom_hash)) else {return Ok(settings)};
+        for (key, value) in table {
+            match (key.as_str(), value) {
+                ("platform", Value::String(name)) => settings.platform = Some(name.clone()),
+                ("cycles_per_frame", &Value::Integer(n)) if n >= 1 && n <= u32::MAX as i64 => settings.cycles_per_frame = Some(n as u32),
+                ("colors", Value::Array(colors)) => settings.colors = Some(colors.iter().map(|color| match color {
+                    Value::String(text) => parse_color(text).ok_or_else(|| format!("{} isn't a colour, they're like \"#2A7B6F\"", text)),
+                    _ => Err(String::from("colors should be strings like \"#2A7B6F\"")),
+                }).collect::<Result<_, _>>()?),
+                ("palette", value) => settings.palette = Some(value.clone()),
+                ("platform", _) => return Err(String::from("platform should be a string, e.g. platform = \"schip\"")),
+                ("cycles_per_frame", _) => return Err(String::from("cycles_per_frame should be a number of at least 1")),
+                ("colors", _) => return Err(String::from("colors should be a list, e.g. colors = [\"#000000\", \"#FFFFFF\"]")),
+                _ => return Err(format!("unknown setting {}", key)),
+            }
+        }
+        Ok(settings)
+    }
+}
+
+fn default_path() -> Option<String> {
+    let candidates = [
+        Some(String::from("chip8.toml")),
+        env::var("HOME").ok().map(|home| format!("{}/.config/chip8/config.toml", home)),
+    ];
+    candidates.into_iter().flatten().find(|path| fs::metadata(path).is_ok())
+}
+
+// the characters of `text` that aren't in a "string" or a 'literal string', quotes included, and where they are
+fn outside_strings(text: &str) -> impl Iterator<Item = (usize, char)> + '_ {
+    let (mut quote, mut escaped) = (None, false);
+    text.char_indices().filter(move |&(_, c)| match quote {
+        // only "strings" have escapes, so \" doesn't end one
+        Some(_) if escaped => {
+            escaped = false;
+            false
+        },
+        Some('"') if c == '\\' => {
+            escaped = true;
+            false
+        },
+        Some(q) => {
+            if c == q {quote = None}
+            false
+        },
+        None if c == '"' || c == '\'' => {
+            quote = Some(c);
+            false
+        },
+        None => true,
+    })
+}
+
+// a # outside a string starts a comment
+fn strip_comment(line: &str) -> &str {
+    match outside_strings(line).find(|&(_, c)| c == '#') {
+        Some((i, _)) => &line[..i],
+        None => line,
+    }
+}
+
+// an array's items, split on the commas that aren't in a string or a nested array (so "," is a key name)
+fn split_items(inner: &str) -> Vec<&str> {
+    let mut items = Vec::new();
+    let (mut depth, mut start) = (0, 0);
+    for (i, c) in outside_strings(inner) {
+        match c {
+            '[' => depth += 1,
+            ']' => depth -= 1,
+            ',' if depth == 0 => {
+                items.push(&inner[start..i]);
+                start = i + 1;
+            },
+            _ => {},
+        }
+    }
+    items.push(&inner[start..]);
+    items
+}
+
+// a whole "string" or 'literal string', or None if `text` isn't one (or has an escape TOML doesn't have)
+fn parse_string(text: &str) -> Option<String> {
+    if let Some(inner) = text.strip_prefix('\'') {
+        let inner = inner.strip_suffix('\'')?;
+        return (!inner.contains('\'')).then(|| inner.to_string());
+    }
+    let inner = text.strip_prefix('"')?.strip_suffix('"')?;
+    let mut string = String::new();
+    let mut chars = inner.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '"' => return None,
+            '\\' => string.push(match chars.next()? {
+                '\\' => '\\',
+                '"' => '"',
+                'n' => '\n',
+                't' => '\t',
+                'r' => '\r',
+                'b' => '\u{8}',
+                'f' => '\u{c}',
+                u @ ('u' | 'U') => {
+                    let width = if u == 'u' {4} else {8};
+                    let digits: String = chars.by_ref().take(width).collect();
+                    if digits.len() != width {return None}
+                    char::from_u32(u32::from_str_radix(&digits, 16).ok()?)?
+                },
+                _ => return None,
+            }),
+            _ => string.push(c),
+        }
+    }
+    Some(string)
+}
+
+// keys and table names can be quoted too
+fn unquote(text: &str) -> String {
+    parse_string(text).unwrap_or_else(|| text.to_string())
+}
+
+fn parse_value(text: &str) -> Option<Value> {
+    if let Some(inner) = text.strip_prefix('[') {
+        let inner = inner.strip_suffix(']')?.trim();
+        let items = split_items(inner).into_iter().map(str::trim).filter(|item| !item.is_empty());
+        return items.map(parse_value).collect::<Option<Vec<_>>>().map(Value::Array);
+    }
+    if text.starts_with('"') || text.starts_with('\'') {
+        return parse_string(text).map(Value::String);
+    }
+    match text {
+        "true" => return Some(Value::Boolean(true)),
+        "false" => return Some(Value::Boolean(false)),
+        _ => {},
+    }
+    let digits = text.replace('_', "");
+    match digits.strip_prefix("0x") {
+        Some(hex) => i64::from_str_radix(hex, 16).ok(),
+        None => digits.parse().ok(),
+    }
+    .map(Value::Integer)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn string(text: &str) -> Value {
+        Value::String(String::from(text))
+    }
+
+    #[test]
+    fn comments_start_outside_strings() {
+        assert_eq!(strip_comment("a = 1 # one"), "a = 1 ");
+        assert_eq!(strip_comment("# all of it"), "");
+        assert_eq!(strip_comment(r##"color = "#FFAA00" # amber"##), r##"color = "#FFAA00" "##);
+        assert_eq!(strip_comment("a = 1"), "a = 1");
+    }
+
+    #[test]
+    fn arrays_split_on_commas_outside_strings() {
+        assert_eq!(split_items(r#"",", "Up""#), [r#"",""#, r#" "Up""#]);
+        assert_eq!(split_items("[1, 2], 3"), ["[1, 2]", " 3"]);
+        assert_eq!(parse_value(r#"[",", "Up"]"#), Some(Value::Array(vec![string(","), string("Up")])));
+        assert_eq!(parse_value("[]"), Some(Value::Array(Vec::new())));
+        assert_eq!(parse_value("[1, [2, 3],]"), Some(Value::Array(vec![
+            Value::Integer(1),
+            Value::Array(vec![Value::Integer(2), Value::Integer(3)]),
+        ])));
+    }
+
+    #[test]
+    fn values() {
+        assert_eq!(parse_value("30"), Some(Value::Integer(30)));
+        assert_eq!(parse_value("-2"), Some(Value::Integer(-2)));
+        assert_eq!(parse_value("1_000"), Some(Value::Integer(1000)));
+        assert_eq!(parse_value("0x1F"), Some(Value::Integer(31)));
+        assert_eq!(parse_value("true"), Some(Value::Boolean(true)));
+        assert_eq!(parse_value("false"), Some(Value::Boolean(false)));
+        assert_eq!(parse_value(r#""schip""#), Some(string("schip")));
+        assert_eq!(parse_value("schip"), None);
+        assert_eq!(parse_value(r#""unfinished"#), None);
+        assert_eq!(parse_value("[1, 2"), None);
+    }
+
+    #[test]
+    fn tables_and_errors() {
+        let config = Config::parse("palette = \"amber\"\n\n[keymap]\nlayout = \"dvorak\" # mine\n").unwrap();
+        assert_eq!(config.table("").unwrap().get("palette"), Some(&string("amber")));
+        assert_eq!(config.table("keymap").unwrap().get("layout"), Some(&string("dvorak")));
+        assert_eq!(Config::parse("a = 1\nb\n").unwrap_err(), "2: expected key = value");
+        assert_eq!(Config::parse("[keymap\n").unwrap_err(), "1: expected ] at the end of the table name");
+        assert_eq!(Config::parse("a = nope\n").unwrap_err(), "1: can't read the value for a");
+    }
+
+    #[test]
+    fn rom_settings() {
+        let config = Config::parse("[rom.abc]\nplatform = \"schip\"\ncycles_per_frame = 30\ncolors = [\"#000000\", \"FFAA00\"]\n").unwrap();
+        let settings = RomSettings::from_config(&config, "abc").unwrap();
+        assert_eq!(settings.platform.as_deref(), Some("schip"));
+        assert_eq!(settings.cycles_per_frame, Some(30));
+        assert_eq!(settings.colors, Some(vec![0x000000, 0xFFAA00]));
+        assert!(RomSettings::from_config(&config, "def").unwrap().platform.is_none());
+
+        let error = |text: &str| RomSettings::from_config(&Config::parse(text).unwrap(), "abc").unwrap_err();
+        assert_eq!(error("[rom.abc]\ncycles_per_frame = 0\n"), "cycles_per_frame should be a number of at least 1");
+        assert_eq!(error("[rom.abc]\ncolors = [\"red\"]\n"), "red isn't a colour, they're like \"#2A7B6F\"");
+        assert_eq!(error("[rom.abc]\nspeed = 3\n"), "unknown setting speed");
+    }
+
+    #[test]
+    fn basic_strings_have_escapes() {
+        assert_eq!(parse_value(r#""\\""#), Some(string("\\")));
+        assert_eq!(parse_value(r#""say \"hi\"""#), Some(string("say \"hi\"")));
+        assert_eq!(parse_value(r#""tab\there\n""#), Some(string("tab\there\n")));
+        assert_eq!(parse_value(r#""\u00e9\U0001F600""#), Some(string("\u{e9}\u{1F600}")));
+    }
+
+    #[test]
+    fn bad_escapes_are_refused() {
+        assert_eq!(parse_value(r#""\q""#), None);
+        assert_eq!(parse_value(r#""\u12""#), None);
+        assert_eq!(parse_value(r#""\""#), None);
+        assert_eq!(parse_value(r#""a"b""#), None);
+    }
+
+    #[test]
+    fn literal_strings_are_taken_as_they_are() {
+        assert_eq!(parse_value(r"'\'"), Some(string("\\")));
+        assert_eq!(parse_value(r#"'C:\roms\"pong".ch8'"#), Some(string(r#"C:\roms\"pong".ch8"#)));
+        assert_eq!(parse_value("'it's'"), None);
+    }
+
+    #[test]
+    fn escaped_quotes_dont_end_a_string() {
+        assert_eq!(strip_comment(r##"a = "\"#" # comment"##), r##"a = "\"#" "##);
+        assert_eq!(strip_comment(r#"a = '\' # comment"#), r#"a = '\' "#);
+        assert_eq!(split_items(r##""\"", "\\", ',', "#""##), [r#""\"""#, r#" "\\""#, " ','", r##" "#""##]);
+    }
+
+    #[test]
+    fn keys_and_table_names_can_be_quoted() {
+        let config = Config::parse("[rom.'abc'.keymap]\n\"1\" = 'Q'\n").unwrap();
+        assert_eq!(config.table("rom.abc.keymap").unwrap().get("1"), Some(&string("Q")));
+    }
+}
@@ -0,0 +1,111 @@
+// the debugger's side panel: registers, timers, the stack and the disassembly around the PC
+// drawn straight into the window buffer next to the CHIP-8 screen
+
+use chip8_core::{disasm::{self, Syntax}, watch::Access, Assembly, Chip8, Debugger, Stop};
+use crate::font::{draw_text, GLYPH_HEIGHT, GLYPH_WIDTH};
+
+pub const PANEL_WIDTH: usize = 320;
+
+const TEXT_SCALE: usize = 2;
+const CHAR_WIDTH: usize = (GLYPH_WIDTH + 1) * TEXT_SCALE;
+const LINE_HEIGHT: usize = (GLYPH_HEIGHT + 1) * TEXT_SCALE;
+const MARGIN: usize = 6;
+
+const BACKGROUND: u32 = 0x3D405B;
+const TEXT: u32 = 0xF2EEDD;
+const HIGHLIGHT: u32 = 0xE07A5F; // the current instruction and breakpoints
+const DIM: u32 = 0x9A9CB0; // key help
+
+const DISASSEMBLY_BEFORE: usize = 4; // instructions shown above the PC
+
+// `buffer` is the whole window, the panel fills the columns from `left` to the right hand edge
+// `source` is the assembled program and its lines, when we're running Octo source
+pub fn draw(buffer: &mut [u32], buffer_width: usize, left: usize, chip8: &Chip8, debugger: &Debugger, source: Option<(&Assembly, &[String])>) {
+    let height = buffer.len() / buffer_width;
+    for row in buffer.chunks_exact_mut(buffer_width) {
+        row[left..].fill(BACKGROUND);
+    }
+
+    let mut lines: Vec<(String, u32)> = Vec::new();
+    let pc = chip8.program_counter();
+
+    let status = match chip8.fault() {
+        Some(err) => format!("HALTED: {}", err),
+        None if debugger.is_paused() => format!("PAUSED AT {:04X}", pc),
+        None => String::from("RUNNING"),
+    };
+    lines.push((status, HIGHLIGHT));
+    if let Some(reason) = stop_reason(debugger) {
+        lines.push((reason, HIGHLIGHT));
+    }
+    if let Some((assembly, text)) = source {
+        let line = match assembly.line_at(pc) {
+            Some(number) => format!("LINE {} {}", number, text.get(number - 1).map_or("", |line| line.trim())),
+            None => String::from("LINE -"),
+        };
+        lines.push((line, TEXT));
+    }
+    lines.push((format!("PC {:04X}  I {:04X}  SP {}", pc, chip8.index(), chip8.stack().len()), TEXT));
+    for (i, values) in chip8.registers().chunks(4).enumerate() {
+        let registers: Vec<String> = values.iter().enumerate().map(|(j, value)| format!("V{:X} {:02X}", i * 4 + j, value)).collect();
+        lines.push((registers.join("  "), TEXT));
+    }
+    lines.push((format!("DT {:02X}  ST {:02X}", chip8.delay_timer.current_time, chip8.sound_timer.current_time), TEXT));
+    // the stack wraps onto more lines when it's deep
+    let stack: Vec<String> = chip8.stack().iter().map(|address| format!("{:04X}", address)).collect();
+    if stack.is_empty() {
+        lines.push((String::from("STACK -"), TEXT));
+    }
+    for (i, chunk) in stack.chunks(6).enumerate() {
+        let label = if i == 0 {"STACK"} else {"     "};
+        lines.push((format!("{} {}", label, chunk.join(" ")), TEXT));
+    }
+    lines.push((String::new(), TEXT));
+
+    let help = [
+        "F8 RUN/PAUSE  F2 BREAKPOINT",
+        "F11 STEP  F10 STEP OVER",
+        "SHIFT+F11 RUN TO RETURN",
+    ];
+    // instructions are 2 bytes, so the ones before the PC are a guess (data could be mixed in)
+    let rows = (height - MARGIN) / LINE_HEIGHT;
+    let disassembly_rows = rows.saturating_sub(lines.len() + help.len() + 1);
+    let mut address = pc.wrapping_sub(2 * DISASSEMBLY_BEFORE.min(disassembly_rows / 2) as u16) as usize;
+    for _ in 0..disassembly_rows {
+        let instruction = disasm::disassemble(chip8.memory(), address, chip8.platform(), Syntax::Cowgod);
+        let address_u16 = chip8.memory().wrap(address) as u16;
+        let marker = match (address_u16 == pc, debugger.has_breakpoint(address_u16)) {
+            (true, _) => '>',
+            (false, true) => '*',
+            _ => ' ',
+        };
+        let colour = if address_u16 == pc || debugger.has_breakpoint(address_u16) {HIGHLIGHT} else {TEXT};
+        lines.push((format!("{}{:04X} {:04X} {}", marker, address_u16, instruction.opcode, instruction.text), colour));
+        address += instruction.len;
+    }
+
+    lines.push((String::new(), TEXT));
+    lines.extend(help.iter().map(|line| (String::from(*line), DIM)));
+
+    let columns = (buffer_width - left - MARGIN) / CHAR_WIDTH;
+    for (i, (line, colour)) in lines.iter().enumerate() {
+        let text: String = line.chars().take(columns).collect();
+        draw_text(buffer, buffer_width, left + MARGIN, MARGIN + i * LINE_HEIGHT, &text, *colour, TEXT_SCALE);
+    }
+}
+
+// what made the debugger stop, for the panel and the title bar
+pub fn stop_reason(debugger: &Debugger) -> Option<String> {
+    if !debugger.is_paused() {
+        return None;
+    }
+    match debugger.last_stop()? {
+        Stop::Breakpoint(address) => Some(format!("breakpoint at {:#05X}", address)),
+        Stop::Condition(index) => debugger.conditions().nth(index).map(|condition| format!("{}", condition)),
+        Stop::Watchpoint { pc, hit } => Some(match hit.access {
+            Access::Read => format!("{:#05X} read {:#04X} from {:#05X}", pc, hit.value, hit.address),
+            Access::Write => format!("{:#05X} wrote {:#04X} to {:#05X}", pc, hit.value, hit.address),
+        }),
+        Stop::Finished => None,
+    }
+}
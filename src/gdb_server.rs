@@ -0,0 +1,70 @@
+// --gdb PORT: a gdb remote serial protocol server on localhost, polled once a frame from the main loop
+// the protocol itself is chip8_core::gdb, this just moves bytes between it and the socket
+
+use std::io::{self, ErrorKind, Read, Write};
+use std::net::{TcpListener, TcpStream};
+
+use chip8_core::{gdb::GdbStub, Chip8, Debugger};
+
+pub struct GdbServer {
+    listener: TcpListener,
+    client: Option<TcpStream>, // one at a time, like gdbserver
+    stub: GdbStub,
+}
+
+impl GdbServer {
+    pub fn bind(port: u16) -> io::Result<Self> {
+        let listener = TcpListener::bind(("127.0.0.1", port))?;
+        listener.set_nonblocking(true)?;
+        Ok(GdbServer { listener, client: None, stub: GdbStub::new() })
+    }
+
+    // picks up a new connection, answers whatever gdb has sent and reports a continue stopping
+    pub fn poll(&mut self, chip8: &mut Chip8, debugger: &mut Debugger) {
+        if self.client.is_none() {
+            if let Ok((stream, address)) = self.listener.accept() {
+                if stream.set_nonblocking(true).is_ok() {
+                    println!("gdb connected from {}", address);
+                    // gdb expects the target to be stopped when it attaches
+                    debugger.pause();
+                    self.stub = GdbStub::new();
+                    self.client = Some(stream);
+                }
+            }
+        }
+        let Some(client) = &mut self.client else {return};
+
+        let mut received = Vec::new();
+        let mut chunk = [0; 1024];
+        let mut closed = false;
+        loop {
+            match client.read(&mut chunk) {
+                Ok(0) => {
+                    closed = true;
+                    break;
+                },
+                Ok(n) => received.extend_from_slice(&chunk[..n]),
+                Err(err) if err.kind() == ErrorKind::WouldBlock => break,
+                Err(_) => {
+                    closed = true;
+                    break;
+                },
+            }
+        }
+
+        let mut reply = self.stub.receive(&received, chip8, debugger);
+        if let Some(stop) = self.stub.poll(chip8, debugger) {
+            reply.extend(stop);
+        }
+        if !reply.is_empty() && client.write_all(&reply).is_err() {
+            closed = true;
+        }
+
+        if closed || self.stub.is_detached() {
+            println!("gdb disconnected");
+            self.client = None;
+            // don't leave the program stuck paused with nobody to resume it
+            debugger.resume();
+        }
+    }
+}
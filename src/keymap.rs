@@ -0,0 +1,332 @@
+// which host keys press which CHIP-8 keys
+// a layout puts the keypad on the 4x4 block under 1-4 (wherever that is on your keyboard),
+// then [keymap] in the config can rebind keys, and [rom.<sha1>.keymap] rebind them again for one ROM:
+//
+//   [keymap]
+//   layout = "azerty"            # qwerty (the default), azerty or dvorak
+//   5 = ["Z", "Up"]              # any number of host keys per CHIP-8 key
+//
+//   [rom.0c9bd4c3d9d7d2cd4a5d5a3e7c2f1b6f0a8e7d12.keymap]
+//   4 = "Left"
+//   6 = "Right"
+
+use std::collections::BTreeMap;
+
+use minifb::Key;
+
+use crate::config::{Config, Value};
+
+// the CHIP-8 keypad, row by row
+//   1 2 3 C
+//   4 5 6 D
+//   7 8 9 E
+//   A 0 B F
+const KEYPAD: [u8; 16] = [0x1, 0x2, 0x3, 0xC, 0x4, 0x5, 0x6, 0xD, 0x7, 0x8, 0x9, 0xE, 0xA, 0x0, 0xB, 0xF];
+
+const QWERTY: [Key; 16] = [
+    Key::Key1, Key::Key2, Key::Key3, Key::Key4,
+    Key::Q, Key::W, Key::E, Key::R,
+    Key::A, Key::S, Key::D, Key::F,
+    Key::Z, Key::X, Key::C, Key::V,
+];
+const AZERTY: [Key; 16] = [
+    Key::Key1, Key::Key2, Key::Key3, Key::Key4,
+    Key::A, Key::Z, Key::E, Key::R,
+    Key::Q, Key::S, Key::D, Key::F,
+    Key::W, Key::X, Key::C, Key::V,
+];
+const DVORAK: [Key; 16] = [
+    Key::Key1, Key::Key2, Key::Key3, Key::Key4,
+    Key::Apostrophe, Key::Comma, Key::Period, Key::P,
+    Key::A, Key::O, Key::E, Key::U,
+    Key::Semicolon, Key::Q, Key::J, Key::K,
+];
+
+// the keys the emulator itself uses (main.rs handles them), which can't press CHIP-8 keys as well
+// shift counts too, since it's held with F4 and F11
+const HOTKEYS: [(Key, &str); 19] = [
+    (Key::Escape, "quit"),
+    (Key::Backspace, "rewind"),
+    (Key::Tab, "fast-forward"),
+    (Key::Equal, "speed up"),
+    (Key::NumPadPlus, "speed up"),
+    (Key::Minus, "slow down"),
+    (Key::NumPadMinus, "slow down"),
+    (Key::LeftShift, "Shift+F4 and Shift+F11"),
+    (Key::RightShift, "Shift+F4 and Shift+F11"),
+    (Key::F2, "toggle breakpoint"),
+    (Key::F3, "slow motion"),
+    (Key::F4, "next palette"),
+    (Key::F5, "save state"),
+    (Key::F6, "previous save slot"),
+    (Key::F7, "next save slot"),
+    (Key::F8, "pause"),
+    (Key::F9, "load state"),
+    (Key::F10, "step over"),
+    (Key::F11, "step"),
+];
+
+pub struct Keymap {
+    bindings: [Vec<Key>; 16], // the host keys for each CHIP-8 key
+}
+
+impl Keymap {
+    // the layout, then the controls the ROM database lists for the game, then the global [keymap], then the ROM's own
+    pub fn from_config(config: &Config, rom_hash: &str, controls: Option<&BTreeMap<String, u8>>) -> Result<Keymap, String> {
+        let global = config.table("keymap");
+        let rom = config.table(&format!("rom.{}.keymap", rom_hash));
+
+        let layout = rom.and_then(|table| table.get("layout")).or_else(|| global.and_then(|table| table.get("layout")));
+        let layout = match layout {
+            None => QWERTY,
+            Some(Value::String(name)) => layout_named(name).ok_or_else(|| format!("unknown keyboard layout {}, try qwerty, azerty or dvorak", name))?,
+            Some(_) => return Err(String::from("layout should be a string, e.g. layout = \"azerty\"")),
+        };
+
+        let mut bindings: [Vec<Key>; 16] = Default::default();
+        for (position, &key) in layout.iter().enumerate() {
+            bindings[KEYPAD[position] as usize].push(key);
+        }
+        // the arrows for the game's directions, space and ctrl for its buttons, alongside the keypad
+        // (not shift, that's held for Shift+F4 and Shift+F11)
+        for (name, &chip8_key) in controls.into_iter().flatten() {
+            let key = match name.as_str() {
+                "up" => Key::Up,
+                "down" => Key::Down,
+                "left" => Key::Left,
+                "right" => Key::Right,
+                "a" => Key::Space,
+                "b" => Key::LeftCtrl,
+                _ => continue,
+            };
+            bindings[chip8_key as usize & 0xF].push(key);
+        }
+        for table in [global, rom].into_iter().flatten() {
+            rebind(&mut bindings, table)?;
+        }
+        Ok(Keymap { bindings })
+    }
+
+    // which CHIP-8 keys are down, given the host keys that are
+    pub fn keypad(&self, held: &[Key]) -> [bool; 16] {
+        let mut keypad = [false; 16];
+        for (chip8_key, keys) in self.bindings.iter().enumerate() {
+            keypad[chip8_key] = keys.iter().any(|key| held.contains(key));
+        }
+        keypad
+    }
+}
+
+// `5 = ["W", "Up"]` replaces whatever 5 was bound to, `5 = []` unbinds it
+fn rebind(bindings: &mut [Vec<Key>; 16], table: &BTreeMap<String, Value>) -> Result<(), String> {
+    for (name, value) in table {
+        if name == "layout" {continue}
+        let chip8_key = u8::from_str_radix(name, 16).ok().filter(|&key| key < 16)
+            .ok_or_else(|| format!("{} isn't a CHIP-8 key, they're 0 to F", name))?;
+        let names = match value {
+            Value::String(name) => vec![name.as_str()],
+            Value::Array(values) => values.iter().map(|value| match value {
+                Value::String(name) => Ok(name.as_str()),
+                _ => Err(format!("the keys for {} should be strings, e.g. [\"W\", \"Up\"]", name)),
+            }).collect::<Result<Vec<_>, _>>()?,
+            _ => return Err(format!("the keys for {} should be a string or a list of them", name)),
+        };
+        bindings[chip8_key as usize] = names.iter()
+            .map(|key| {
+                let host = host_key(key).ok_or_else(|| format!("unknown key {} (for CHIP-8 key {})", key, name))?;
+                match HOTKEYS.iter().find(|(hotkey, _)| *hotkey == host) {
+                    Some((_, action)) => Err(format!("{} is the emulator's {} key, so it can't be CHIP-8 key {} too", key, action, name)),
+                    None => Ok(host),
+                }
+            })
+            .collect::<Result<_, _>>()?;
+    }
+    Ok(())
+}
+
+fn layout_named(name: &str) -> Option<[Key; 16]> {
+    match name.to_ascii_lowercase().as_str() {
+        "qwerty" => Some(QWERTY),
+        "azerty" => Some(AZERTY),
+        "dvorak" => Some(DVORAK),
+        _ => None,
+    }
+}
+
+// the names minifb gives keys, without the Key:: (and Key0-Key9 can just be 0-9), any case
+fn host_key(name: &str) -> Option<Key> {
+    let name = name.to_ascii_lowercase();
+    let letters = [
+        Key::A, Key::B, Key::C, Key::D, Key::E, Key::F, Key::G, Key::H, Key::I, Key::J, Key::K, Key::L, Key::M,
+        Key::N, Key::O, Key::P, Key::Q, Key::R, Key::S, Key::T, Key::U, Key::V, Key::W, Key::X, Key::Y, Key::Z,
+    ];
+    let digits = [Key::Key0, Key::Key1, Key::Key2, Key::Key3, Key::Key4, Key::Key5, Key::Key6, Key::Key7, Key::Key8, Key::Key9];
+    let numpad = [
+        Key::NumPad0, Key::NumPad1, Key::NumPad2, Key::NumPad3, Key::NumPad4,
+        Key::NumPad5, Key::NumPad6, Key::NumPad7, Key::NumPad8, Key::NumPad9,
+    ];
+    let single = |name: &str| name.chars().next().filter(|_| name.chars().count() == 1);
+
+    if let Some(c) = single(&name).filter(char::is_ascii_lowercase) {
+        return Some(letters[(c as u8 - b'a') as usize]);
+    }
+    let digit = name.strip_prefix("key").unwrap_or(&name);
+    if let Some(c) = single(digit).filter(char::is_ascii_digit) {
+        return Some(digits[(c as u8 - b'0') as usize]);
+    }
+    if let Some(c) = name.strip_prefix("numpad").and_then(single).filter(char::is_ascii_digit) {
+        return Some(numpad[(c as u8 - b'0') as usize]);
+    }
+    let key = match name.as_str() {
+        // the punctuation keys can be given as themselves, e.g. "," for Comma
+        "'" => Key::Apostrophe,
+        "," => Key::Comma,
+        "." => Key::Period,
+        ";" => Key::Semicolon,
+        "/" => Key::Slash,
+        "\\" => Key::Backslash,
+        "-" => Key::Minus,
+        "=" => Key::Equal,
+        "[" => Key::LeftBracket,
+        "]" => Key::RightBracket,
+        "`" => Key::Backquote,
+        "up" => Key::Up,
+        "down" => Key::Down,
+        "left" => Key::Left,
+        "right" => Key::Right,
+        "space" => Key::Space,
+        "enter" => Key::Enter,
+        "tab" => Key::Tab,
+        "escape" => Key::Escape,
+        "backspace" => Key::Backspace,
+        "leftshift" => Key::LeftShift,
+        "rightshift" => Key::RightShift,
+        "leftctrl" => Key::LeftCtrl,
+        "rightctrl" => Key::RightCtrl,
+        "leftalt" => Key::LeftAlt,
+        "rightalt" => Key::RightAlt,
+        "apostrophe" => Key::Apostrophe,
+        "comma" => Key::Comma,
+        "period" => Key::Period,
+        "semicolon" => Key::Semicolon,
+        "slash" => Key::Slash,
+        "backslash" => Key::Backslash,
+        "minus" => Key::Minus,
+        "equal" => Key::Equal,
+        "leftbracket" => Key::LeftBracket,
+        "rightbracket" => Key::RightBracket,
+        "backquote" => Key::Backquote,
+        "numpadplus" => Key::NumPadPlus,
+        "numpadminus" => Key::NumPadMinus,
+        "numpadasterisk" => Key::NumPadAsterisk,
+        "numpadslash" => Key::NumPadSlash,
+        "numpaddot" => Key::NumPadDot,
+        "numpadenter" => Key::NumPadEnter,
+        "insert" => Key::Insert,
+        "delete" => Key::Delete,
+        "home" => Key::Home,
+        "end" => Key::End,
+        "pageup" => Key::PageUp,
+        "pagedown" => Key::PageDown,
+        _ => return function_key(&name),
+    };
+    Some(key)
+}
+
+// F1 to F12 (F1 and F12 are free, the rest are hotkeys)
+fn function_key(name: &str) -> Option<Key> {
+    let keys = [Key::F1, Key::F2, Key::F3, Key::F4, Key::F5, Key::F6, Key::F7, Key::F8, Key::F9, Key::F10, Key::F11, Key::F12];
+    let n: usize = name.strip_prefix('f')?.parse().ok()?;
+    keys.get(n.checked_sub(1)?).copied()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn keymap(config: &str, controls: Option<&BTreeMap<String, u8>>) -> Result<Keymap, String> {
+        Keymap::from_config(&Config::parse(config).unwrap(), "abc", controls)
+    }
+
+    fn keys(keymap: &Keymap, chip8_key: usize) -> &[Key] {
+        &keymap.bindings[chip8_key]
+    }
+
+    #[test]
+    fn qwerty_by_default() {
+        let keymap = keymap("", None).unwrap();
+        assert_eq!(keys(&keymap, 0x1), [Key::Key1]);
+        assert_eq!(keys(&keymap, 0xC), [Key::Key4]);
+        assert_eq!(keys(&keymap, 0x5), [Key::W]);
+        assert_eq!(keys(&keymap, 0x0), [Key::X]);
+        assert_eq!(keys(&keymap, 0xF), [Key::V]);
+        let mut keypad = [false; 16];
+        keypad[0x5] = true;
+        assert_eq!(keymap.keypad(&[Key::W, Key::Y]), keypad);
+    }
+
+    #[test]
+    fn layouts() {
+        let keymap = keymap("[keymap]\nlayout = \"AZERTY\"\n", None).unwrap();
+        assert_eq!(keys(&keymap, 0x4), [Key::A]);
+        assert_eq!(keys(&keymap, 0xA), [Key::W]);
+        assert_eq!(self::keymap("[keymap]\nlayout = \"colemak\"\n", None).err().unwrap(), "unknown keyboard layout colemak, try qwerty, azerty or dvorak");
+    }
+
+    #[test]
+    fn the_roms_keymap_wins_over_the_global_one() {
+        let config = "[keymap]\nlayout = \"dvorak\"\n5 = [\"Up\", \"I\"]\n6 = \"L\"\n\n[rom.abc.keymap]\nlayout = \"qwerty\"\n5 = \",\"\n";
+        let keymap = keymap(config, None).unwrap();
+        // the ROM's layout, then the global bindings, then the ROM's
+        assert_eq!(keys(&keymap, 0x4), [Key::Q]);
+        assert_eq!(keys(&keymap, 0x6), [Key::L]);
+        assert_eq!(keys(&keymap, 0x5), [Key::Comma]);
+        // and only for that ROM
+        let other = Keymap::from_config(&Config::parse(config).unwrap(), "def", None).unwrap();
+        assert_eq!(keys(&other, 0x4), [Key::Apostrophe]);
+        assert_eq!(keys(&other, 0x5), [Key::Up, Key::I]);
+    }
+
+    #[test]
+    fn database_controls_go_alongside_the_keypad() {
+        let controls = BTreeMap::from([(String::from("up"), 5), (String::from("b"), 6), (String::from("start"), 1)]);
+        let keymap = keymap("", Some(&controls)).unwrap();
+        assert_eq!(keys(&keymap, 0x5), [Key::W, Key::Up]);
+        assert_eq!(keys(&keymap, 0x6), [Key::E, Key::LeftCtrl]);
+        assert_eq!(keys(&keymap, 0x1), [Key::Key1]);
+    }
+
+    #[test]
+    fn key_names() {
+        assert_eq!(host_key("numpad7"), Some(Key::NumPad7));
+        assert_eq!(host_key("Key3"), Some(Key::Key3));
+        assert_eq!(host_key("3"), Some(Key::Key3));
+        assert_eq!(host_key("LeftCtrl"), Some(Key::LeftCtrl));
+        assert_eq!(host_key("\\"), Some(Key::Backslash));
+        assert_eq!(host_key("nope"), None);
+        let keymap = keymap("[keymap]\n1 = \"\\\\\"\n2 = '\\'\n3 = []\n", None).unwrap();
+        assert_eq!(keys(&keymap, 0x1), [Key::Backslash]);
+        assert_eq!(keys(&keymap, 0x2), [Key::Backslash]);
+        assert_eq!(keys(&keymap, 0x3), []);
+    }
+
+    #[test]
+    fn hotkeys_cant_be_bound() {
+        let error = |config: &str| keymap(config, None).err().unwrap();
+        assert_eq!(error("[keymap]\n5 = \"Escape\"\n"), "Escape is the emulator's quit key, so it can't be CHIP-8 key 5 too");
+        assert_eq!(error("[keymap]\n5 = [\"W\", \"F5\"]\n"), "F5 is the emulator's save state key, so it can't be CHIP-8 key 5 too");
+        assert_eq!(error("[rom.abc.keymap]\nA = \"LeftShift\"\n"), "LeftShift is the emulator's Shift+F4 and Shift+F11 key, so it can't be CHIP-8 key A too");
+        assert_eq!(error("[keymap]\n1 = \"=\"\n"), "= is the emulator's speed up key, so it can't be CHIP-8 key 1 too");
+        // F1 and F12 aren't the emulator's
+        assert!(keymap("[keymap]\n1 = \"F1\"\n2 = \"F12\"\n", None).is_ok());
+    }
+
+    #[test]
+    fn bad_bindings() {
+        let error = |config: &str| keymap(config, None).err().unwrap();
+        assert_eq!(error("[keymap]\nG = \"W\"\n"), "G isn't a CHIP-8 key, they're 0 to F");
+        assert_eq!(error("[keymap]\n5 = \"Hyper\"\n"), "unknown key Hyper (for CHIP-8 key 5)");
+        assert_eq!(error("[keymap]\n5 = 3\n"), "the keys for 5 should be a string or a list of them");
+        assert_eq!(error("[keymap]\n5 = [3]\n"), "the keys for 5 should be strings, e.g. [\"W\", \"Up\"]");
+    }
+}
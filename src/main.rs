@@ -1,125 +1,754 @@
 // control center
 
-// I have decided to take the filepath to the program as a command line argument for now
+// the ROM and everything else comes from the command line (cli.rs has the options)
 // maybe a simply gui in future
 
-mod chip8;
-mod memory;
-mod timer;
+// the emulator itself lives in the chip8_core crate, this is just the minifb window and rodio audio around it
+
 mod audio_state;
-mod opcode;
+mod cli;
+mod config;
+mod debug_panel;
+mod font;
+mod gdb_server;
+mod keymap;
+mod palette;
 
-use std::{env, time::Instant};
-use minifb::{Key, Window, WindowOptions, KeyRepeat};
-use crate::{audio_state::AudioState, chip8::Chip8};
+use std::{env, fs, io::{self, BufWriter, Write}, process, time::{Duration, Instant, SystemTime, UNIX_EPOCH}};
+use minifb::{Key, Window, WindowOptions, KeyRepeat, Scale};
+use chip8_core::{assemble, disasm::{self, Syntax}, display::{HIRES_HEIGHT, HIRES_WIDTH}, hash, memory::DEFAULT_LOAD_ADDRESS, runner::Run, trace, Chip8, Coverage, Chip8Error, Database, Debugger, Memory, MemoryIncrement, Movie, Platform, Playback, Profile, Quirks, Rewind, RomInfo, Trace};
+use crate::audio_state::AudioState;
+use crate::cli::{Command, RunOptions};
+use crate::config::{Config, RomSettings, Value};
+use crate::debug_panel::PANEL_WIDTH;
+use crate::gdb_server::GdbServer;
+use crate::keymap::Keymap;
+use crate::palette::Palettes;
 
+// the window is sized for the 64x32 screen (times --scale), the 128x64 hi-res screen is drawn to fit
 const WIDTH: usize = 64;
 const HEIGHT: usize = 32;
-const SCALE: usize = 10;
 
 const TICK_RATE: f64 = 60.0;
 const TICK_DURATION: f64 = 1.0 / TICK_RATE;
-const CYCLES_PER_FRAME: usize = 10; // 8-16 to run at 500-1000hz (given the tick rate) -- adjust as necessary for the program you want to run
+const CYCLES_PER_FRAME: usize = 10; // 8-16 to run at 500-1000hz (given the tick rate), the default when nothing else (--ipf, the config, the ROM database) says
+const REWIND_SECONDS: usize = 20; // how far back holding backspace can go
+const MAX_CATCH_UP: f64 = 0.25; // at most this many seconds of frames are run at once
+const MAX_CYCLES_PER_FRAME: usize = 100_000; // where + stops, plenty for any ROM
+const FAST_FORWARD: f64 = 4.0; // frames run for each one shown
+const SLOW_MOTION: f64 = 0.25;
 
 fn main() {
+    let command = cli::parse(env::args().skip(1).collect()).unwrap_or_else(|(err, usage)| cli::fail_with_usage(&err, usage));
+    match command {
+        Command::Run(options) => run(*options),
+        // `chip8 disasm rom.ch8` prints the ROM as source instead of running it
+        Command::Disasm { rom_path, platform, syntax } => disasm_command(&rom_path, platform, syntax),
+        // `chip8 asm game.8o` compiles Octo source into a ROM
+        Command::Asm { source_path, output, source_map } => asm_command(&source_path, output, source_map),
+        Command::Info { rom_path, config } => info_command(&rom_path, config.as_deref()),
+        Command::Help(usage) => println!("{}", usage),
+    }
+}
+
+fn run(options: RunOptions) {
+    let file_path = &options.rom_path;
+    let mut breakpoints = options.breakpoints.clone();
+
+    // Octo source is assembled on the way in, and the debugger shows which line is running
+    let source = file_path.ends_with(".8o").then(|| {
+        // the assembler lays programs out from 0x200
+        if options.load_address.is_some_and(|address| address != DEFAULT_LOAD_ADDRESS) {
+            cli::fail("Octo source is assembled for 0x200, so --load-address can't be used with it");
+        }
+        let text = fs::read_to_string(file_path).unwrap_or_else(|err| cli::fail(&format!("can't read {}: {}", file_path, err)));
+        let assembly = assemble(&text).unwrap_or_else(|err| {
+            report_assembly_error(file_path, &text, &err);
+            process::exit(1);
+        });
+        let lines: Vec<String> = text.lines().map(String::from).collect();
+        (assembly, lines)
+    });
+    let program = match &source {
+        Some((assembly, _)) => assembly.rom.clone(),
+        None => fs::read(file_path).unwrap_or_else(|err| cli::fail(&format!("can't read {}: {}", file_path, err))),
+    };
+    // :breakpoint in the source works like --break
+    if let Some((assembly, _)) = &source {
+        breakpoints.extend(assembly.breakpoints.iter().map(|(_, address)| *address));
+    }
+
+    let debugging = options.debugging() || !breakpoints.is_empty();
 
-    let args: Vec<String> = env::args().collect();
-    let file_path = args
-        .get(1)
-        .expect("Please provide a filepath as the first command line argument.");
+    // known ROMs get their platform, quirks, speed, keys and colours from the ROM database, looked up by hash
+    // (the same SHA-1 load_program records), and [rom.<sha1>] in the config can set them too
+    let config = Config::load(options.config.as_deref()).unwrap_or_else(|err| cli::fail(&err));
+    let database = load_database(&config).unwrap_or_else(|err| cli::fail(&err));
+    let settings = Settings::resolve(&config, &database, &program, &options).unwrap_or_else(|err| cli::fail(&err));
+    if let Some(info) = &settings.info {
+        note(&options, &format!("{} is in the ROM database ({}, {})", info.title, info.platform.name(), info.cycles_per_frame.map_or(String::from("default speed"), |n| format!("{} instructions a frame", n))));
+    }
+
+    // the keymap can be different for each ROM too
+    let keymap = Keymap::from_config(&config, &settings.rom_hash, settings.info.as_ref().map(|info| &info.keys))
+        .unwrap_or_else(|err| cli::fail(&format!("bad keymap in the config: {}", err)));
+
+    // a replay runs on exactly the machine it was recorded on, so the platform and seed come from the movie
+    let mut playback = options.replay.as_ref().map(|path| {
+        let text = fs::read_to_string(path).unwrap_or_else(|err| cli::fail(&format!("can't read {}: {}", path, err)));
+        let movie = Movie::parse(&text).unwrap_or_else(|err| cli::fail(&format!("{}: {}", path, err)));
+        if !movie.matches_rom(&program) {
+            cli::fail(&format!("{} was recorded with a different ROM", path));
+        }
+        Playback::new(movie)
+    });
 
-    let mut chip8 = Chip8::new();
-    chip8.load_program(file_path).expect("Failed to load program. Please check that it's a standard CHIP-8 ROM.");
+    let (mut chip8, mut cycles_per_frame) = match &playback {
+        Some(playback) => (playback.movie().new_machine(), playback.movie().cycles_per_frame as usize),
+        None => {
+            let memory_size = options.memory_size.unwrap_or(settings.platform.memory_size());
+            let load_address = options.load_address.unwrap_or(DEFAULT_LOAD_ADDRESS);
+            Memory::check(memory_size, load_address).unwrap_or_else(|err| cli::fail(&err.to_string()));
+            let mut chip8 = Chip8::with_memory(settings.platform, settings.quirks, Memory::new(memory_size, load_address));
+
+            // without --seed, a different seed every run so CXNN is properly random
+            // it's printed so a run that went wrong can be repeated
+            let seed = options.seed.unwrap_or_else(|| SystemTime::now().duration_since(UNIX_EPOCH).map(|t| t.as_nanos() as u64).unwrap_or(0));
+            chip8.seed_rng(seed);
+            note(&options, &format!("RNG seed: {}", seed));
+            (chip8, settings.cycles_per_frame)
+        },
+    };
+
+    chip8.set_error_policy(options.on_fault);
+    if let Err(err) = chip8.load_program(&program) {
+        cli::fail(&format!("can't load {}: {}", file_path, err));
+    }
 
+    let mut recording = options.record.as_ref().map(|_| Movie::new(&program, &chip8, cycles_per_frame as u32));
+
+    let mut debugger = Debugger::new();
+    breakpoints.into_iter().for_each(|address| debugger.add_breakpoint(address));
+    options.conditions.iter().for_each(|condition| debugger.add_condition(*condition));
+    options.watchpoints.iter().for_each(|watchpoint| chip8.add_watchpoint(*watchpoint));
+    if options.profile.is_some() || options.profile_folded.is_some() {
+        chip8.set_profile(Some(Profile::new()));
+    }
+    if options.coverage.is_some() {
+        chip8.set_coverage(Some(Coverage::new(chip8.memory().size())));
+    }
+    if options.trace.is_some() || options.trace_last.is_some() {
+        chip8.set_trace(Some(Trace::new(options.trace_range, options.trace_last)));
+    }
+    // a whole trace is written as it goes, a --trace-last one once the program faults
+    let mut trace_out = match (&options.trace, options.trace_last) {
+        (Some(path), None) => Some(open_trace(Some(path))),
+        _ => None,
+    };
+
+    // --headless: no window or sound, run for a while and print the screen (the same loop as chip8-headless)
+    if options.headless {
+        let run = Run { frames: options.frames, cycles: u64::MAX, cycles_per_frame: cycles_per_frame as u32 };
+        run.run(&mut chip8, playback.as_mut(), |chip8| flush_trace(chip8, &mut trace_out, &options));
+        flush_trace(&mut chip8, &mut trace_out, &options);
+        finish_trace(&mut trace_out);
+        if let Some(err) = chip8.fault() {
+            eprintln!("halted at {:#05X}: {}", chip8.program_counter(), err);
+        }
+        print!("{}", chip8.display.to_ascii());
+        write_outputs(&options, &chip8, recording, &program);
+        if chip8.fault().is_some() {
+            process::exit(1);
+        }
+        return;
+    }
+
+    let mut gdb = options.gdb.map(|port| {
+        let server = GdbServer::bind(port).unwrap_or_else(|err| cli::fail(&format!("can't listen for gdb on port {}: {}", port, err)));
+        println!("waiting for gdb on localhost:{}", port);
+        server
+    });
+    if options.start_paused {
+        debugger.pause();
+    }
+
+    // the debugger panel sits to the right of the screen
+    // --fullscreen is as close as minifb gets, a borderless window scaled up as far as fits the screen
+    let scale = options.scale;
+    let window_width = if debugging {WIDTH * scale + PANEL_WIDTH} else {WIDTH * scale};
+    let window_options = if options.fullscreen {
+        WindowOptions { borderless: true, topmost: true, scale: Scale::FitScreen, ..WindowOptions::default() }
+    } else {
+        WindowOptions::default()
+    };
     let mut window = Window::new(
         "CHIP-8 Emulator",
-        WIDTH * SCALE,
-        HEIGHT * SCALE,
-        WindowOptions::default(),
+        window_width,
+        HEIGHT * scale,
+        window_options,
     )
-    .expect("Failed to create window");
+    .unwrap_or_else(|err| cli::fail(&format!("can't open a window: {}", err)));
+    if options.start_paused {
+        window.set_title(&format!("CHIP-8 Emulator - paused at {:#05X}", chip8.program_counter()));
+    }
+
+    // F4 goes through the palettes (shift+F4 backwards), starting from this ROM's
+    let mut palette_list = settings.palettes.list().to_vec();
+    let mut palette_index = match palette_list.iter().position(|(_, colours)| *colours == settings.palette) {
+        Some(index) => index,
+        None => {
+            palette_list.insert(0, (String::from("this ROM's"), settings.palette));
+            0
+        },
+    };
 
-    let mut buffer = vec![0; WIDTH * HEIGHT];
+    let mut buffer = vec![0; HIRES_WIDTH * HIRES_HEIGHT];
+    // we draw the whole window ourselves, the screen scaled up, the speed over it and the debugger panel beside it
+    let mut window_buffer = vec![0; window_width * HEIGHT * scale];
 
     // timer stuff
     let mut last = Instant::now();
     let mut accumulator: f64 = 0.0;
-    let mut audio = AudioState::new();
+    let mut audio = if options.mute {AudioState::muted()} else {AudioState::new()};
 
+    // save states: F5 saves, F9 loads, F6/F7 pick the slot (0-9), saved next to the ROM
+    let mut slot: u8 = 0;
 
-    while window.is_open() && !window.is_key_down(Key::Escape) {
+    // hold backspace to play backwards, one snapshot per timer tick
+    let mut rewind = Rewind::new(REWIND_SECONDS * TICK_RATE as usize);
+    let mut was_rewinding = false;
+    let mut replay_finished = false;
+
+    // Tab fast-forwards, F3 slows down, +/- change the instructions per frame (shown on screen for a moment)
+    let mut speed = Speed::Normal;
+    let mut show_ipf_until = Instant::now();
+
+    // which CHIP-8 keys the host keys held down last frame were pressing
+    let mut keypad_state = [false; 16];
+
+
+    // a recording or replay can rewind (the movie goes back with it), but not load a state:
+    // a state could be from any run, and a movie only has the inputs to get from frame 0 to here
+    let keep_timeline = recording.is_some() || playback.is_some();
+
+    // 00FD (SUPER-CHIP exit) closes the emulator too
+    while window.is_open() && !window.is_key_down(Key::Escape) && !chip8.exited {
+
+        // gdb can pause, step, poke memory and set breakpoints between frames
+        if let Some(gdb) = &mut gdb {
+            gdb.poll(&mut chip8, &mut debugger);
+        }
 
         // timer stuff
         let now = Instant::now();
         let dur_len = now.duration_since(last);
         last = now;
-        accumulator += dur_len.as_secs_f64();
+        // after a long stall (e.g. the window being dragged) carry on rather than racing to catch up
+        // fast-forward runs several frames for each one shown, slow motion one every few
+        accumulator = (accumulator + dur_len.as_secs_f64() * speed.factor()).min(MAX_CATCH_UP * speed.factor());
 
-        // update timers if we have a tick
+        // once the program has faulted everything stops, the last frame stays on screen (dimmed)
+        // rewinding still works, so you can go back to before it went wrong
+        let rewinding = window.is_key_down(Key::Backspace);
+        if rewinding && !was_rewinding {
+            window.set_title("CHIP-8 Emulator - rewinding");
+            audio.update(0, None);
+        }
+        if was_rewinding && !rewinding {
+            let frame = chip8.frame_count();
+            if let Some(playback) = &mut playback {
+                // the snapshot's keys are the movie's, so just carry on from here
+                playback.seek(frame);
+                replay_finished = false;
+            } else {
+                // the snapshots have old key presses in them, start again from what's really held down
+                // (a recording drops what came after this frame and records the releases)
+                if let Some(movie) = &mut recording {
+                    movie.truncate(frame);
+                    for (key, _) in chip8.keypad().iter().enumerate().filter(|(_, &down)| down) {
+                        movie.record(frame, key as u8, false);
+                    }
+                }
+                release_all_keys(&mut chip8);
+                keypad_state = [false; 16];
+            }
+        }
+        was_rewinding = rewinding;
+
+        // one frame per tick: this frame's inputs, then its cycles, then the timers
+        // always in that order, so a movie replays exactly the way it was recorded
         while accumulator >= TICK_DURATION {
-            chip8.update_timers();
-            audio.update(chip8.sound_timer.current_time);
             accumulator -= TICK_DURATION;
+            if rewinding {
+                rewind.step_back(&mut chip8);
+                continue;
+            }
+            // while the debugger is paused time stands still, the timers included
+            if chip8.fault().is_some() || chip8.exited || debugger.is_paused() {continue}
+
+            if let Some(playback) = &mut playback {
+                playback.apply(&mut chip8);
+            }
+
+            if let Err(err) = debugger.run(&mut chip8, cycles_per_frame) {
+                report_fault(&mut window, &mut audio, &chip8, err);
+                continue;
+            }
+            // a breakpoint part way through still finishes the frame
+            if debugger.is_paused() {
+                let reason = debug_panel::stop_reason(&debugger).unwrap_or_default();
+                window.set_title(&format!("CHIP-8 Emulator - paused at {:#05X} {}", chip8.program_counter(), reason));
+                audio.update(0, None);
+            }
+
+            chip8.update_timers();
+            // fast-forwarded sound is just noise
+            if speed == Speed::FastForward {
+                audio.update(0, None);
+            } else {
+                audio.update(chip8.sound_timer.current_time, chip8.audio_pattern());
+            }
+            rewind.push(&chip8);
         }
 
-        for _i in 0..CYCLES_PER_FRAME {
-            chip8.run_cycle_once();
+        flush_trace(&mut chip8, &mut trace_out, &options);
+
+        if !replay_finished && playback.as_ref().is_some_and(Playback::is_finished) {
+            // the program keeps running, but from here on nothing is pressed
+            replay_finished = true;
+            window.set_title("CHIP-8 Emulator - replay finished");
         }
 
         // Update the pixel buffer
-        for y in 0..HEIGHT {
-            for x in 0..WIDTH {
-                let color = if chip8.display[y * WIDTH + x] { 0x2A7B6F } else { 0xF2EEDD }; //{ 0xFFFFFF } else { 0x000000 };
-                buffer[y * WIDTH + x] = color;
+        let halted = chip8.fault().is_some();
+        let width = chip8.display.width();
+        let height = chip8.display.height();
+        for (pixel, &colour) in buffer.iter_mut().zip(chip8.display.pixels()) {
+            // classic ROMs only ever use plane 1, XO-CHIP can mix in plane 2 for four colours
+            *pixel = palette_list[palette_index].1[(colour as usize).min(3)]; //{ 0xFFFFFF } else { 0x000000 };
+            if halted {
+                *pixel = (*pixel >> 1) & 0x7F7F7F;
             }
         }
 
+        // nearest neighbour, so hi-res pixels come out half the size of lo-res ones
+        let screen_width = WIDTH * scale;
+        for (y, row) in window_buffer.chunks_exact_mut(window_width).enumerate() {
+            for (x, pixel) in row[..screen_width].iter_mut().enumerate() {
+                *pixel = buffer[(y * height / (HEIGHT * scale)) * width + x * width / screen_width];
+            }
+        }
+        if speed != Speed::Normal || Instant::now() < show_ipf_until {
+            draw_speed(&mut window_buffer, window_width, speed, cycles_per_frame);
+        }
+        if debugging {
+            let panel_source = source.as_ref().map(|(assembly, lines)| (assembly, lines.as_slice()));
+            debug_panel::draw(&mut window_buffer, window_width, screen_width, &chip8, &debugger, panel_source);
+        }
         window
-            .update_with_buffer(&buffer, WIDTH, HEIGHT)
+            .update_with_buffer(&window_buffer, window_width, HEIGHT * scale)
             .expect("Failed to update buffer");
 
-        window.get_keys_pressed(KeyRepeat::No).iter().for_each(|key|
-            match key {
-                Key::Key1 => chip8.update_keypad(0x1,true),
-                Key::Key2 => chip8.update_keypad(0x2,true),
-                Key::Key3 => chip8.update_keypad(0x3,true),
-                Key::Key4 => chip8.update_keypad(0xC,true),
-                Key::Q => chip8.update_keypad(0x4,true),
-                Key::W => chip8.update_keypad(0x5,true),
-                Key::E => chip8.update_keypad(0x6,true),
-                Key::R => chip8.update_keypad(0xD,true),
-                Key::A => chip8.update_keypad(0x7,true),
-                Key::S => chip8.update_keypad(0x8,true),
-                Key::D => chip8.update_keypad(0x9,true),
-                Key::F => chip8.update_keypad(0xE,true),
-                Key::Z => chip8.update_keypad(0xA,true),
-                Key::X => chip8.update_keypad(0x0,true),
-                Key::C => chip8.update_keypad(0xB,true),
-                Key::V => chip8.update_keypad(0xF,true),
-                _ => (),
+        let pressed = window.get_keys_pressed(KeyRepeat::No);
+
+        for &key in &pressed {
+            let message = match key {
+                Key::F5 => match fs::write(state_path(file_path, slot), chip8.save_state()) {
+                    Ok(()) => format!("saved slot {}", slot),
+                    Err(err) => format!("couldn't save slot {}: {}", slot, err),
+                },
+                Key::F9 if keep_timeline => String::from("can't load states while recording or replaying"),
+                Key::F9 => match fs::read(state_path(file_path, slot)) {
+                    Ok(state) => match chip8.load_state(&state) {
+                        Ok(()) => {
+                            // the rewind history belongs to the old timeline
+                            rewind.clear();
+                            release_all_keys(&mut chip8);
+                            keypad_state = [false; 16];
+                            format!("loaded slot {}", slot)
+                        },
+                        Err(err) => format!("slot {}: {}", slot, err),
+                    },
+                    Err(_) => format!("slot {} is empty", slot),
+                },
+                Key::Tab | Key::F3 => {
+                    let toggled = if key == Key::Tab {Speed::FastForward} else {Speed::SlowMotion};
+                    speed = if speed == toggled {Speed::Normal} else {toggled};
+                    // a fresh start, rather than racing through the frames saved up at the old speed
+                    accumulator = 0.0;
+                    speed.label().to_lowercase()
+                },
+                // a movie is played back at the speed it was recorded at
+                Key::Equal | Key::NumPadPlus | Key::Minus | Key::NumPadMinus if keep_timeline => {
+                    String::from("can't change the speed while recording or replaying")
+                },
+                Key::Equal | Key::NumPadPlus | Key::Minus | Key::NumPadMinus => {
+                    // steps of about a tenth, so it's quick to get anywhere from 1 to 1000s
+                    let step = (cycles_per_frame / 10).max(1);
+                    cycles_per_frame = if key == Key::Equal || key == Key::NumPadPlus {
+                        (cycles_per_frame + step).min(MAX_CYCLES_PER_FRAME)
+                    } else {
+                        cycles_per_frame.saturating_sub(step).max(1)
+                    };
+                    show_ipf_until = Instant::now() + Duration::from_secs(2);
+                    format!("{} instructions per frame", cycles_per_frame)
+                },
+                Key::F4 => {
+                    let shift = window.is_key_down(Key::LeftShift) || window.is_key_down(Key::RightShift);
+                    let step = if shift {palette_list.len() - 1} else {1};
+                    palette_index = (palette_index + step) % palette_list.len();
+                    format!("{} palette", palette_list[palette_index].0)
+                },
+                Key::F6 => {
+                    slot = (slot + 9) % 10;
+                    format!("slot {}", slot)
+                },
+                Key::F7 => {
+                    slot = (slot + 1) % 10;
+                    format!("slot {}", slot)
+                },
+                // the debugger: F8 pause / resume, F11 step, F10 step over, shift+F11 run to return, F2 breakpoint at the PC
+                Key::F8 if debugging => {
+                    if debugger.is_paused() {
+                        debugger.resume();
+                        String::from("running")
+                    } else {
+                        debugger.pause();
+                        audio.update(0, None);
+                        format!("paused at {:#05X}", chip8.program_counter())
+                    }
+                },
+                Key::F11 if debugging && (window.is_key_down(Key::LeftShift) || window.is_key_down(Key::RightShift)) => {
+                    if debugger.run_to_return(&chip8) {
+                        String::from("running to return")
+                    } else {
+                        String::from("not in a subroutine")
+                    }
+                },
+                Key::F10 | Key::F11 if debugging => {
+                    let result = if key == Key::F10 {debugger.step_over(&mut chip8)} else {debugger.step(&mut chip8)};
+                    if let Err(err) = result {
+                        report_fault(&mut window, &mut audio, &chip8, err);
+                        continue;
+                    }
+                    if debugger.is_paused() {
+                        audio.update(0, None);
+                        format!("paused at {:#05X} {}", chip8.program_counter(), debug_panel::stop_reason(&debugger).unwrap_or_default())
+                    } else {
+                        String::from("stepping over")
+                    }
+                },
+                Key::F2 if debugging => {
+                    let address = chip8.program_counter();
+                    if debugger.toggle_breakpoint(address) {
+                        format!("breakpoint at {:#05X}", address)
+                    } else {
+                        format!("removed breakpoint at {:#05X}", address)
+                    }
+                },
+                _ => continue,
+            };
+            window.set_title(&format!("CHIP-8 Emulator - {}", message));
+        }
+
+        // during a replay the keypad only listens to the movie
+        // a CHIP-8 key can have several host keys, so it's down while any of them are and only changes when that does
+        if playback.is_none() {
+            let keypad = keymap.keypad(&window.get_keys());
+            for chip8_key in 0..16 {
+                let down = keypad[chip8_key];
+                if down == keypad_state[chip8_key] {continue}
+                keypad_state[chip8_key] = down;
+                chip8.update_keypad(chip8_key, down);
+                // stamped with the frame it'll take effect on, the next one to run
+                if let Some(movie) = &mut recording {
+                    movie.record(chip8.frame_count(), chip8_key as u8, down);
+                }
             }
-        );
-
-        window.get_keys_released().iter().for_each(|key|
-            match key {
-                Key::Key1 => chip8.update_keypad(0x1,false),
-                Key::Key2 => chip8.update_keypad(0x2,false),
-                Key::Key3 => chip8.update_keypad(0x3,false),
-                Key::Key4 => chip8.update_keypad(0xC,false),
-                Key::Q => chip8.update_keypad(0x4,false),
-                Key::W => chip8.update_keypad(0x5,false),
-                Key::E => chip8.update_keypad(0x6,false),
-                Key::R => chip8.update_keypad(0xD,false),
-                Key::A => chip8.update_keypad(0x7,false),
-                Key::S => chip8.update_keypad(0x8,false),
-                Key::D => chip8.update_keypad(0x9,false),
-                Key::F => chip8.update_keypad(0xE,false),
-                Key::Z => chip8.update_keypad(0xA,false),
-                Key::X => chip8.update_keypad(0x0,false),
-                Key::C => chip8.update_keypad(0xB,false),
-                Key::V => chip8.update_keypad(0xF,false),
-                _ => (),
+        }
+    }
+
+    finish_trace(&mut trace_out);
+    write_outputs(&options, &chip8, recording, &program);
+}
+
+// chip8 disasm ROM [vip|chip48|schip|xochip] [--syntax octo|cowgod]
+fn disasm_command(file_path: &str, platform: Platform, syntax: Syntax) {
+    let program = fs::read(file_path).unwrap_or_else(|err| cli::fail(&format!("can't read {}: {}", file_path, err)));
+    print!("{}", disasm::disassemble_rom(&program, DEFAULT_LOAD_ADDRESS, platform, syntax));
+}
+
+// chip8 asm SOURCE [-o ROM] [--source-map FILE]
+// the ROM defaults to the source's name with .ch8, the source map has one "address line" pair per line
+fn asm_command(file_path: &str, output: Option<String>, map_path: Option<String>) {
+    let text = fs::read_to_string(file_path).unwrap_or_else(|err| cli::fail(&format!("can't read {}: {}", file_path, err)));
+    let assembly = assemble(&text).unwrap_or_else(|err| {
+        report_assembly_error(file_path, &text, &err);
+        process::exit(1);
+    });
+    let output = output.unwrap_or_else(|| format!("{}.ch8", file_path.strip_suffix(".8o").unwrap_or(file_path)));
+    fs::write(&output, &assembly.rom).unwrap_or_else(|err| cli::fail(&format!("can't write {}: {}", output, err)));
+    if let Some(map_path) = map_path {
+        fs::write(&map_path, assembly.source_map_text()).unwrap_or_else(|err| cli::fail(&format!("can't write {}: {}", map_path, err)));
+    }
+    println!("{} bytes written to {}", assembly.rom.len(), output);
+}
+
+// chip8 info ROM -- the hash, what the database and config say about it, and what `run` would do
+fn info_command(file_path: &str, config_path: Option<&str>) {
+    let program = fs::read(file_path).unwrap_or_else(|err| cli::fail(&format!("can't read {}: {}", file_path, err)));
+    let config = Config::load(config_path).unwrap_or_else(|err| cli::fail(&err));
+    let database = load_database(&config).unwrap_or_else(|err| cli::fail(&err));
+    let settings = Settings::resolve(&config, &database, &program, &RunOptions::default()).unwrap_or_else(|err| cli::fail(&err));
+
+    println!("file      {}", file_path);
+    println!("size      {} bytes", program.len());
+    println!("sha1      {}", settings.rom_hash);
+    match &settings.info {
+        Some(info) => println!("database  {}", if info.title.is_empty() {"(untitled)"} else {&info.title}),
+        None => println!("database  not found ({} ROMs known)", database.len()),
+    }
+    match config.table(&format!("rom.{}", settings.rom_hash)) {
+        Some(table) => println!("config    [rom.{}] sets {}", settings.rom_hash, table.keys().cloned().collect::<Vec<_>>().join(", ")),
+        None => println!("config    nothing for this ROM"),
+    }
+    println!("platform  {}", settings.platform.name());
+    let quirks = settings.quirks;
+    let on: Vec<&str> = [
+        ("vf_reset", quirks.vf_reset),
+        ("memory_increment", quirks.memory_increment == MemoryIncrement::ByXPlusOne),
+        ("memory_increment_by_x", quirks.memory_increment == MemoryIncrement::ByX),
+        ("display_wait", quirks.display_wait),
+        ("clipping", quirks.clipping),
+        ("shifting", quirks.shifting),
+        ("jumping", quirks.jumping),
+    ].iter().filter(|(_, on)| *on).map(|(name, _)| *name).collect();
+    println!("quirks    {}", if on.is_empty() {String::from("none")} else {on.join(", ")});
+    println!("ipf       {}", settings.cycles_per_frame);
+    let name = settings.palettes.list().iter().find(|(_, colours)| *colours == settings.palette).map_or("this ROM's", |(name, _)| name.as_str());
+    println!("palette   {} ({})", name, settings.palette.iter().map(|colour| format!("#{:06X}", colour)).collect::<Vec<_>>().join(" "));
+}
+
+// what a ROM runs with: the command line, then [rom.<sha1>] in the config, then the ROM database, then the defaults
+struct Settings {
+    rom_hash: String,
+    info: Option<RomInfo>,
+    platform: Platform,
+    quirks: Quirks,
+    cycles_per_frame: usize,
+    palette: [u32; 4], // background, plane 1, plane 2, both planes
+    palettes: Palettes,
+}
+
+impl Settings {
+    fn resolve(config: &Config, database: &Database, program: &[u8], options: &RunOptions) -> Result<Settings, String> {
+        let rom_sha1 = hash::sha1(program);
+        let rom_hash = hash::to_hex(&rom_sha1);
+        let info = database.lookup(&rom_sha1).cloned();
+        let rom_settings = RomSettings::from_config(config, &rom_hash).map_err(|err| format!("bad settings for this ROM in the config: {}", err))?;
+
+        let configured = match &rom_settings.platform {
+            Some(name) => match (Platform::from_name(name), Quirks::preset(name)) {
+                (Some(platform), Some(quirks)) => Some((platform, quirks)),
+                _ => return Err(format!("unknown platform {} in the config, try vip, chip48, schip or xochip", name)),
+            },
+            None => None,
+        };
+        let (platform, quirks) = options.platform.or(configured)
+            .or(info.as_ref().map(|info| (info.platform, info.quirks)))
+            .unwrap_or((Platform::Chip8, Quirks::default()));
+        let quirks = options.quirks.unwrap_or(quirks);
+
+        let cycles_per_frame = options.ipf
+            .or(rom_settings.cycles_per_frame.map(|n| n as usize))
+            .or(info.as_ref().and_then(|info| info.cycles_per_frame).map(|n| n as usize))
+            .unwrap_or(CYCLES_PER_FRAME);
+
+        // the ROM's own colours where something gives them, the defaults for the rest
+        // --palette, then the ROM's palette or colours from the config, then the database's, then the config's palette
+        let palettes = Palettes::from_config(config)?;
+        let database_colours = info.as_ref().map(|info| info.colors.as_slice()).filter(|colours| !colours.is_empty());
+        let default = config.table("").and_then(|table| table.get("palette"));
+        let palette = if let Some(text) = &options.palette {
+            palettes.parse(text)?
+        } else if let Some(value) = &rom_settings.palette {
+            palettes.value(value).map_err(|err| format!("the palette for this ROM in the config: {}", err))?
+        } else if let Some(colours) = rom_settings.colors.as_deref().or(database_colours) {
+            palette::complete(colours)?
+        } else if let Some(value) = default {
+            palettes.value(value).map_err(|err| format!("palette in the config: {}", err))?
+        } else {
+            palette::PRESETS[0].1
+        };
+
+        Ok(Settings { rom_hash, info, platform, quirks, cycles_per_frame, palette, palettes })
+    }
+}
+
+// `database = "path/to/programs.json"` in the config, the bundled one otherwise
+fn load_database(config: &Config) -> Result<Database, String> {
+    match config.table("").and_then(|table| table.get("database")) {
+        Some(Value::String(path)) => {
+            let text = fs::read_to_string(path).map_err(|err| format!("can't read the ROM database {}: {}", path, err))?;
+            Database::parse(&text).map_err(|err| format!("{}: {}", path, err))
+        },
+        Some(_) => Err(String::from("database in the config should be the path to a programs.json")),
+        None => Ok(Database::bundled()),
+    }
+}
+
+// the movie, coverage and profile, once the emulator closes
+fn write_outputs(options: &RunOptions, chip8: &Chip8, recording: Option<Movie>, program: &[u8]) {
+    if let (Some(movie), Some(path)) = (recording, &options.record) {
+        match fs::write(path, movie.to_text()) {
+            Ok(()) => println!("Recorded {} inputs to {}", movie.events.len(), path),
+            Err(err) => eprintln!("Couldn't write {}: {}", path, err),
+        }
+    }
+    if let (Some(coverage), Some(path)) = (chip8.coverage(), &options.coverage) {
+        let load_address = chip8.memory().load_address();
+        let data = if path.ends_with(".png") {
+            coverage.png(load_address, program.len())
+        } else {
+            coverage.report(load_address, program.len()).into_bytes()
+        };
+        match fs::write(path, data) {
+            Ok(()) => println!("Wrote the coverage to {}", path),
+            Err(err) => eprintln!("Couldn't write {}: {}", path, err),
+        }
+    }
+    if let Some(profile) = chip8.profile() {
+        let outputs = [
+            (&options.profile, profile.report(chip8.memory(), chip8.platform())),
+            (&options.profile_folded, profile.folded()),
+        ];
+        for (path, text) in outputs {
+            let Some(path) = path else {continue};
+            match fs::write(path, text) {
+                Ok(()) => println!("Wrote the profile to {}", path),
+                Err(err) => eprintln!("Couldn't write {}: {}", path, err),
             }
-        );
+        }
+    }
+}
+
+// --headless prints the screen on stdout, so everything else goes to stderr
+fn note(options: &RunOptions, message: &str) {
+    if options.headless {
+        eprintln!("{}", message);
+    } else {
+        println!("{}", message);
+    }
+}
+
+// e.g. "game.8o:12: don't know what v0+= means" followed by the line itself
+fn report_assembly_error(file_path: &str, text: &str, err: &chip8_core::AssemblyError) {
+    eprintln!("{}:{}: {}", file_path, err.line, err.message);
+    if let Some(line) = text.lines().nth(err.line.saturating_sub(1)) {
+        eprintln!("    {}", line.trim());
+    }
+}
+
+// the trace file (stderr if there isn't one), starting with the header
+fn open_trace(path: Option<&str>) -> Box<dyn Write> {
+    let mut out: Box<dyn Write> = match path {
+        Some(path) => {
+            let file = fs::File::create(path).unwrap_or_else(|err| cli::fail(&format!("can't create the trace file {}: {}", path, err)));
+            Box::new(BufWriter::new(file))
+        },
+        None => Box::new(io::stderr()),
+    };
+    if let Err(err) = out.write_all(trace::HEADER.as_bytes()) {
+        cli::fail(&format!("can't write the trace: {}", err));
+    }
+    out
+}
+
+// the trace so far, or with --trace-last the last N lines once the program has faulted
+// (the file's only opened then, and just the once, so nothing written to it is lost)
+fn flush_trace(chip8: &mut Chip8, out: &mut Option<Box<dyn Write>>, options: &RunOptions) {
+    if options.trace_last.is_some() && chip8.fault().is_some() && chip8.trace_mut().is_some() {
+        write_trace(out.get_or_insert_with(|| open_trace(options.trace.as_deref())), chip8);
+        chip8.set_trace(None);
+    } else if let Some(out) = out {
+        write_trace(out, chip8);
+    }
+}
+
+fn write_trace(out: &mut Box<dyn Write>, chip8: &mut Chip8) {
+    for line in chip8.trace_mut().map(Trace::take_lines).unwrap_or_default() {
+        if let Err(err) = writeln!(out, "{}", line) {
+            cli::fail(&format!("can't write the trace: {}", err));
+        }
+    }
+}
+
+// flushed before exiting, since process::exit doesn't and a dropped BufWriter would hide the error
+fn finish_trace(out: &mut Option<Box<dyn Write>>) {
+    if let Some(Err(err)) = out.as_mut().map(|out| out.flush()) {
+        cli::fail(&format!("can't write the trace: {}", err));
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Speed {
+    Normal,
+    FastForward,
+    SlowMotion,
+}
+
+impl Speed {
+    // how much faster than real time the frames run
+    fn factor(self) -> f64 {
+        match self {
+            Speed::Normal => 1.0,
+            Speed::FastForward => FAST_FORWARD,
+            Speed::SlowMotion => SLOW_MOTION,
+        }
+    }
+
+    fn label(self) -> &'static str {
+        match self {
+            Speed::Normal => "NORMAL SPEED",
+            Speed::FastForward => "FAST FORWARD",
+            Speed::SlowMotion => "SLOW MOTION",
+        }
+    }
+}
+
+// e.g. "FAST FORWARD X4  IPF 10" in the top left corner of the screen
+fn draw_speed(buffer: &mut [u32], buffer_width: usize, speed: Speed, cycles_per_frame: usize) {
+    let text = match speed {
+        Speed::Normal => format!("IPF {}", cycles_per_frame),
+        _ => format!("{} X{}  IPF {}", speed.label(), speed.factor(), cycles_per_frame),
+    };
+    let (scale, margin) = (2, 4);
+    let text_width = text.len() * (font::GLYPH_WIDTH + 1) * scale;
+    let text_height = font::GLYPH_HEIGHT * scale;
+    // a dark box so it can be read over any picture
+    for row in buffer.chunks_exact_mut(buffer_width).take(text_height + 2 * margin) {
+        for pixel in row.iter_mut().take(text_width + 2 * margin) {
+            *pixel = 0x202233;
+        }
+    }
+    font::draw_text(buffer, buffer_width, margin, margin, &text, 0xF2EEDD, scale);
+}
+
+// save state files sit next to the ROM, e.g. game.ch8.state3
+fn state_path(rom_path: &str, slot: u8) -> String {
+    format!("{}.state{}", rom_path, slot)
+}
+
+// say why in the title bar and on the terminal
+fn report_fault(window: &mut Window, audio: &mut AudioState, chip8: &Chip8, err: Chip8Error) {
+    let message = format!("halted at {:#05X}: {}", chip8.program_counter(), err);
+    eprintln!("{}", message);
+    window.set_title(&format!("CHIP-8 Emulator - {}", message));
+    audio.update(0, None);
+}
+
+fn release_all_keys(chip8: &mut Chip8) {
+    for key in 0..16 {
+        chip8.update_keypad(key, false);
     }
-}
\ No newline at end of file
+}
@@ -0,0 +1,170 @@
+// palettes -- four colours each: background, plane 1, plane 2, both planes
+// classic ROMs only ever draw plane 1, so only the first two show up unless an XO-CHIP ROM uses plane 2
+//
+// as well as the presets, the config can add its own and pick the default:
+//
+//   palette = "amber"                         # a name, or a list of colours
+//
+//   [palettes]
+//   paper = ["#F4F1E8", "#202020"]            # 2 colours is fine, the XO-CHIP ones are mixed from them
+
+use std::collections::BTreeMap;
+
+use chip8_core::database::parse_color;
+
+use crate::config::{Config, Value};
+
+pub const PRESETS: [(&str, [u32; 4]); 6] = [
+    ("default", [0xF2EEDD, 0x2A7B6F, 0xE07A5F, 0x3D405B]),
+    ("classic", [0x000000, 0xFFFFFF, 0x808080, 0xC0C0C0]),       // black and white
+    ("green", [0x0B1A0B, 0x33FF66, 0x1C7A33, 0xB3FFC6]),         // a green phosphor monitor
+    ("amber", [0x1A1000, 0xFFB000, 0x805800, 0xFFE0A0]),         // an amber one
+    ("lcd", [0x9BBC0F, 0x0F380F, 0x8BAC0F, 0x306230]),           // the original Game Boy's greens
+    ("high-contrast", [0x000000, 0xFFFFFF, 0xFFFF00, 0x00FFFF]),
+];
+
+pub struct Palettes {
+    list: Vec<(String, [u32; 4])>, // the presets, then the config's [palettes]
+}
+
+impl Palettes {
+    pub fn from_config(config: &Config) -> Result<Palettes, String> {
+        let mut palettes = Palettes { list: PRESETS.iter().map(|(name, colours)| (name.to_string(), *colours)).collect() };
+        let empty = BTreeMap::new();
+        for (name, value) in config.table("palettes").unwrap_or(&empty) {
+            let colours = colour_list(value).map_err(|err| format!("palette {}: {}", name, err))?;
+            // a palette with a preset's name replaces it
+            match palettes.list.iter_mut().find(|(existing, _)| existing.eq_ignore_ascii_case(name)) {
+                Some(existing) => existing.1 = colours,
+                None => palettes.list.push((name.clone(), colours)),
+            }
+        }
+        Ok(palettes)
+    }
+
+    pub fn named(&self, name: &str) -> Option<[u32; 4]> {
+        self.list.iter().find(|(existing, _)| existing.eq_ignore_ascii_case(name)).map(|(_, colours)| *colours)
+    }
+
+    pub fn list(&self) -> &[(String, [u32; 4])] {
+        &self.list
+    }
+
+    // a palette's name or "#000000,#FFFFFF", e.g. from --palette
+    pub fn parse(&self, text: &str) -> Result<[u32; 4], String> {
+        if let Some(colours) = self.named(text) {
+            return Ok(colours);
+        }
+        if !text.contains('#') && !text.contains(',') {
+            return Err(format!("unknown palette {}, try {}", text, self.names()));
+        }
+        let colours = text.split(',').map(str::trim)
+            .map(|colour| parse_color(colour).ok_or(format!("{} isn't a colour, they're like #2A7B6F", colour)))
+            .collect::<Result<Vec<u32>, String>>()?;
+        complete(&colours)
+    }
+
+    // the config's palette = "amber" or palette = ["#000000", "#FFFFFF"]
+    pub fn value(&self, value: &Value) -> Result<[u32; 4], String> {
+        match value {
+            Value::String(text) => self.parse(text),
+            _ => colour_list(value),
+        }
+    }
+
+    pub fn names(&self) -> String {
+        self.list.iter().map(|(name, _)| name.as_str()).collect::<Vec<_>>().join(", ")
+    }
+}
+
+// 1 to 4 colours, the missing ones worked out from the first two so XO-CHIP's second plane still shows up
+pub fn complete(colours: &[u32]) -> Result<[u32; 4], String> {
+    match *colours {
+        [] => Err(String::from("a palette needs at least one colour")),
+        [background] => Ok([background, !background & 0xFFFFFF, mix(background, !background & 0xFFFFFF, 1, 2), mix(background, !background & 0xFFFFFF, 3, 4)]),
+        [background, plane1] => Ok([background, plane1, mix(background, plane1, 1, 2), mix(background, plane1, 3, 4)]),
+        [background, plane1, plane2] => Ok([background, plane1, plane2, mix(plane1, plane2, 1, 2)]),
+        [background, plane1, plane2, both] => Ok([background, plane1, plane2, both]),
+        _ => Err(String::from("a palette has at most four colours (background, plane 1, plane 2, both planes)")),
+    }
+}
+
+fn colour_list(value: &Value) -> Result<[u32; 4], String> {
+    let Value::Array(values) = value else {
+        return Err(String::from("should be a list of colours, e.g. [\"#000000\", \"#FFFFFF\"]"));
+    };
+    let colours = values.iter().map(|value| match value {
+        Value::String(text) => parse_color(text).ok_or(format!("{} isn't a colour, they're like \"#2A7B6F\"", text)),
+        _ => Err(String::from("colours should be strings like \"#2A7B6F\"")),
+    }).collect::<Result<Vec<u32>, String>>()?;
+    complete(&colours)
+}
+
+// `numerator / denominator` of the way from `from` to `to`, channel by channel
+fn mix(from: u32, to: u32, numerator: u32, denominator: u32) -> u32 {
+    let channel = |shift: u32| {
+        let (a, b) = ((from >> shift) & 0xFF, (to >> shift) & 0xFF);
+        ((a * (denominator - numerator) + b * numerator) / denominator) << shift
+    };
+    channel(16) | channel(8) | channel(0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn from_config(config: &str) -> Result<Palettes, String> {
+        Palettes::from_config(&Config::parse(config).unwrap())
+    }
+
+    #[test]
+    fn presets_ignore_case() {
+        let palettes = from_config("").unwrap();
+        assert_eq!(palettes.parse("amber"), Ok(PRESETS[3].1));
+        assert_eq!(palettes.parse("High-Contrast"), Ok(PRESETS[5].1));
+        assert_eq!(palettes.named("LCD"), Some(PRESETS[4].1));
+        assert_eq!(palettes.names(), "default, classic, green, amber, lcd, high-contrast");
+        assert_eq!(palettes.parse("sepia"), Err(String::from("unknown palette sepia, try default, classic, green, amber, lcd, high-contrast")));
+    }
+
+    #[test]
+    fn the_config_adds_and_overrides_palettes() {
+        let palettes = from_config("[palettes]\nAmber = [\"#000000\", \"#FF8000\", \"#804000\", \"#FFC080\"]\npaper = [\"#F4F1E8\", \"#202020\"]").unwrap();
+        // replaced in place, under the preset's name
+        assert_eq!(palettes.parse("amber"), Ok([0x000000, 0xFF8000, 0x804000, 0xFFC080]));
+        assert_eq!(palettes.list()[3].0, "amber");
+        assert_eq!(palettes.list().len(), PRESETS.len() + 1);
+        assert_eq!(palettes.parse("Paper").map(|colours| colours[..2].to_vec()), Ok(vec![0xF4F1E8, 0x202020]));
+        assert_eq!(palettes.value(&Value::String(String::from("paper"))), palettes.parse("paper"));
+    }
+
+    #[test]
+    fn missing_colours_are_mixed_from_the_others() {
+        // one colour, then its inverse
+        assert_eq!(complete(&[0x000000]), Ok([0x000000, 0xFFFFFF, 0x7F7F7F, 0xBFBFBF]));
+        assert_eq!(complete(&[0x000000, 0xFF0000]), Ok([0x000000, 0xFF0000, 0x7F0000, 0xBF0000]));
+        // both planes is half way between the two planes
+        assert_eq!(complete(&[0x000000, 0xFF0000, 0x0000FF]), Ok([0x000000, 0xFF0000, 0x0000FF, 0x7F007F]));
+        assert_eq!(complete(&[1, 2, 3, 4]), Ok([1, 2, 3, 4]));
+        let palettes = from_config("").unwrap();
+        assert_eq!(palettes.parse("#000000, #FF0000"), Ok([0x000000, 0xFF0000, 0x7F0000, 0xBF0000]));
+        assert_eq!(palettes.parse("#FFFFFF"), Ok([0xFFFFFF, 0x000000, 0x7F7F7F, 0x3F3F3F]));
+    }
+
+    #[test]
+    fn rejects_bad_palettes() {
+        assert_eq!(complete(&[]), Err(String::from("a palette needs at least one colour")));
+        let too_many = Err(String::from("a palette has at most four colours (background, plane 1, plane 2, both planes)"));
+        assert_eq!(complete(&[1, 2, 3, 4, 5]), too_many);
+        let palettes = from_config("").unwrap();
+        assert_eq!(palettes.parse("#000000,#111111,#222222,#333333,#444444"), too_many);
+        assert_eq!(palettes.parse("#000000,#GGGGGG"), Err(String::from("#GGGGGG isn't a colour, they're like #2A7B6F")));
+        assert_eq!(palettes.parse("#FFF"), Err(String::from("#FFF isn't a colour, they're like #2A7B6F")));
+        assert_eq!(palettes.value(&Value::Array(Vec::new())), Err(String::from("a palette needs at least one colour")));
+        assert_eq!(palettes.value(&Value::Integer(1)), Err(String::from("should be a list of colours, e.g. [\"#000000\", \"#FFFFFF\"]")));
+        assert_eq!(
+            from_config("[palettes]\nbad = [\"#12\"]").err(),
+            Some(String::from("palette bad: #12 isn't a colour, they're like \"#2A7B6F\"")),
+        );
+    }
+}